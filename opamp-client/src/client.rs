@@ -5,8 +5,11 @@ use crate::common::clientstate::SyncedStateError;
 use crate::common::message_processor::ProcessError;
 use crate::http::HttpClientError;
 use crate::opamp::proto::{
-    AgentDescription, ComponentHealth, CustomCapabilities, RemoteConfigStatus,
+    AgentDescription, AvailableComponents, ComponentHealth, CustomCapabilities, CustomMessage,
+    PackageStatuses, RemoteConfigStatus, ServerCapabilities,
 };
+use crate::operation::capabilities;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Represents various errors that can occur during OpAMP connections.
@@ -36,6 +39,31 @@ pub enum ClientError {
     /// Indicates that the report health capability are not set.
     #[error("report health capability is not set")]
     UnsetHealthCapability,
+    /// Indicates that the report package statuses capability is not set.
+    #[error("report package statuses capability is not set")]
+    UnsetPackageStatusesCapability,
+    /// Indicates that the report available components capability is not set.
+    #[error("report available components capability is not set")]
+    UnsetAvailableComponentsCapability,
+    /// Indicates that the provided available components have no hash.
+    #[error("the hash of the available components is empty")]
+    EmptyAvailableComponentsHash,
+    /// Indicates that the custom message capability is not one of the Agent's custom capabilities.
+    #[error("custom capability `{0}` is not supported by the agent")]
+    UnsupportedCustomCapability(String),
+    /// Indicates that the accepts OpAMP connection settings capability is not set.
+    #[error("accepts OpAMP connection settings capability is not set")]
+    UnsetOpAmpConnectionSettingsCapability,
+    /// Indicates that the Server doesn't have the capability required by the operation.
+    #[error("the server doesn't have the `{}` capability", .0.as_str_name())]
+    UnsupportedServerCapability(ServerCapabilities),
+    /// Indicates that a previous custom message has not been sent yet.
+    #[error("a previous custom message is still pending to be sent")]
+    CustomMessagePending,
+    /// The server reported it is unavailable, either through a `ServerErrorResponse` or by
+    /// throttling requests. It can include the delay requested by the server before retrying.
+    #[error("server unavailable, retry after: {0:?}")]
+    ServerUnavailable(Option<Duration>),
     /// Indicates an error while fetching effective configuration.
     #[error("error while fetching effective config")]
     EffectiveConfigError,
@@ -74,10 +102,22 @@ pub trait Client: Send + Sync {
     /// Sets the health status of the Agent. The [`ComponentHealth`] will be included
     fn set_health(&self, health: ComponentHealth) -> ClientResult<()>;
 
+    /// Sets the health of the component in the provided `path` of the [`ComponentHealth`] tree,
+    /// e.g. `["pipelines", "traces"]` for the `traces` component of the `pipelines` one. An empty
+    /// path sets the health of the Agent itself. The missing components are created, and the
+    /// components of the current health are kept if the new one doesn't include them.
+    ///
+    /// The `status_time_unix_nano` is set to the current time if it is not set. If
+    /// [`derive_parent_health`](crate::operation::settings::StartSettings::derive_parent_health)
+    /// is set, the parents are healthy only if all their components are. The whole tree is sent,
+    /// unless it is the same as the current one. It requires the `ReportsHealth` capability.
+    fn set_component_health(&self, path: &[&str], health: ComponentHealth) -> ClientResult<()>;
+
     /// Fetches the current local effective config (normally using
     /// [`get_effective_config`](crate::operation::callbacks::Callbacks::get_effective_config) callback) and sends it to the Server.
     /// The reason why there is a callback to fetch the `EffectiveConfig` from the Agent and it is not
-    /// sent by the Agent like health, is to allow the compression mechanism without storing it.
+    /// sent by the Agent like health, is to allow the compression mechanism without storing it:
+    /// only a digest of the last config is kept, and the config is not sent if it didn't change.
     fn update_effective_config(&self) -> ClientResult<()>;
 
     /// Sets the current [`RemoteConfigStatus`].
@@ -85,6 +125,39 @@ pub trait Client: Send + Sync {
 
     /// Sets the custom capabilities of the Agent.
     fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()>;
+
+    /// Sets the current [`PackageStatuses`] of the Agent. Useful for Agents that manage their
+    /// packages on their own instead of using the [`PackageSyncer`](crate::operation::packages::PackageSyncer).
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()>;
+
+    /// Sends a [`CustomMessage`] to the Server. The message capability must be one of the
+    /// [`CustomCapabilities`] set by the Agent.
+    ///
+    /// Only one custom message can be pending at a time: if the previous one has not been sent yet
+    /// [`ClientError::CustomMessagePending`] is returned and the caller should retry later.
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()>;
+
+    /// Requests the Server to issue a client certificate from the provided PEM-encoded Certificate
+    /// Signing Request (CSR), signed by the Agent's private key. The Agent must have the
+    /// `AcceptsOpAmpConnectionSettings` capability and the Server the
+    /// `AcceptsConnectionSettingsRequest` one, which is known once the first response is received.
+    ///
+    /// The certificate issued by the Server is provided through
+    /// [`on_client_certificate`](crate::operation::callbacks::Callbacks::on_client_certificate).
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()>;
+
+    /// Returns the capabilities reported by the Server in the last response including them, or
+    /// [`None`] if no response has been received yet. Fields the Server declared it does not
+    /// accept, such as the remote config status, the effective config or the package statuses,
+    /// are not sent. Fields without a Server capability, such as the custom messages or the
+    /// available components, are always sent.
+    fn server_capabilities(&self) -> ClientResult<Option<capabilities::ServerCapabilities>>;
+
+    /// Sets the [`AvailableComponents`] of the Agent, which requires the
+    /// `ReportsAvailableComponents` capability. The `hash` identifies the components and cannot be
+    /// empty: only the hash is sent, unless the Server requests the full components. Nothing is
+    /// sent if the hash doesn't change.
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()>;
 }
 
 /// A trait defining the methods necessary for managing a client in the OpAMP library.
@@ -0,0 +1,950 @@
+//! Transport-agnostic OpAMP client state shared by every transport implementation.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use tracing::{debug, error, warn};
+
+use crate::{
+    Client, ClientError, ClientResult,
+    common::{
+        clientstate::ClientSyncedState,
+        heartbeat::SharedHeartbeatInterval,
+        message_processor::{
+            ConnectionSettings, ProcessResult, get_telemetry_connection_settings,
+            heartbeat_interval, opamp_connection_settings, process_message,
+        },
+        nextmessage::NextMessage,
+        notifier::Notifier,
+    },
+    opamp::proto::{
+        self, AgentCapabilities, AgentDescription, AgentDisconnect, AgentRemoteConfig,
+        AgentToServer, AgentToServerFlags, AvailableComponents, CertificateRequest,
+        ComponentHealth, ConnectionSettingsOffers, ConnectionSettingsRequest, CustomCapabilities,
+        CustomMessage, OpAmpConnectionSettings, OpAmpConnectionSettingsRequest, PackageStatuses,
+        RemoteConfigStatus, RemoteConfigStatuses, ServerToAgent,
+    },
+    operation::{
+        callbacks::Callbacks,
+        capabilities::{Capabilities, ServerCapabilities},
+        connection_settings::ConnectionSettingsHandler,
+        instance_uid::SharedInstanceUid,
+        remote_config::RemoteConfigHandler,
+        settings::StartSettings,
+        state_store::{PersistedState, PersistedStateHandle, StateStore},
+    },
+};
+
+/// Holds everything an OpAMP client needs regardless of the transport used to reach the server:
+/// the user callbacks, the next message to be sent, the synced state and the notifier used to
+/// request a new message to be sent.
+///
+/// Transports only need to pop messages, send them and hand the received [`ServerToAgent`]
+/// messages back through [`ClientCore::process`].
+// If there is an error sending, the syncState should still be updated, we want it to be consistent
+// with the agent status, and we leave the responsibility to the OpAMP server to call ReportFullState
+// if it detects a gap on sequence numbers.
+pub(crate) struct ClientCore<C>
+where
+    C: Callbacks,
+{
+    pub(crate) callbacks: C,
+    pub(crate) message: Arc<RwLock<NextMessage>>,
+    pub(crate) synced_state: ClientSyncedState,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) pending_msg: Notifier,
+    pub(crate) instance_uid: SharedInstanceUid,
+    pub(crate) heartbeat_interval: SharedHeartbeatInterval,
+    derive_parent_health: bool,
+    state_store: Option<PersistedStateHandle>,
+    connection_settings_handler: Option<Box<dyn ConnectionSettingsHandler>>,
+    remote_config_handler: Option<Box<dyn RemoteConfigHandler>>,
+    // Remote config reported as `Applying`, applied once the message reporting it is sent.
+    applying_remote_config: Mutex<Option<ApplyingRemoteConfig>>,
+}
+
+// Remote config waiting for its `Applying` status to be sent before being applied.
+struct ApplyingRemoteConfig {
+    config: AgentRemoteConfig,
+    // Whether the message reporting the `Applying` status was taken to be sent.
+    sent: bool,
+}
+
+impl<C> ClientCore<C>
+where
+    C: Callbacks,
+{
+    /// Initializes a new [`ClientCore`] from the provided [`StartSettings`].
+    pub(crate) fn new(
+        callbacks: C,
+        start_settings: StartSettings,
+        pending_msg: Notifier,
+    ) -> ClientResult<Self> {
+        let capabilities = start_settings.capabilities;
+        let derive_parent_health = start_settings.derive_parent_health;
+        let instance_uid = SharedInstanceUid::from(start_settings.instance_uid.clone());
+
+        let (initial_message, synced_state) = Self::initial_message_and_state(start_settings)?;
+
+        Ok(Self {
+            callbacks,
+            message: Arc::new(RwLock::new(NextMessage::new(initial_message))),
+            synced_state,
+            capabilities,
+            pending_msg,
+            instance_uid,
+            heartbeat_interval: SharedHeartbeatInterval::default(),
+            derive_parent_health,
+            state_store: None,
+            connection_settings_handler: None,
+            remote_config_handler: None,
+            applying_remote_config: Mutex::default(),
+        })
+    }
+
+    /// Sets the [`StateStore`] used to persist the client state, restoring the state it holds:
+    /// the stored instance uid replaces the one in the [`StartSettings`] and is not requested
+    /// anymore, the stored [`RemoteConfigStatus`] is reported in the next message, the remote
+    /// config with the stored hash is considered handled and the connection settings offer with
+    /// the stored hash is considered accepted.
+    pub(crate) fn set_state_store<S>(&mut self, store: S) -> ClientResult<()>
+    where
+        S: StateStore + 'static,
+    {
+        let handle = PersistedStateHandle::load(Box::new(store));
+        let state = handle.state();
+
+        match state.instance_uid {
+            Some(instance_uid) => {
+                debug!(%instance_uid, "restoring the persisted instance uid");
+                self.instance_uid.set(instance_uid);
+                self.message
+                    .write()
+                    .map_err(|_| ClientError::PoisonError)?
+                    .update(|msg| msg.flags &= !(AgentToServerFlags::RequestInstanceUid as u64));
+            }
+            None => handle.update(|state| state.instance_uid = Some(self.instance_uid.get())),
+        }
+
+        if let Some(status) = state.remote_config_status {
+            self.synced_state.set_remote_config_status(status.clone())?;
+            self.message
+                .write()
+                .map_err(|_| ClientError::PoisonError)?
+                .update(|msg| msg.remote_config_status = Some(status));
+        }
+
+        if let Some(hash) = state.remote_config_hash {
+            self.synced_state.set_remote_config_hash(hash)?;
+        }
+        if let Some(hash) = state.connection_settings_hash {
+            self.synced_state.set_connection_settings_hash(hash)?;
+        }
+        if let Some(hash) = state.opamp_connection_settings_hash {
+            self.synced_state.set_opamp_connection_settings_hash(hash)?;
+        }
+
+        self.state_store = Some(handle);
+        Ok(())
+    }
+
+    /// Sets the [`ConnectionSettingsHandler`] which accepts or rejects the telemetry and other
+    /// connection settings offered by the server.
+    pub(crate) fn set_connection_settings_handler<H>(&mut self, handler: H)
+    where
+        H: ConnectionSettingsHandler + 'static,
+    {
+        self.connection_settings_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the [`RemoteConfigHandler`] which applies the remote config offered by the server.
+    pub(crate) fn set_remote_config_handler<H>(&mut self, handler: H)
+    where
+        H: RemoteConfigHandler + 'static,
+    {
+        self.remote_config_handler = Some(Box::new(handler));
+    }
+
+    /// Returns the OpAMP connection settings offered by the server along with the hash of the
+    /// offer, if the agent has the `AcceptsOpAmpConnectionSettings` capability and the offer was
+    /// not accepted already.
+    pub(crate) fn opamp_connection_settings_offer(
+        &self,
+        msg: &ServerToAgent,
+    ) -> ClientResult<Option<(OpAmpConnectionSettings, Vec<u8>)>> {
+        let Some(settings) = opamp_connection_settings(msg, self.capabilities) else {
+            return Ok(None);
+        };
+        let hash = msg
+            .connection_settings
+            .as_ref()
+            .map(|offers| offers.hash.clone())
+            .unwrap_or_default();
+        if self
+            .synced_state
+            .opamp_connection_settings_hash_unchanged(&hash)?
+        {
+            debug!("OpAMP connection settings offer already accepted, ignoring it");
+            return Ok(None);
+        }
+        Ok(Some((settings, hash)))
+    }
+
+    /// Remembers the OpAMP connection settings offer with the provided hash as handled, so it is
+    /// not handled again until it changes, even if its settings were not applied.
+    pub(crate) fn opamp_connection_settings_handled(&self, hash: &[u8]) -> ClientResult<()> {
+        // Offers without hash cannot be identified, they are always handled.
+        if !hash.is_empty() {
+            self.synced_state
+                .set_opamp_connection_settings_hash(hash.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Remembers the OpAMP connection settings applied by the transport, so the offer with the
+    /// same hash is not applied again, persisting them if a [`StateStore`] was set.
+    pub(crate) fn accept_opamp_connection_settings(
+        &self,
+        settings: OpAmpConnectionSettings,
+        hash: Vec<u8>,
+    ) -> ClientResult<()> {
+        self.opamp_connection_settings_handled(&hash)?;
+        self.persist(|state| {
+            state.opamp_connection_settings = Some(settings);
+            state.opamp_connection_settings_hash = (!hash.is_empty()).then_some(hash);
+        });
+        Ok(())
+    }
+
+    /// Returns the persisted state, if a [`StateStore`] was set.
+    pub(crate) fn persisted_state(&self) -> Option<PersistedState> {
+        self.state_store.as_ref().map(PersistedStateHandle::state)
+    }
+
+    /// Modifies the persisted state with the provided function, if a [`StateStore`] was set.
+    pub(crate) fn persist<F>(&self, f: F)
+    where
+        F: FnOnce(&mut PersistedState),
+    {
+        if let Some(state_store) = self.state_store.as_ref() {
+            state_store.update(f);
+        }
+    }
+
+    /// Helper to build the initial [AgentToServer] message to be sent to the server and the corresponding
+    /// internal state to keep track of sent fields (check [ClientSyncedState] for details).
+    fn initial_message_and_state(
+        start_settings: StartSettings,
+    ) -> ClientResult<(AgentToServer, ClientSyncedState)> {
+        // Destructured to get compile errors if any field is added to StartSettings
+        let StartSettings {
+            instance_uid,
+            capabilities,
+            custom_capabilities,
+            agent_description,
+            request_instance_uid,
+            derive_parent_health: _,
+        } = start_settings;
+
+        // Store initial state fields
+        let initial_state = ClientSyncedState::default();
+        if !agent_description.is_empty() {
+            initial_state.set_agent_description(agent_description.clone().into())?;
+        }
+        if let Some(custom_capabilities) = custom_capabilities.as_ref() {
+            initial_state.set_custom_capabilities(custom_capabilities.clone())?;
+        }
+
+        // build initial message
+        let initial_message = AgentToServer {
+            instance_uid: instance_uid.into(),
+            agent_description: Some(agent_description.into()),
+            capabilities: capabilities.into(),
+            custom_capabilities,
+            flags: if request_instance_uid {
+                AgentToServerFlags::RequestInstanceUid as u64
+            } else {
+                AgentToServerFlags::Unspecified as u64
+            },
+            ..Default::default()
+        };
+
+        Ok((initial_message, initial_state))
+    }
+
+    /// Returns the next [`AgentToServer`] message to be sent, without the fields the server
+    /// declared it does not accept.
+    pub(crate) fn pop_message(&self) -> ClientResult<AgentToServer> {
+        let mut next_message = self.message.write().map_err(|_| ClientError::PoisonError)?;
+        let mut msg = next_message.pop();
+        // Read while holding the message lock, the server can assign a new instance uid.
+        msg.instance_uid = self.instance_uid.get().into();
+        drop(next_message);
+
+        // The `Applying` status of the pending remote config, if any, is included in this message.
+        if let Some(applying) = self
+            .applying_remote_config
+            .lock()
+            .map_err(|_| ClientError::PoisonError)?
+            .as_mut()
+        {
+            applying.sent = true;
+        }
+
+        if let Some(server_capabilities) = self.synced_state.server_capabilities()? {
+            remove_unaccepted_fields(&mut msg, server_capabilities);
+        }
+        Ok(msg)
+    }
+
+    /// Puts back a message returned by [`pop_message`](ClientCore::pop_message) that could not be
+    /// sent, so its changes are sent in the next message instead of being lost.
+    #[cfg(feature = "websocket")]
+    pub(crate) fn restore_message(&self, msg: AgentToServer) -> ClientResult<()> {
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .restore(msg);
+        Ok(())
+    }
+
+    /// Returns the last [`AgentToServer`] message to be sent, which includes the [`AgentDisconnect`]
+    /// as required by the OpAMP protocol.
+    pub(crate) fn pop_disconnect_message(&self) -> ClientResult<AgentToServer> {
+        let mut msg = self.pop_message()?;
+        msg.agent_disconnect = Some(AgentDisconnect::default());
+        Ok(msg)
+    }
+
+    /// Processes a [`ServerToAgent`] message received from the server and requests a new message
+    /// to be sent if the server asked for it. The heartbeat interval requested by the server is
+    /// kept in `heartbeat_interval`, so the transport can schedule the following messages.
+    ///
+    /// Returns [`ClientError::ServerUnavailable`] if the server reported it is unavailable, so the
+    /// transport can retry later.
+    pub(crate) fn process(&self, mut server_to_agent: ServerToAgent) -> ClientResult<()> {
+        if let Some(interval) = heartbeat_interval(&server_to_agent, self.capabilities) {
+            self.heartbeat_interval.set(interval);
+        }
+        if let Some(offers) = server_to_agent.connection_settings.as_mut() {
+            self.handle_connection_settings_offers(offers)?;
+        }
+        if let Some(handler) = self.remote_config_handler.as_deref()
+            && let Some(config) = server_to_agent.remote_config.take_if(|_| {
+                self.capabilities
+                    .has_capability(AgentCapabilities::AcceptsRemoteConfig)
+            })
+        {
+            self.handle_remote_config(handler, config)?;
+        }
+        let result = process_message(
+            server_to_agent,
+            &self.callbacks,
+            &self.synced_state,
+            self.capabilities,
+            self.message.clone(),
+            &self.pending_msg,
+            &self.instance_uid,
+        );
+        // The server may have assigned a new instance uid.
+        self.persist(|state| state.instance_uid = Some(self.instance_uid.get()));
+
+        match result? {
+            ProcessResult::NeedsResend => self.pending_msg.notify_or_warn(),
+            ProcessResult::Unavailable(retry_after) => {
+                return Err(ClientError::ServerUnavailable(retry_after));
+            }
+            ProcessResult::Synced => {}
+        }
+        Ok(())
+    }
+
+    // Applies the remote config through the handler unless it was already handled. If its status
+    // is reported, the `Applying` status is sent first and the config is applied afterwards, see
+    // `apply_sent_remote_config`.
+    fn handle_remote_config(
+        &self,
+        handler: &dyn RemoteConfigHandler,
+        config: AgentRemoteConfig,
+    ) -> ClientResult<()> {
+        let mut applying = self
+            .applying_remote_config
+            .lock()
+            .map_err(|_| ClientError::PoisonError)?;
+        if self
+            .synced_state
+            .remote_config_hash_unchanged(&config.config_hash)?
+            || applying
+                .as_ref()
+                .is_some_and(|applying| applying.config.config_hash == config.config_hash)
+        {
+            debug!("remote config already handled, ignoring it");
+            return Ok(());
+        }
+
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsRemoteConfig)
+        {
+            drop(applying);
+            return self.apply_remote_config(handler, config);
+        }
+
+        self.report_remote_config_status(RemoteConfigStatus {
+            last_remote_config_hash: config.config_hash.clone(),
+            status: RemoteConfigStatuses::Applying as i32,
+            error_message: String::default(),
+        })?;
+        *applying = Some(ApplyingRemoteConfig {
+            config,
+            sent: false,
+        });
+        Ok(())
+    }
+
+    /// Applies the remote config reported as `Applying`, once the message reporting it was sent.
+    /// Transports call it after sending every message.
+    pub(crate) fn apply_sent_remote_config(&self) -> ClientResult<()> {
+        let Some(handler) = self.remote_config_handler.as_deref() else {
+            return Ok(());
+        };
+        let applying = self
+            .applying_remote_config
+            .lock()
+            .map_err(|_| ClientError::PoisonError)?
+            .take_if(|applying| applying.sent);
+        match applying {
+            Some(applying) => self.apply_remote_config(handler, applying.config),
+            None => Ok(()),
+        }
+    }
+
+    // Applies the remote config through the handler, reporting the `Applied` or `Failed` status.
+    fn apply_remote_config(
+        &self,
+        handler: &dyn RemoteConfigHandler,
+        config: AgentRemoteConfig,
+    ) -> ClientResult<()> {
+        let reports_status = self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsRemoteConfig);
+        let report = |status: RemoteConfigStatuses, error_message: String| {
+            if !reports_status {
+                return Ok(());
+            }
+            self.report_remote_config_status(RemoteConfigStatus {
+                last_remote_config_hash: config.config_hash.clone(),
+                status: status as i32,
+                error_message,
+            })
+        };
+
+        let result = handler.apply(&config);
+        // The config is not handled again, even if its status is not reported.
+        self.remember_remote_config_hash(config.config_hash.clone())?;
+        if let Err(err) = result {
+            warn!(%err, "applying the remote config");
+            return report(RemoteConfigStatuses::Failed, err.to_string());
+        }
+        report(RemoteConfigStatuses::Applied, String::default())?;
+
+        if handler.update_effective_config()
+            && self
+                .capabilities
+                .has_capability(AgentCapabilities::ReportsEffectiveConfig)
+        {
+            let _ = self.report_effective_config().inspect_err(
+                |err| error!(%err, "updating the effective config after applying the remote config"),
+            );
+        }
+        Ok(())
+    }
+
+    // Reports the status of the remote config, unless it didn't change.
+    fn report_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()> {
+        if self.synced_state.remote_config_status_unchanged(&status)? {
+            return Ok(());
+        }
+        self.synced_state.set_remote_config_status(status.clone())?;
+        if status.status == RemoteConfigStatuses::Applied as i32
+            || status.status == RemoteConfigStatuses::Failed as i32
+        {
+            self.remember_remote_config_hash(status.last_remote_config_hash.clone())?;
+        }
+        self.persist(|state| state.remote_config_status = Some(status.clone()));
+
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.remote_config_status = Some(status);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with remote"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    // Remembers the hash of the remote config applied or failed to be applied, so it is not handled
+    // again, persisting it if a `StateStore` was set.
+    fn remember_remote_config_hash(&self, hash: Vec<u8>) -> ClientResult<()> {
+        self.synced_state.set_remote_config_hash(hash.clone())?;
+        self.persist(|state| state.remote_config_hash = Some(hash));
+        Ok(())
+    }
+
+    // Fetches the effective config from the callbacks and reports it, unless it didn't change.
+    fn report_effective_config(&self) -> ClientResult<()> {
+        let config = self
+            .callbacks
+            .get_effective_config()
+            .map_err(|_| ClientError::EffectiveConfigError)?;
+
+        if self.synced_state.effective_config_unchanged(&config)? {
+            return Ok(());
+        }
+        self.synced_state.set_effective_config(&config)?;
+
+        // update message
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.effective_config = Some(config);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with fetched effective config"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    // Removes the telemetry and other connection settings of an offer with the same hash as the
+    // last accepted one, so they are only surfaced when they change. Otherwise, they are handled
+    // by the `ConnectionSettingsHandler` if set, or left in the offer to be surfaced through
+    // `on_message`, and the hash is remembered if all of them are accepted.
+    fn handle_connection_settings_offers(
+        &self,
+        offers: &mut ConnectionSettingsOffers,
+    ) -> ClientResult<()> {
+        if self
+            .synced_state
+            .opamp_connection_settings_hash_unchanged(&offers.hash)?
+        {
+            debug!("OpAMP connection settings offer already accepted, ignoring it");
+            offers.opamp = None;
+        }
+        if self
+            .synced_state
+            .connection_settings_hash_unchanged(&offers.hash)?
+        {
+            debug!("connection settings offer already accepted, ignoring the telemetry settings");
+            take_telemetry_connection_settings(offers);
+            return Ok(());
+        }
+
+        let accepted = match self.connection_settings_handler.as_deref() {
+            Some(handler) => offer_connection_settings(
+                handler,
+                get_telemetry_connection_settings(
+                    Some(take_telemetry_connection_settings(offers)),
+                    self.capabilities,
+                ),
+            ),
+            None => true,
+        };
+        // Offers without hash cannot be identified, they are always surfaced.
+        if accepted && !offers.hash.is_empty() {
+            self.synced_state
+                .set_connection_settings_hash(offers.hash.clone())?;
+            self.persist(|state| state.connection_settings_hash = Some(offers.hash.clone()));
+        }
+        Ok(())
+    }
+}
+
+// Moves the telemetry and other connection settings out of the offers, leaving the OpAMP ones.
+fn take_telemetry_connection_settings(
+    offers: &mut ConnectionSettingsOffers,
+) -> ConnectionSettingsOffers {
+    ConnectionSettingsOffers {
+        own_metrics: offers.own_metrics.take(),
+        own_traces: offers.own_traces.take(),
+        own_logs: offers.own_logs.take(),
+        other_connections: std::mem::take(&mut offers.other_connections),
+        ..Default::default()
+    }
+}
+
+// Offers every setting to the handler, returning whether all of them were accepted.
+fn offer_connection_settings(
+    handler: &dyn ConnectionSettingsHandler,
+    (own_metrics, own_traces, own_logs, other_connections): ConnectionSettings,
+) -> bool {
+    let results = [
+        own_metrics.map(|settings| ("own_metrics", handler.on_own_metrics(&settings))),
+        own_traces.map(|settings| ("own_traces", handler.on_own_traces(&settings))),
+        own_logs.map(|settings| ("own_logs", handler.on_own_logs(&settings))),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(other_connections.iter().map(|(name, settings)| {
+        (
+            name.as_str(),
+            handler.on_other_connection_settings(name, settings),
+        )
+    }));
+
+    let mut accepted = true;
+    for (settings_name, result) in results {
+        if let Err(err) = result {
+            warn!(settings_name, %err, "connection settings offer rejected by the agent");
+            accepted = false;
+        }
+    }
+    accepted
+}
+
+// Removes the fields of the message the server doesn't accept according to its capabilities.
+// They are kept in the synced state, so they are sent if the server requests the full state. The
+// fields without a corresponding server capability, such as the custom messages or the available
+// components, are always sent.
+fn remove_unaccepted_fields(msg: &mut AgentToServer, server_capabilities: ServerCapabilities) {
+    if msg.remote_config_status.is_some()
+        && !server_capabilities.has_capability(proto::ServerCapabilities::AcceptsStatus)
+    {
+        debug!("the server doesn't accept status reports, removing the remote config status");
+        msg.remote_config_status = None;
+    }
+    if msg.effective_config.is_some()
+        && !server_capabilities.has_capability(proto::ServerCapabilities::AcceptsEffectiveConfig)
+    {
+        debug!("the server doesn't accept the effective config, removing it from the message");
+        msg.effective_config = None;
+    }
+    if msg.package_statuses.is_some()
+        && !server_capabilities.has_capability(proto::ServerCapabilities::AcceptsPackagesStatus)
+    {
+        debug!("the server doesn't accept package statuses, removing them from the message");
+        msg.package_statuses = None;
+    }
+    if msg.connection_settings_request.is_some()
+        && !server_capabilities
+            .has_capability(proto::ServerCapabilities::AcceptsConnectionSettingsRequest)
+    {
+        debug!(
+            "the server doesn't accept connection settings requests, removing it from the message"
+        );
+        msg.connection_settings_request = None;
+    }
+}
+
+impl<C> Client for ClientCore<C>
+where
+    C: Callbacks + Send + Sync,
+{
+    /// Sets the agent description of the Agent.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn set_agent_description(&self, description: AgentDescription) -> ClientResult<()> {
+        if self
+            .synced_state
+            .agent_description_unchanged(&description)?
+        {
+            return Ok(());
+        }
+        self.synced_state
+            .set_agent_description(description.clone())?;
+
+        // update message
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.agent_description = Some(description);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with provided description"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+    /// Returns the agent description from the synced state.
+    fn get_agent_description(&self) -> ClientResult<AgentDescription> {
+        match self.synced_state.agent_description() {
+            Ok(Some(description)) => Ok(description),
+            Err(e) => Err(e.into()),
+            _ => Ok(AgentDescription::default()),
+        }
+    }
+
+    /// Sets the health status of the Agent.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn set_health(&self, health: ComponentHealth) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsHealth)
+        {
+            return Err(ClientError::UnsetHealthCapability);
+        }
+
+        if self.synced_state.health_unchanged(&health)? {
+            return Ok(());
+        }
+        self.synced_state.set_health(health.clone())?;
+
+        // update message
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.health = Some(health);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with provided health"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Sets the health of the component in the provided path of the health tree.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn set_component_health(&self, path: &[&str], health: ComponentHealth) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsHealth)
+        {
+            return Err(ClientError::UnsetHealthCapability);
+        }
+
+        let Some(health) =
+            self.synced_state
+                .update_component_health(path, health, self.derive_parent_health)?
+        else {
+            return Ok(());
+        };
+
+        // update message
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.health = Some(health);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            component = path.join("/"),
+            "sending AgentToServer with updated component health"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Fetches the current local effective config using
+    /// [`get_effective_config`](Callbacks::get_effective_config) callback and sends it to the Server.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn update_effective_config(&self) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsEffectiveConfig)
+        {
+            return Err(ClientError::UnsetEffectConfigCapability);
+        }
+
+        self.report_effective_config()
+    }
+
+    /// Sends the status of the remote config
+    /// that was previously received from the Server.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn set_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsRemoteConfig)
+        {
+            return Err(ClientError::UnsetRemoteConfigStatusCapability);
+        }
+
+        self.report_remote_config_status(status)
+    }
+
+    fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()> {
+        if self
+            .synced_state
+            .custom_capabilities_unchanged(&custom_capabilities)?
+        {
+            return Ok(());
+        }
+        self.synced_state
+            .set_custom_capabilities(custom_capabilities.clone())?;
+
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.custom_capabilities = Some(custom_capabilities);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with custom capabilities"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Sets the package statuses of the Agent.
+    ///
+    /// It uses compression and will only modify the message if there is a change.
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsPackageStatuses)
+        {
+            return Err(ClientError::UnsetPackageStatusesCapability);
+        }
+
+        if self.synced_state.package_statuses_unchanged(&statuses)? {
+            return Ok(());
+        }
+        self.synced_state.set_package_statuses(statuses.clone())?;
+
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.package_statuses = Some(statuses);
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with package statuses"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Queues a custom message to be sent immediately to the Server.
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()> {
+        if !self
+            .synced_state
+            .custom_capabilities()?
+            .is_some_and(|c| c.capabilities.contains(&message.capability))
+        {
+            return Err(ClientError::UnsupportedCustomCapability(message.capability));
+        }
+
+        let mut next_message = self.message.write().map_err(|_| ClientError::PoisonError)?;
+        if next_message.has_pending_custom_message() {
+            return Err(ClientError::CustomMessagePending);
+        }
+        next_message.update(|msg| {
+            msg.custom_message = Some(message);
+        });
+        drop(next_message);
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with custom message"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Returns the capabilities reported by the Server.
+    fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>> {
+        Ok(self.synced_state.server_capabilities()?)
+    }
+
+    /// Sets the available components of the Agent, only their hash is sent.
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsAvailableComponents)
+        {
+            return Err(ClientError::UnsetAvailableComponentsCapability);
+        }
+        if components.hash.is_empty() {
+            return Err(ClientError::EmptyAvailableComponentsHash);
+        }
+
+        if self
+            .synced_state
+            .available_components_unchanged(&components)?
+        {
+            return Ok(());
+        }
+        let hash = components.hash.clone();
+        self.synced_state.set_available_components(components)?;
+
+        // The Server requests the full components through the ReportAvailableComponents flag if
+        // it doesn't know the hash.
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.available_components = Some(AvailableComponents {
+                    hash,
+                    ..Default::default()
+                });
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with the available components hash"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+
+    /// Queues a connection settings request including the provided CSR.
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()> {
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::AcceptsOpAmpConnectionSettings)
+        {
+            return Err(ClientError::UnsetOpAmpConnectionSettingsCapability);
+        }
+
+        let server_capability = proto::ServerCapabilities::AcceptsConnectionSettingsRequest;
+        if !self
+            .synced_state
+            .server_capabilities()?
+            .is_some_and(|capabilities| capabilities.has_capability(server_capability))
+        {
+            return Err(ClientError::UnsupportedServerCapability(server_capability));
+        }
+        self.synced_state.set_certificate_requested()?;
+
+        self.message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.connection_settings_request = Some(ConnectionSettingsRequest {
+                    opamp: Some(OpAmpConnectionSettingsRequest {
+                        certificate_request: Some(CertificateRequest { csr }),
+                    }),
+                });
+            });
+
+        debug!(
+            instance_uid = %self.instance_uid,
+            "sending AgentToServer with connection settings request"
+        );
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+}
@@ -1,7 +1,16 @@
-use std::sync::RwLock;
+use std::{
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
 
-use crate::opamp::proto::{
-    AgentDescription, ComponentHealth, CustomCapabilities, PackageStatuses, RemoteConfigStatus,
+use crate::{
+    opamp::proto::{
+        AgentDescription, AvailableComponents, ComponentHealth, CustomCapabilities,
+        EffectiveConfig, PackageStatuses, RemoteConfigStatus,
+    },
+    operation::capabilities::ServerCapabilities,
 };
 
 use std::sync::PoisonError;
@@ -35,12 +44,13 @@ impl<T> From<PoisonError<T>> for SyncedStateError {
 // keep it in memory. To avoid storing it in memory the EffectiveConfig is supposed to be
 // stored by the Agent implementation (e.g. it can be stored on disk) and is fetched
 // via get_effective_config callback when it is needed by OpAMP client, and then it is
-// discarded from memory. See implementation of update_effective_config().
+// discarded from memory. Only a digest of the last one is kept, so it is not sent again
+// if it didn't change. See implementation of update_effective_config().
 //
-// It is safe to call methods of this struct concurrently.
-#[derive(Debug, Default)]
+// It is safe to call methods of this struct concurrently. Clones share the same underlying state.
+#[derive(Debug, Default, Clone)]
 pub struct ClientSyncedState {
-    data: RwLock<Data>,
+    data: Arc<RwLock<Data>>,
 }
 
 #[derive(Debug, Default)]
@@ -49,7 +59,20 @@ struct Data {
     custom_capabilities: Option<CustomCapabilities>,
     health: Option<ComponentHealth>,
     remote_config_status: Option<RemoteConfigStatus>,
+    // Hash of the last remote config applied or failed to be applied.
+    remote_config_hash: Option<Vec<u8>>,
     package_statuses: Option<PackageStatuses>,
+    available_components: Option<AvailableComponents>,
+    // Digest of the last effective config, see `effective_config_digest`.
+    effective_config_digest: Option<Vec<u8>>,
+    // Hash of the last connection settings offer accepted by the Agent.
+    connection_settings_hash: Option<Vec<u8>>,
+    // Hash of the last connection settings offer whose OpAMP settings were handled, applied or not.
+    opamp_connection_settings_hash: Option<Vec<u8>>,
+    // Capabilities reported by the server in the last message including them.
+    server_capabilities: Option<ServerCapabilities>,
+    // Whether a client certificate was requested and the server has not offered it yet.
+    certificate_requested: bool,
 }
 
 impl ClientSyncedState {
@@ -90,6 +113,36 @@ impl ClientSyncedState {
         Ok(self.data.read()?.health.clone())
     }
 
+    /// Sets the health of the component in the provided path of the health tree, see
+    /// [`set_component_health`]. Returns the updated tree, or `None` if it is the same as the
+    /// current one, which is kept in that case.
+    pub(crate) fn update_component_health(
+        &self,
+        path: &[&str],
+        health: ComponentHealth,
+        derive_parent_health: bool,
+    ) -> Result<Option<ComponentHealth>, SyncedStateError> {
+        let mut data = self.data.write()?;
+        let mut updated = data.health.clone().unwrap_or_default();
+        set_component_health(
+            &mut updated,
+            path,
+            health,
+            derive_parent_health,
+            now_unix_nano(),
+        );
+
+        if data
+            .health
+            .as_ref()
+            .is_some_and(|current| current.is_same_as(&updated))
+        {
+            return Ok(None);
+        }
+        data.health = Some(updated.clone());
+        Ok(Some(updated))
+    }
+
     pub(crate) fn health_unchanged(
         &self,
         health: &ComponentHealth,
@@ -125,7 +178,23 @@ impl ClientSyncedState {
         Ok(false)
     }
 
-    #[allow(dead_code)]
+    pub(crate) fn set_remote_config_hash(&self, hash: Vec<u8>) -> Result<(), SyncedStateError> {
+        self.data.write()?.remote_config_hash = Some(hash);
+        Ok(())
+    }
+
+    pub(crate) fn remote_config_hash_unchanged(
+        &self,
+        hash: &[u8],
+    ) -> Result<bool, SyncedStateError> {
+        Ok(self
+            .data
+            .read()?
+            .remote_config_hash
+            .as_deref()
+            .is_some_and(|synced| synced == hash))
+    }
+
     pub(crate) fn set_package_statuses(
         &self,
         status: PackageStatuses,
@@ -138,6 +207,16 @@ impl ClientSyncedState {
         Ok(self.data.read()?.package_statuses.clone())
     }
 
+    pub(crate) fn package_statuses_unchanged(
+        &self,
+        statuses: &PackageStatuses,
+    ) -> Result<bool, SyncedStateError> {
+        if let Some(synced_package_statuses) = self.package_statuses()? {
+            return Ok(synced_package_statuses.eq(statuses));
+        }
+        Ok(false)
+    }
+
     pub(crate) fn custom_capabilities(
         &self,
     ) -> Result<Option<CustomCapabilities>, SyncedStateError> {
@@ -159,13 +238,197 @@ impl ClientSyncedState {
         self.data.write()?.custom_capabilities = Some(custom_capabilities);
         Ok(())
     }
+
+    pub(crate) fn set_available_components(
+        &self,
+        components: AvailableComponents,
+    ) -> Result<(), SyncedStateError> {
+        self.data.write()?.available_components = Some(components);
+        Ok(())
+    }
+
+    pub(crate) fn available_components(
+        &self,
+    ) -> Result<Option<AvailableComponents>, SyncedStateError> {
+        Ok(self.data.read()?.available_components.clone())
+    }
+
+    /// The components are identified by their hash, so only the hash is compared.
+    pub(crate) fn available_components_unchanged(
+        &self,
+        components: &AvailableComponents,
+    ) -> Result<bool, SyncedStateError> {
+        Ok(self
+            .data
+            .read()?
+            .available_components
+            .as_ref()
+            .is_some_and(|synced| synced.hash == components.hash))
+    }
+
+    /// Only the digest of the effective config is kept.
+    pub(crate) fn set_effective_config(
+        &self,
+        config: &EffectiveConfig,
+    ) -> Result<(), SyncedStateError> {
+        self.data.write()?.effective_config_digest = Some(effective_config_digest(config));
+        Ok(())
+    }
+
+    pub(crate) fn effective_config_unchanged(
+        &self,
+        config: &EffectiveConfig,
+    ) -> Result<bool, SyncedStateError> {
+        Ok(self
+            .data
+            .read()?
+            .effective_config_digest
+            .as_ref()
+            .is_some_and(|digest| *digest == effective_config_digest(config)))
+    }
+
+    pub(crate) fn set_connection_settings_hash(
+        &self,
+        hash: Vec<u8>,
+    ) -> Result<(), SyncedStateError> {
+        self.data.write()?.connection_settings_hash = Some(hash);
+        Ok(())
+    }
+
+    pub(crate) fn connection_settings_hash_unchanged(
+        &self,
+        hash: &[u8],
+    ) -> Result<bool, SyncedStateError> {
+        Ok(self
+            .data
+            .read()?
+            .connection_settings_hash
+            .as_deref()
+            .is_some_and(|synced| synced == hash))
+    }
+
+    pub(crate) fn set_opamp_connection_settings_hash(
+        &self,
+        hash: Vec<u8>,
+    ) -> Result<(), SyncedStateError> {
+        self.data.write()?.opamp_connection_settings_hash = Some(hash);
+        Ok(())
+    }
+
+    pub(crate) fn opamp_connection_settings_hash_unchanged(
+        &self,
+        hash: &[u8],
+    ) -> Result<bool, SyncedStateError> {
+        Ok(self
+            .data
+            .read()?
+            .opamp_connection_settings_hash
+            .as_deref()
+            .is_some_and(|synced| synced == hash))
+    }
+
+    pub(crate) fn server_capabilities(
+        &self,
+    ) -> Result<Option<ServerCapabilities>, SyncedStateError> {
+        Ok(self.data.read()?.server_capabilities)
+    }
+    pub(crate) fn set_server_capabilities(
+        &self,
+        capabilities: ServerCapabilities,
+    ) -> Result<(), SyncedStateError> {
+        self.data.write()?.server_capabilities = Some(capabilities);
+        Ok(())
+    }
+
+    pub(crate) fn set_certificate_requested(&self) -> Result<(), SyncedStateError> {
+        self.data.write()?.certificate_requested = true;
+        Ok(())
+    }
+    /// Returns whether a client certificate was requested, clearing the request.
+    pub(crate) fn take_certificate_requested(&self) -> Result<bool, SyncedStateError> {
+        Ok(std::mem::take(
+            &mut self.data.write()?.certificate_requested,
+        ))
+    }
+}
+
+// Sets the health of the component in the path, creating the missing components. The components
+// of the current health which are not included in the new one are kept. The status time is set to
+// `now` if the new health doesn't include it. If `derive_parent_health` is set, every parent in the
+// path is healthy only if all its components are.
+fn set_component_health(
+    component: &mut ComponentHealth,
+    path: &[&str],
+    mut health: ComponentHealth,
+    derive_parent_health: bool,
+    now: u64,
+) {
+    let Some((name, path)) = path.split_first() else {
+        if health.status_time_unix_nano == 0 {
+            health.status_time_unix_nano = now;
+        }
+        let components = std::mem::take(&mut component.component_health_map);
+        *component = health;
+        for (name, inner) in components {
+            component.component_health_map.entry(name).or_insert(inner);
+        }
+        return;
+    };
+
+    let inner = component
+        .component_health_map
+        .entry(name.to_string())
+        .or_insert_with(|| ComponentHealth {
+            status_time_unix_nano: now,
+            ..Default::default()
+        });
+    set_component_health(inner, path, health, derive_parent_health, now);
+
+    if derive_parent_health {
+        let healthy = component
+            .component_health_map
+            .values()
+            .all(|inner| inner.healthy);
+        if component.healthy != healthy {
+            component.healthy = healthy;
+            component.status_time_unix_nano = now;
+        }
+    }
+}
+
+fn now_unix_nano() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+// Computes the digest of the config files sorted by name, as the order of the map is not stable.
+// Every field is prefixed with its length so different files cannot have the same digest.
+fn effective_config_digest(config: &EffectiveConfig) -> Vec<u8> {
+    let mut files = config
+        .config_map
+        .iter()
+        .flat_map(|config_map| config_map.config_map.iter())
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(name, _)| *name);
+
+    let mut hasher = Sha256::new();
+    for (name, file) in files {
+        for field in [name.as_bytes(), &file.body, file.content_type.as_bytes()] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+    }
+    hasher.finalize().to_vec()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::opamp::proto::any_value::Value;
-    use crate::opamp::proto::{AnyValue, KeyValue};
+    use crate::opamp::proto::{AgentConfigFile, AgentConfigMap, AnyValue, KeyValue};
+    use std::collections::HashMap;
 
     #[test]
     fn agent_description_unchanged() {
@@ -226,6 +489,168 @@ mod tests {
         );
     }
 
+    #[test]
+    fn package_statuses_unchanged() {
+        let expected_package_statuses = PackageStatuses {
+            server_provided_all_packages_hash: vec![1, 2, 3],
+            ..Default::default()
+        };
+
+        let synced_state = ClientSyncedState::default();
+        assert!(
+            !synced_state
+                .package_statuses_unchanged(&expected_package_statuses)
+                .unwrap()
+        );
+        assert!(
+            synced_state
+                .set_package_statuses(expected_package_statuses.clone())
+                .is_ok()
+        );
+        assert!(
+            synced_state
+                .package_statuses_unchanged(&expected_package_statuses)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn update_component_health() {
+        let healthy = |healthy: bool| ComponentHealth {
+            healthy,
+            ..Default::default()
+        };
+        let component = |health: &ComponentHealth, path: &[&str]| {
+            path.iter().fold(health.clone(), |health, name| {
+                health.component_health_map[*name].clone()
+            })
+        };
+
+        let synced_state = ClientSyncedState::default();
+        let health = synced_state
+            .update_component_health(&["pipelines", "traces"], healthy(true), false)
+            .unwrap()
+            .unwrap();
+        let traces = component(&health, &["pipelines", "traces"]);
+        assert!(traces.healthy);
+        assert_ne!(traces.status_time_unix_nano, 0);
+        // Parents are not derived
+        assert!(!component(&health, &["pipelines"]).healthy);
+        assert!(!health.healthy);
+
+        // The same health with a new status time is unchanged
+        assert_eq!(
+            synced_state
+                .update_component_health(&["pipelines", "traces"], healthy(true), false)
+                .unwrap(),
+            None
+        );
+        assert_eq!(synced_state.health().unwrap(), Some(health));
+
+        // The components are kept when the parent is set
+        let health = synced_state
+            .update_component_health(
+                &[],
+                ComponentHealth {
+                    healthy: true,
+                    status_time_unix_nano: 42,
+                    ..Default::default()
+                },
+                false,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(health.status_time_unix_nano, 42);
+        assert!(component(&health, &["pipelines", "traces"]).healthy);
+    }
+
+    #[test]
+    fn update_component_health_derives_parent_health() {
+        let healthy = |healthy: bool| ComponentHealth {
+            healthy,
+            ..Default::default()
+        };
+        let synced_state = ClientSyncedState::default();
+
+        let health = synced_state
+            .update_component_health(&["pipelines", "traces"], healthy(true), true)
+            .unwrap()
+            .unwrap();
+        assert!(health.healthy);
+        assert!(health.component_health_map["pipelines"].healthy);
+
+        let health = synced_state
+            .update_component_health(&["pipelines", "logs"], healthy(false), true)
+            .unwrap()
+            .unwrap();
+        assert!(!health.healthy);
+        let pipelines = &health.component_health_map["pipelines"];
+        assert!(!pipelines.healthy);
+        assert!(pipelines.component_health_map["traces"].healthy);
+
+        let health = synced_state
+            .update_component_health(&["pipelines", "logs"], healthy(true), true)
+            .unwrap()
+            .unwrap();
+        assert!(health.healthy);
+    }
+
+    #[test]
+    fn effective_config_unchanged() {
+        let file = |body: &str| AgentConfigFile {
+            body: body.as_bytes().to_vec(),
+            content_type: "text/yaml".to_string(),
+        };
+        let config = |files: Vec<(&str, AgentConfigFile)>| EffectiveConfig {
+            config_map: Some(AgentConfigMap {
+                config_map: files
+                    .into_iter()
+                    .map(|(name, file)| (name.to_string(), file))
+                    .collect(),
+            }),
+        };
+        let files = (0..10)
+            .map(|i| (format!("file-{i}"), file("body")))
+            .collect::<HashMap<_, _>>();
+
+        let synced_state = ClientSyncedState::default();
+        assert!(
+            !synced_state
+                .effective_config_unchanged(&EffectiveConfig::default())
+                .unwrap()
+        );
+        synced_state
+            .set_effective_config(&EffectiveConfig {
+                config_map: Some(AgentConfigMap {
+                    config_map: files.clone(),
+                }),
+            })
+            .unwrap();
+
+        // The order of the files doesn't matter
+        let mut reversed = files.into_iter().collect::<Vec<_>>();
+        reversed.sort_by(|(a, _), (b, _)| b.cmp(a));
+        assert!(
+            synced_state
+                .effective_config_unchanged(&EffectiveConfig {
+                    config_map: Some(AgentConfigMap {
+                        config_map: reversed.into_iter().collect(),
+                    }),
+                })
+                .unwrap()
+        );
+
+        // Moving content between fields changes the digest
+        synced_state
+            .set_effective_config(&config(vec![("ab", file("c"))]))
+            .unwrap();
+        assert!(
+            !synced_state
+                .effective_config_unchanged(&config(vec![("a", file("bc"))]))
+                .unwrap()
+        );
+    }
+
     #[test]
     fn compare_health() {
         let health1 = ComponentHealth {
@@ -306,4 +731,15 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn certificate_requested() {
+        let synced_state = ClientSyncedState::default();
+        assert!(!synced_state.take_certificate_requested().unwrap());
+
+        synced_state.set_certificate_requested().unwrap();
+
+        assert!(synced_state.take_certificate_requested().unwrap());
+        assert!(!synced_state.take_certificate_requested().unwrap());
+    }
 }
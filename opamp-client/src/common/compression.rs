@@ -5,12 +5,46 @@ use libflate::gzip::{Decoder, Encoder};
 use prost::{DecodeError, Message};
 use thiserror::Error;
 
+/// Value of the `Accept-Encoding` header listing the algorithms the client can decode.
+pub(crate) const ACCEPT_ENCODING: &str = if cfg!(all(feature = "deflate", feature = "zstd")) {
+    "gzip, deflate, zstd"
+} else if cfg!(feature = "deflate") {
+    "gzip, deflate"
+} else if cfg!(feature = "zstd") {
+    "gzip, zstd"
+} else {
+    "gzip"
+};
+
 /// Compressor represents compression algorithm
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Compressor {
     /// Plain does not apply any compression algorithm to the encoded data.
     Plain,
     /// Gzip compresses the data with the gzip algorithm after encoding it.
     Gzip,
+    /// Deflate compresses the data with the zlib format after encoding it, as defined for the
+    /// `deflate` HTTP content coding.
+    #[cfg(feature = "deflate")]
+    Deflate,
+    /// Zstd compresses the data with the zstd algorithm after encoding it.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compressor {
+    /// Returns the `Content-Encoding` header value for the algorithm, [`None`] if the data is
+    /// not compressed.
+    pub(crate) fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compressor::Plain => None,
+            Compressor::Gzip => Some("gzip"),
+            #[cfg(feature = "deflate")]
+            Compressor::Deflate => Some("deflate"),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd => Some("zstd"),
+        }
+    }
 }
 
 #[derive(Error, Debug, PartialEq)]
@@ -20,12 +54,16 @@ pub enum CompressorError {
 }
 
 // TryFrom returns a compressor type given an slice of bytes
-// Only gzip format is supported
+// Only gzip format is supported, along with the formats enabled through cargo features
 impl TryFrom<&[u8]> for Compressor {
     type Error = CompressorError;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         match value {
             b"gzip" => Ok(Compressor::Gzip),
+            #[cfg(feature = "deflate")]
+            b"deflate" => Ok(Compressor::Deflate),
+            #[cfg(feature = "zstd")]
+            b"zstd" => Ok(Compressor::Zstd),
             val => match str::from_utf8(val) {
                 Ok(s) => Err(CompressorError::UnsupportedEncoding(s.to_string())),
                 Err(_) => Err(CompressorError::UnsupportedEncoding(format!("{val:?}"))),
@@ -54,14 +92,26 @@ pub(crate) fn encode_message<M>(comp: &Compressor, msg: &M) -> Result<Vec<u8>, E
 where
     M: Message,
 {
-    let proto_enc = msg.encode_to_vec();
+    compress(comp, msg.encode_to_vec())
+}
+
+/// Compresses already encoded data with the provided algorithm
+pub(crate) fn compress(comp: &Compressor, data: Vec<u8>) -> Result<Vec<u8>, EncoderError> {
     match comp {
-        Compressor::Plain => Ok(proto_enc),
+        Compressor::Plain => Ok(data),
         Compressor::Gzip => {
             let mut encoder = Encoder::new(Vec::new())?;
-            encoder.write_all(&proto_enc)?;
+            encoder.write_all(&data)?;
             Ok(encoder.finish().into_result()?)
         }
+        #[cfg(feature = "deflate")]
+        Compressor::Deflate => {
+            let mut encoder = libflate::zlib::Encoder::new(Vec::new())?;
+            encoder.write_all(&data)?;
+            Ok(encoder.finish().into_result()?)
+        }
+        #[cfg(feature = "zstd")]
+        Compressor::Zstd => Ok(zstd::encode_all(data.as_slice(), 0)?),
     }
 }
 
@@ -75,9 +125,18 @@ where
         Compressor::Gzip => {
             let mut decoder = Decoder::new(msg)?;
             let mut buf = Vec::new();
-            decoder.read_to_end(&mut buf).unwrap();
+            decoder.read_to_end(&mut buf)?;
+            Ok(M::decode(buf.as_slice())?)
+        }
+        #[cfg(feature = "deflate")]
+        Compressor::Deflate => {
+            let mut decoder = libflate::zlib::Decoder::new(msg)?;
+            let mut buf = Vec::new();
+            decoder.read_to_end(&mut buf)?;
             Ok(M::decode(buf.as_slice())?)
         }
+        #[cfg(feature = "zstd")]
+        Compressor::Zstd => Ok(M::decode(zstd::decode_all(msg)?.as_slice())?),
     }
 }
 
@@ -85,7 +144,9 @@ where
 mod tests {
     use std::collections::HashMap;
 
+    use assert_matches::assert_matches;
     use rand::distr::{Alphanumeric, SampleString};
+    use rstest::rstest;
 
     use crate::opamp::proto::{AgentConfigFile, AgentConfigMap, AgentToServer, EffectiveConfig};
 
@@ -149,4 +210,57 @@ mod tests {
             sample_message
         );
     }
+
+    #[test]
+    fn corrupted_gzip_stream() {
+        let message = AgentToServer {
+            sequence_num: 42,
+            ..Default::default()
+        };
+        let gzip_data = encode_message(&Compressor::Gzip, &message).unwrap();
+
+        // Valid header but truncated body
+        let truncated = &gzip_data[..gzip_data.len() - 4];
+        assert_matches!(
+            decode_message::<AgentToServer>(&Compressor::Gzip, truncated),
+            Err(DecoderError::IO(_))
+        );
+    }
+
+    #[rstest]
+    #[case::plain(b"gzip", Ok(Compressor::Gzip))]
+    #[cfg_attr(
+        feature = "deflate",
+        case::deflate(b"deflate", Ok(Compressor::Deflate))
+    )]
+    #[cfg_attr(feature = "zstd", case::zstd(b"zstd", Ok(Compressor::Zstd)))]
+    #[case::unsupported(b"br", Err(CompressorError::UnsupportedEncoding("br".to_string())))]
+    fn compressor_from_content_encoding(
+        #[case] value: &[u8],
+        #[case] expected: Result<Compressor, CompressorError>,
+    ) {
+        assert_eq!(Compressor::try_from(value), expected);
+    }
+
+    #[cfg(any(feature = "deflate", feature = "zstd"))]
+    #[rstest]
+    #[cfg_attr(feature = "deflate", case::deflate(Compressor::Deflate))]
+    #[cfg_attr(feature = "zstd", case::zstd(Compressor::Zstd))]
+    fn feature_compressors_roundtrip(#[case] compressor: Compressor) {
+        let message = AgentToServer {
+            sequence_num: 42,
+            ..Default::default()
+        };
+
+        let data = encode_message(&compressor, &message).unwrap();
+
+        assert_eq!(
+            decode_message::<AgentToServer>(&compressor, &data).unwrap(),
+            message
+        );
+        assert_matches!(
+            decode_message::<AgentToServer>(&compressor, &data[..data.len() / 2]),
+            Err(DecoderError::IO(_))
+        );
+    }
 }
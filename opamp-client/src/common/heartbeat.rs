@@ -0,0 +1,24 @@
+//! Heartbeat interval requested by the server.
+
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+/// Heartbeat interval requested by the server through the OpAMP connection settings, shared by
+/// the [`ClientCore`](super::client::ClientCore) processing the messages and the transport
+/// scheduling them. Clones share the same value.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SharedHeartbeatInterval(Arc<RwLock<Option<Duration>>>);
+
+impl SharedHeartbeatInterval {
+    /// Returns the last heartbeat interval requested by the server, if any.
+    pub(crate) fn get(&self) -> Option<Duration> {
+        *self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replaces the heartbeat interval.
+    pub(crate) fn set(&self, interval: Duration) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Some(interval);
+    }
+}
@@ -1,28 +1,35 @@
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use thiserror::Error;
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 
 use crate::common::clientstate::{ClientSyncedState, SyncedStateError};
 use crate::{
     opamp::proto::{
         AgentCapabilities::{
-            AcceptsOtherConnectionSettings, AcceptsRemoteConfig, AcceptsRestartCommand,
-            ReportsOwnLogs, ReportsOwnMetrics, ReportsOwnTraces,
+            AcceptsOpAmpConnectionSettings, AcceptsOtherConnectionSettings, AcceptsRemoteConfig,
+            AcceptsRestartCommand, ReportsHeartbeat, ReportsOwnLogs, ReportsOwnMetrics,
+            ReportsOwnTraces,
         },
-        AgentToServer, ConnectionSettingsOffers, CustomCapabilities, OtherConnectionSettings,
-        ServerToAgent, ServerToAgentFlags, TelemetryConnectionSettings,
+        AgentToServer, AgentToServerFlags, AvailableComponents, ConnectionSettingsOffers,
+        CustomCapabilities, OpAmpConnectionSettings, OtherConnectionSettings, ServerCapabilities,
+        ServerErrorResponse, ServerErrorResponseType, ServerToAgent, ServerToAgentFlags,
+        TelemetryConnectionSettings,
+        server_error_response::Details,
     },
     operation::{
         callbacks::{Callbacks, MessageData},
         capabilities::Capabilities,
+        instance_uid::{InstanceUid, InstanceUidError, SharedInstanceUid},
+        packages::PackageSyncer,
     },
 };
 
-use super::nextmessage::NextMessage;
+use super::{nextmessage::NextMessage, notifier::Notifier};
 use crate::opamp::proto::AgentCapabilities;
 
 #[derive(Error, Debug)]
@@ -33,12 +40,19 @@ pub enum ProcessError {
     /// Represents a synced state error.
     #[error("synced state error: {0}")]
     SyncedStateError(#[from] SyncedStateError),
+
+    /// The instance uid assigned by the server in the agent identification is not valid.
+    #[error("invalid instance uid in the agent identification: {0}")]
+    InvalidInstanceUid(#[from] InstanceUidError),
 }
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum ProcessResult {
     Synced,
     NeedsResend,
+    /// The server reported it is unavailable, the request should be retried later. It includes the
+    /// delay requested by the server, if any.
+    Unavailable(Option<Duration>),
 }
 
 /// Asynchronously parses a [`ServerToAgent`] message and calls the corresponding callbacks.
@@ -52,17 +66,28 @@ pub(crate) enum ProcessResult {
 /// * `synced_state` - A reference to the [`ClientSyncedState`] object holding the client state.
 /// * `capabilities` - A reference to the [`Capabilities`] object that describes agent capabilities.
 /// * `next_message` - An [`Arc<RwLock<NextMessage>>`] containing the next message to send.
+/// * `pending_msg` - A reference to the [`Notifier`] used to request a new message to be sent.
+/// * `instance_uid` - A reference to the [`SharedInstanceUid`] of the client, replaced if the server assigns a new one.
 ///
 /// # Returns
 ///
-/// A [`Result`] containing a [`ProcessResult`] or a [`ProcessError`].
+/// A [`Result`] containing a [`ProcessResult`] or a [`ProcessError`]. An invalid instance uid
+/// assigned by the server is returned as [`ProcessError::InvalidInstanceUid`] once the rest of the
+/// message has been processed, unless the server reported it is unavailable.
 pub(crate) fn process_message<C: Callbacks>(
     msg: ServerToAgent,
     callbacks: &C,
     synced_state: &ClientSyncedState,
     capabilities: Capabilities,
     next_message: Arc<RwLock<NextMessage>>,
+    pending_msg: &Notifier,
+    instance_uid: &SharedInstanceUid,
 ) -> Result<ProcessResult, ProcessError> {
+    // The server may omit its capabilities in subsequent messages.
+    if msg.capabilities != ServerCapabilities::Unspecified as u64 {
+        synced_state.set_server_capabilities(msg.capabilities.into())?;
+    }
+
     if msg
         .command
         .as_ref()
@@ -77,36 +102,130 @@ pub(crate) fn process_message<C: Callbacks>(
         return Ok(ProcessResult::Synced);
     }
     let custom_capabilities = synced_state.custom_capabilities()?;
-    let msg_data = message_data(&msg, capabilities, custom_capabilities);
-
-    if let Some(id) = &msg_data.agent_identification {
-        next_message
-            .write()
-            .map_err(|_| ProcessError::PoisonError)?
-            .update(move |msg: &mut AgentToServer| {
-                msg.instance_uid.clone_from(&id.new_instance_uid);
+    let mut msg_data = message_data(&msg, capabilities, custom_capabilities);
+
+    msg_data.package_syncer = msg
+        .packages_available
+        .clone()
+        .filter(|_| {
+            report_capability(
+                "PackagesAvailable",
+                capabilities,
+                AgentCapabilities::AcceptsPackages,
+            )
+        })
+        .map(|packages_available| {
+            PackageSyncer::new(
+                packages_available,
+                synced_state.clone(),
+                next_message.clone(),
+                pending_msg.clone(),
+                capabilities,
+            )
+        });
+
+    let mut invalid_instance_uid = None;
+    match (msg_data.instance_uid.clone(), new_instance_uid(&msg)) {
+        (Some(new_instance_uid), _) => {
+            let mut next_message = next_message
+                .write()
+                .map_err(|_| ProcessError::PoisonError)?;
+            info!(
+                %instance_uid, %new_instance_uid,
+                "switching to the instance uid assigned by the server"
+            );
+            // Every component of the client reads the shared instance uid, and the next message
+            // takes it while holding the lock, so all of them switch at once.
+            instance_uid.set(new_instance_uid);
+            next_message.update(|msg: &mut AgentToServer| {
+                // The instance uid is not requested anymore once the server provides it.
+                msg.flags &= !(AgentToServerFlags::RequestInstanceUid as u64);
             });
+        }
+        (None, Some(Err(err))) => {
+            error!(%err, "rejecting the agent identification received from server");
+            invalid_instance_uid = Some(err);
+        }
+        _ => {}
     }
 
     callbacks.on_message(msg_data);
 
-    // FIXME: revisit this once we clarify the opamp settings update flows
-    // if let Some(o) = msg
-    //     .connection_settings
-    //     .and_then(|s| s.opamp)
-    //     .filter(|_| report_capability("opamp", capabilities, AcceptsOpAmpConnectionSettings))
-    // {
-    //     callbacks
-    //         .on_opamp_connection_settings(&o)
-    //         .is_ok()
-    //         .then(|| callbacks.on_opamp_connection_settings_accepted(&o));
-    // }
-
-    if let Some(err) = msg.error_response {
+    if let Some(certificate) = msg
+        .connection_settings
+        .as_ref()
+        .and_then(|settings| settings.opamp.as_ref())
+        .and_then(|opamp| opamp.certificate.as_ref())
+        && synced_state.take_certificate_requested()?
+    {
+        callbacks.on_client_certificate(certificate);
+    }
+
+    let unavailable = msg.error_response.and_then(|err| {
         error!(?err, "received an error from server");
+        let retry = unavailable_retry(&err);
+        callbacks.on_error(err);
+        retry
+    });
+
+    let result = rcv_flags(synced_state, msg.flags, next_message, callbacks)?;
+
+    // Any message requested by the server is kept in the next message until the retry.
+    if let Some(retry_after) = unavailable {
+        return Ok(ProcessResult::Unavailable(retry_after));
+    }
+    if let Some(err) = invalid_instance_uid {
+        if result == ProcessResult::NeedsResend {
+            pending_msg.notify_or_warn();
+        }
+        return Err(err.into());
     }
+    Ok(result)
+}
 
-    rcv_flags(synced_state, msg.flags, next_message, callbacks)
+/// Returns the OpAMP connection settings offered by the server, if the agent has the
+/// `AcceptsOpAmpConnectionSettings` capability. Applying them depends on the transport, so the
+/// offer is not handled by [`process_message`].
+pub(crate) fn opamp_connection_settings(
+    msg: &ServerToAgent,
+    capabilities: Capabilities,
+) -> Option<OpAmpConnectionSettings> {
+    msg.connection_settings
+        .as_ref()
+        .and_then(|settings| settings.opamp.clone())
+        .filter(|_| {
+            report_capability(
+                "OpAmpConnectionSettings",
+                capabilities,
+                AcceptsOpAmpConnectionSettings,
+            )
+        })
+}
+
+/// Returns the heartbeat interval requested by the server through the OpAMP connection settings,
+/// if the agent has the `ReportsHeartbeat` capability. A zero interval is ignored, as HTTP clients
+/// keep polling the server anyway.
+pub(crate) fn heartbeat_interval(
+    msg: &ServerToAgent,
+    capabilities: Capabilities,
+) -> Option<Duration> {
+    msg.connection_settings
+        .as_ref()
+        .and_then(|settings| settings.opamp.as_ref())
+        .map(|opamp| opamp.heartbeat_interval_seconds)
+        .filter(|seconds| *seconds > 0)
+        .filter(|_| report_capability("HeartbeatInterval", capabilities, ReportsHeartbeat))
+        .map(Duration::from_secs)
+}
+
+// Returns the delay requested by the server if the error is of type `Unavailable`. The outer
+// option is `None` if the server is not unavailable.
+fn unavailable_retry(err: &ServerErrorResponse) -> Option<Option<Duration>> {
+    (err.r#type == ServerErrorResponseType::Unavailable as i32).then(|| {
+        err.details.map(|Details::RetryInfo(retry_info)| {
+            Duration::from_nanos(retry_info.retry_after_nanoseconds)
+        })
+    })
 }
 
 // Function handling received flags.
@@ -117,31 +236,54 @@ fn rcv_flags<C: Callbacks>(
     callbacks: &C,
 ) -> Result<ProcessResult, ProcessError> {
     let can_report_full_state = flags & ServerToAgentFlags::ReportFullState as u64 != 0;
+    let report_available_components =
+        flags & ServerToAgentFlags::ReportAvailableComponents as u64 != 0;
+    if !can_report_full_state && !report_available_components {
+        return Ok(ProcessResult::Synced);
+    }
+
+    let mut next_message = next_message
+        .write()
+        .map_err(|_| ProcessError::PoisonError)?;
     if can_report_full_state {
         let agent_description = state.agent_description()?;
         let health = state.health()?;
         let remote_config_status = state.remote_config_status()?;
         let package_statuses = state.package_statuses()?;
         let custom_capabilities = state.custom_capabilities()?;
+        // Only the hash of the available components is part of the state.
+        let available_components =
+            state
+                .available_components()?
+                .map(|components| AvailableComponents {
+                    hash: components.hash,
+                    ..Default::default()
+                });
+        let effective_config = callbacks
+            .get_effective_config()
+            .inspect_err(|err| error!(%err, "cannot get effective config"))
+            .ok();
+        if let Some(effective_config) = effective_config.as_ref() {
+            state.set_effective_config(effective_config)?;
+        }
 
-        next_message
-            .write()
-            .map_err(|_| ProcessError::PoisonError)?
-            .update(|msg: &mut AgentToServer| {
-                msg.agent_description = agent_description;
-                msg.custom_capabilities = custom_capabilities;
-                msg.health = health;
-                msg.remote_config_status = remote_config_status;
-                msg.package_statuses = package_statuses;
-                msg.effective_config = callbacks
-                    .get_effective_config()
-                    .inspect_err(|err| error!(%err, "cannot get effective config"))
-                    .ok();
-            });
-        Ok(ProcessResult::NeedsResend)
-    } else {
-        Ok(ProcessResult::Synced)
+        next_message.update(|msg: &mut AgentToServer| {
+            msg.agent_description = agent_description;
+            msg.custom_capabilities = custom_capabilities;
+            msg.health = health;
+            msg.remote_config_status = remote_config_status;
+            msg.package_statuses = package_statuses;
+            msg.available_components = available_components;
+            msg.effective_config = effective_config;
+        });
     }
+    if report_available_components {
+        let available_components = state.available_components()?;
+        next_message.update(|msg: &mut AgentToServer| {
+            msg.available_components = available_components;
+        });
+    }
+    Ok(ProcessResult::NeedsResend)
 }
 
 // A helper function that returns a MessageData object containing relevant fields based on agent capabilities.
@@ -165,21 +307,11 @@ fn message_data(
     let (own_metrics, own_traces, own_logs, other_connection_settings) =
         get_telemetry_connection_settings(msg.connection_settings.clone(), capabilities);
 
-    // TODO: package_syncer feature
-    // let packages_available;
-    // let package_syncer;
-    // if let Some(packages_available) = msg.packages_available.filter(|_| report_capability("PackagesAvailable", capabilities, AgentCapabilities::AcceptsPackages)) {
-    //     let packages_available = packages_available;
-    //     let package_syncer = PackageSyncer::new(packages_available, self.sender.clone());
-    // }
-
-    let agent_identification = msg.agent_identification.clone().filter(|id| {
-        let is_empty_string = id.new_instance_uid.is_empty();
-        if is_empty_string {
-            debug!("empty instance UID is not allowed. Ignoring agent identification.");
-        }
-        !is_empty_string
-    });
+    let instance_uid = new_instance_uid(msg).and_then(Result::ok);
+    let agent_identification = msg
+        .agent_identification
+        .clone()
+        .filter(|_| instance_uid.is_some());
 
     MessageData {
         remote_config,
@@ -188,13 +320,21 @@ fn message_data(
         own_logs,
         other_connection_settings,
         agent_identification,
+        instance_uid,
         custom_capabilities,
         custom_message,
-        // packages_available,
-        // package_syncer,
+        // The package syncer requires access to the client state, it is set by `process_message`.
+        package_syncer: None,
     }
 }
 
+// Returns the instance uid assigned by the server through the agent identification, if any.
+fn new_instance_uid(msg: &ServerToAgent) -> Option<Result<InstanceUid, InstanceUidError>> {
+    msg.agent_identification
+        .as_ref()
+        .map(|id| InstanceUid::try_from(id.new_instance_uid.clone()))
+}
+
 // A helper function checking if an agent has a specified capability and reports information accordingly.
 fn report_capability(
     opt_name: &str,
@@ -213,7 +353,7 @@ fn report_capability(
 }
 
 // Type alias for connection settings.
-type ConnectionSettings = (
+pub(crate) type ConnectionSettings = (
     Option<TelemetryConnectionSettings>,
     Option<TelemetryConnectionSettings>,
     Option<TelemetryConnectionSettings>,
@@ -221,7 +361,7 @@ type ConnectionSettings = (
 );
 
 // A helper function that extracts the telemetry connection settings based on agent capabilities.
-fn get_telemetry_connection_settings(
+pub(crate) fn get_telemetry_connection_settings(
     settings: Option<ConnectionSettingsOffers>,
     capabilities: Capabilities,
 ) -> ConnectionSettings {
@@ -254,11 +394,13 @@ mod tests {
     use crate::common::clientstate::ClientSyncedState;
     use crate::opamp::proto::{
         AgentConfigMap, AgentDescription, AgentIdentification, AgentRemoteConfig, AnyValue,
-        ComponentHealth, CustomCapabilities, CustomMessage, KeyValue, PackageStatuses,
-        RemoteConfigStatus, ServerErrorResponse, ServerToAgent, ServerToAgentCommand,
-        any_value::Value,
+        ComponentDetails, ComponentHealth, CustomCapabilities, CustomMessage, KeyValue,
+        PackageStatuses, PackagesAvailable, RemoteConfigStatus, RetryInfo, ServerErrorResponse,
+        ServerToAgent, ServerToAgentCommand, TlsCertificate, any_value::Value,
     };
     use crate::operation::callbacks::tests::MockCallbacksMockall;
+    use assert_matches::assert_matches;
+    use rstest::rstest;
     use tracing_test::traced_test;
 
     #[test]
@@ -284,6 +426,8 @@ mod tests {
             &synced_state,
             capabilities,
             next_message,
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
         );
 
         assert!(res.is_ok());
@@ -310,6 +454,8 @@ mod tests {
             &synced_state,
             capabilities,
             next_message,
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
         );
 
         assert!(res.is_ok());
@@ -318,11 +464,11 @@ mod tests {
 
     #[test]
     fn receive_agent_identification() {
-        let actual_message: Vec<u8> = "some_agent_uid".into();
+        let new_instance_uid = InstanceUid::create();
 
         let server_to_agent = ServerToAgent {
             agent_identification: Some(AgentIdentification {
-                new_instance_uid: actual_message.clone(),
+                new_instance_uid: new_instance_uid.clone().into(),
             }),
             ..ServerToAgent::default()
         };
@@ -330,11 +476,16 @@ mod tests {
         let synced_state = ClientSyncedState::default();
         let capabilities = capabilities!();
         let custom_capabilities = CustomCapabilities::default();
-        let next_message = Arc::new(RwLock::new(NextMessage::default()));
+        let next_message = Arc::new(RwLock::new(NextMessage::new(AgentToServer {
+            flags: AgentToServerFlags::RequestInstanceUid as u64,
+            ..AgentToServer::default()
+        })));
+        let instance_uid = SharedInstanceUid::from(InstanceUid::create());
 
         callbacks.should_not_on_command(); // I expect on_command to NOT be called
 
         let msg_data = message_data(&server_to_agent, capabilities, Some(custom_capabilities));
+        assert_eq!(msg_data.instance_uid, Some(new_instance_uid.clone()));
         callbacks.should_on_message(msg_data);
 
         let res = process_message(
@@ -343,30 +494,72 @@ mod tests {
             &synced_state,
             capabilities,
             next_message.clone(),
+            &Notifier::new("test".to_string()).0,
+            &instance_uid,
         );
 
+        assert_eq!(instance_uid.get(), new_instance_uid);
         let expected_message = next_message.write().unwrap().pop();
-        assert_eq!(expected_message.instance_uid, actual_message);
+        assert_eq!(
+            expected_message.flags,
+            AgentToServerFlags::Unspecified as u64
+        );
 
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), ProcessResult::Synced);
     }
 
     #[test]
-    /// Expected to not call message update if [`instance_uid`] is not present in [`ServerToAgent`] message
+    fn receive_invalid_agent_identification() {
+        let server_to_agent = ServerToAgent {
+            agent_identification: Some(AgentIdentification {
+                new_instance_uid: "invalid".into(),
+            }),
+            ..ServerToAgent::default()
+        };
+        let current_instance_uid = InstanceUid::create();
+        let instance_uid = SharedInstanceUid::from(current_instance_uid.clone());
+        let next_message = Arc::new(RwLock::new(NextMessage::new(AgentToServer {
+            flags: AgentToServerFlags::RequestInstanceUid as u64,
+            ..AgentToServer::default()
+        })));
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_message(MessageData::default());
+        callbacks.expect_on_error().never();
+
+        let res = process_message(
+            server_to_agent,
+            &callbacks,
+            &ClientSyncedState::default(),
+            capabilities!(),
+            next_message.clone(),
+            &Notifier::new("test".to_string()).0,
+            &instance_uid,
+        );
+
+        assert_matches!(res.unwrap_err(), ProcessError::InvalidInstanceUid(_));
+        // The instance uid is still requested
+        assert_eq!(instance_uid.get(), current_instance_uid);
+        assert_eq!(
+            next_message.write().unwrap().pop().flags,
+            AgentToServerFlags::RequestInstanceUid as u64
+        );
+    }
+
+    #[test]
+    /// Expected to not change the instance uid if [`AgentIdentification`] is not present in [`ServerToAgent`] message
     ///
     fn receive_no_agent_identification() {
-        let agent_uid: Vec<u8> = "some_uid".into();
+        let current_instance_uid = InstanceUid::create();
+        let instance_uid = SharedInstanceUid::from(current_instance_uid.clone());
 
         let server_to_agent = ServerToAgent::default();
         let mut callbacks = MockCallbacksMockall::new();
         let synced_state = ClientSyncedState::default();
         let capabilities = capabilities!();
         let custom_capabilities = CustomCapabilities::default();
-        let next_message = Arc::new(RwLock::new(NextMessage::new(AgentToServer {
-            instance_uid: agent_uid.clone(),
-            ..AgentToServer::default()
-        })));
+        let next_message = Arc::new(RwLock::new(NextMessage::default()));
 
         callbacks.should_not_on_command(); // I expect on_command to NOT be called
 
@@ -379,10 +572,11 @@ mod tests {
             &synced_state,
             capabilities,
             next_message.clone(),
+            &Notifier::new("test".to_string()).0,
+            &instance_uid,
         );
 
-        let expected_message = next_message.write().unwrap().pop();
-        assert_eq!(expected_message.instance_uid, agent_uid);
+        assert_eq!(instance_uid.get(), current_instance_uid);
 
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), ProcessResult::Synced);
@@ -407,19 +601,274 @@ mod tests {
         let next_message = Arc::new(RwLock::new(NextMessage::default()));
 
         callbacks.should_not_on_command(); // I expect on_command to NOT be called
+        callbacks.should_on_error(err_response.clone());
 
         let msg_data = message_data(&server_to_agent, capabilities, Some(custom_capabilities));
         callbacks.should_on_message(msg_data);
 
-        let _res = process_message(
+        let res = process_message(
             server_to_agent,
             &callbacks,
             &synced_state,
             capabilities,
             next_message.clone(),
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
         );
 
         assert!(logs_contain(&format!("{err_response:?}")));
+        assert_eq!(res.unwrap(), ProcessResult::Synced);
+    }
+
+    #[test]
+    fn receive_unavailable_error() {
+        let err_response = ServerErrorResponse {
+            r#type: ServerErrorResponseType::Unavailable as i32,
+            details: Some(Details::RetryInfo(RetryInfo {
+                retry_after_nanoseconds: 1_000_000_000,
+            })),
+            ..ServerErrorResponse::default()
+        };
+        let server_to_agent = ServerToAgent {
+            error_response: Some(err_response.clone()),
+            flags: ServerToAgentFlags::ReportFullState as u64,
+            ..ServerToAgent::default()
+        };
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_error(err_response);
+        callbacks.should_on_message(MessageData::default());
+        callbacks.should_get_effective_config();
+
+        let res = process_message(
+            server_to_agent,
+            &callbacks,
+            &ClientSyncedState::default(),
+            capabilities!(),
+            Arc::new(RwLock::new(NextMessage::default())),
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
+        );
+
+        assert_eq!(
+            res.unwrap(),
+            ProcessResult::Unavailable(Some(Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn receive_client_certificate() {
+        let certificate = TlsCertificate {
+            public_key: b"certificate".to_vec(),
+            ..Default::default()
+        };
+        let server_to_agent = ServerToAgent {
+            capabilities: ServerCapabilities::AcceptsConnectionSettingsRequest as u64,
+            connection_settings: Some(ConnectionSettingsOffers {
+                opamp: Some(OpAmpConnectionSettings {
+                    certificate: Some(certificate.clone()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..ServerToAgent::default()
+        };
+        let synced_state = ClientSyncedState::default();
+        synced_state.set_certificate_requested().unwrap();
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_message(MessageData::default());
+        callbacks
+            .expect_on_client_certificate()
+            .once()
+            .withf(move |c| c == &certificate)
+            .return_const(());
+
+        let res = process_message(
+            server_to_agent.clone(),
+            &callbacks,
+            &synced_state,
+            capabilities!(),
+            Arc::new(RwLock::new(NextMessage::default())),
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
+        );
+        assert_eq!(res.unwrap(), ProcessResult::Synced);
+        assert_eq!(
+            synced_state.server_capabilities().unwrap(),
+            Some(crate::operation::capabilities::ServerCapabilities::new(
+                vec![ServerCapabilities::AcceptsConnectionSettingsRequest]
+            ))
+        );
+
+        // The certificate is only provided once per request and the server capabilities are kept
+        // if they are omitted.
+        callbacks.checkpoint();
+        callbacks.should_on_message(MessageData::default());
+        callbacks.expect_on_client_certificate().never();
+
+        let res = process_message(
+            ServerToAgent {
+                capabilities: ServerCapabilities::Unspecified as u64,
+                ..server_to_agent
+            },
+            &callbacks,
+            &synced_state,
+            capabilities!(),
+            Arc::new(RwLock::new(NextMessage::default())),
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
+        );
+        assert_eq!(res.unwrap(), ProcessResult::Synced);
+        assert_eq!(
+            synced_state.server_capabilities().unwrap(),
+            Some(crate::operation::capabilities::ServerCapabilities::new(
+                vec![ServerCapabilities::AcceptsConnectionSettingsRequest]
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unavailable_retry() {
+        let mut err = ServerErrorResponse::default();
+        assert_eq!(unavailable_retry(&err), None);
+
+        err.r#type = ServerErrorResponseType::Unavailable as i32;
+        assert_eq!(unavailable_retry(&err), Some(None));
+
+        err.details = Some(Details::RetryInfo(RetryInfo {
+            retry_after_nanoseconds: 500,
+        }));
+        assert_eq!(
+            unavailable_retry(&err),
+            Some(Some(Duration::from_nanos(500)))
+        );
+    }
+
+    #[test]
+    fn test_opamp_connection_settings() {
+        let settings = OpAmpConnectionSettings {
+            destination_endpoint: "http://localhost:4320/v1/opamp".to_string(),
+            ..Default::default()
+        };
+        let msg = ServerToAgent {
+            connection_settings: Some(ConnectionSettingsOffers {
+                opamp: Some(settings.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            opamp_connection_settings(
+                &msg,
+                capabilities!(AgentCapabilities::AcceptsOpAmpConnectionSettings)
+            ),
+            Some(settings)
+        );
+        assert_eq!(
+            opamp_connection_settings(&msg, Capabilities::default()),
+            None
+        );
+        assert_eq!(
+            opamp_connection_settings(
+                &ServerToAgent::default(),
+                capabilities!(AgentCapabilities::AcceptsOpAmpConnectionSettings)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_interval() {
+        let msg_with_interval = |heartbeat_interval_seconds| ServerToAgent {
+            connection_settings: Some(ConnectionSettingsOffers {
+                opamp: Some(OpAmpConnectionSettings {
+                    heartbeat_interval_seconds,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let capabilities = capabilities!(AgentCapabilities::ReportsHeartbeat);
+
+        assert_eq!(
+            heartbeat_interval(&msg_with_interval(10), capabilities),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            heartbeat_interval(&msg_with_interval(10), Capabilities::default()),
+            None
+        );
+        assert_eq!(
+            heartbeat_interval(&msg_with_interval(0), capabilities),
+            None
+        );
+        assert_eq!(
+            heartbeat_interval(&ServerToAgent::default(), capabilities),
+            None
+        );
+    }
+
+    #[test]
+    fn receive_packages_available() {
+        let packages_available = PackagesAvailable {
+            all_packages_hash: b"all".to_vec(),
+            ..Default::default()
+        };
+        let server_to_agent = ServerToAgent {
+            packages_available: Some(packages_available.clone()),
+            ..ServerToAgent::default()
+        };
+        let synced_state = ClientSyncedState::default();
+        let next_message = Arc::new(RwLock::new(NextMessage::default()));
+        let (pending_msg, _) = Notifier::new("test".to_string());
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_message(MessageData {
+            package_syncer: Some(PackageSyncer::new(
+                packages_available,
+                synced_state.clone(),
+                next_message.clone(),
+                pending_msg.clone(),
+                capabilities!(AgentCapabilities::AcceptsPackages),
+            )),
+            ..MessageData::default()
+        });
+
+        let res = process_message(
+            server_to_agent,
+            &callbacks,
+            &synced_state,
+            capabilities!(AgentCapabilities::AcceptsPackages),
+            next_message,
+            &pending_msg,
+            &SharedInstanceUid::from(InstanceUid::create()),
+        );
+
+        assert_eq!(res.unwrap(), ProcessResult::Synced);
+    }
+
+    #[test]
+    fn receive_packages_available_but_not_capable() {
+        let server_to_agent = ServerToAgent {
+            packages_available: Some(PackagesAvailable::default()),
+            ..ServerToAgent::default()
+        };
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_message(MessageData::default());
+
+        let res = process_message(
+            server_to_agent,
+            &callbacks,
+            &ClientSyncedState::default(),
+            capabilities!(),
+            Arc::new(RwLock::new(NextMessage::default())),
+            &Notifier::new("test".to_string()).0,
+            &SharedInstanceUid::from(InstanceUid::create()),
+        );
+
+        assert_eq!(res.unwrap(), ProcessResult::Synced);
     }
 
     #[test]
@@ -451,12 +900,13 @@ mod tests {
 
     #[test]
     fn test_message_data_with_agent_identification() {
+        let instance_uid = InstanceUid::create();
         let agent_identification = AgentIdentification {
-            new_instance_uid: "test-instance-uid".into(),
+            new_instance_uid: instance_uid.clone().into(),
         };
 
         let msg = ServerToAgent {
-            agent_identification: Some(agent_identification),
+            agent_identification: Some(agent_identification.clone()),
             ..Default::default()
         };
 
@@ -472,16 +922,19 @@ mod tests {
         assert_eq!(message_data.other_connection_settings, HashMap::default());
         assert_eq!(
             message_data.agent_identification,
-            Some(AgentIdentification {
-                new_instance_uid: "test-instance-uid".into()
-            })
+            Some(agent_identification)
         );
+        assert_eq!(message_data.instance_uid, Some(instance_uid));
     }
 
-    #[test]
-    fn test_message_data_with_agent_identification_and_empty_instance_uid() {
+    #[rstest]
+    #[case::empty("")]
+    #[case::not_an_uuid("test-instance-uid")]
+    fn test_message_data_with_agent_identification_and_invalid_instance_uid(
+        #[case] new_instance_uid: &str,
+    ) {
         let agent_identification = AgentIdentification {
-            new_instance_uid: "".into(),
+            new_instance_uid: new_instance_uid.into(),
         };
 
         let msg = ServerToAgent {
@@ -500,6 +953,7 @@ mod tests {
         assert_eq!(message_data.own_logs, None);
         assert_eq!(message_data.other_connection_settings, HashMap::default());
         assert_eq!(message_data.agent_identification, None);
+        assert_eq!(message_data.instance_uid, None);
     }
 
     #[test]
@@ -690,6 +1144,10 @@ mod tests {
             .set_package_statuses(expected_package_statuses.clone())
             .unwrap();
 
+        state
+            .set_available_components(available_components())
+            .unwrap();
+
         let flags = ServerToAgentFlags::ReportFullState as u64;
         let next_message: Arc<RwLock<NextMessage>> = Arc::new(RwLock::new(NextMessage::default()));
 
@@ -714,6 +1172,52 @@ mod tests {
         );
 
         assert_eq!(expected_package_statuses, message.package_statuses.unwrap());
+        // Only the hash of the available components is reported
+        assert_eq!(
+            message.available_components.unwrap(),
+            AvailableComponents {
+                hash: available_components().hash,
+                ..Default::default()
+            }
+        );
+        assert!(matches!(result, Ok(ProcessResult::NeedsResend)));
+    }
+
+    fn available_components() -> AvailableComponents {
+        AvailableComponents {
+            components: HashMap::from([(
+                "receiver".to_string(),
+                ComponentDetails {
+                    metadata: vec![KeyValue {
+                        key: "code.namespace".to_string(),
+                        value: Some(AnyValue {
+                            value: Some(Value::StringValue("otlp".to_string())),
+                        }),
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            hash: b"hash".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_rcv_flags_report_available_components() {
+        let mut callbacks_mock = MockCallbacksMockall::new();
+        let state = ClientSyncedState::default();
+        state
+            .set_available_components(available_components())
+            .unwrap();
+        let next_message: Arc<RwLock<NextMessage>> = Arc::new(RwLock::new(NextMessage::default()));
+
+        callbacks_mock.should_not_get_effective_config();
+
+        let flags = ServerToAgentFlags::ReportAvailableComponents as u64;
+        let result = rcv_flags(&state, flags, next_message.clone(), &callbacks_mock);
+
+        let message = next_message.write().unwrap().pop();
+        assert_eq!(message.available_components, Some(available_components()));
+        assert_eq!(message.health, None);
         assert!(matches!(result, Ok(ProcessResult::NeedsResend)));
     }
 
@@ -764,6 +1268,8 @@ mod tests {
                     &synced_state,
                     capabilities!(),
                     Arc::new(RwLock::new(NextMessage::default())),
+                    &Notifier::new("test".to_string()).0,
+                    &SharedInstanceUid::from(InstanceUid::create()),
                 )
                 .unwrap_or_else(|_| panic!("failed processing, case: {}", self.name));
 
@@ -1,5 +1,8 @@
+pub(crate) mod client;
 pub(crate) mod clientstate;
 pub(crate) mod compression;
+pub(crate) mod heartbeat;
 pub(crate) mod nextmessage;
+pub(crate) mod notifier;
 
 pub(crate) mod message_processor;
@@ -34,6 +34,12 @@ impl NextMessage {
         modifier(&mut self.message);
     }
 
+    /// Returns `true` if the current message holds a [`CustomMessage`](crate::opamp::proto::CustomMessage)
+    /// that has not been sent yet.
+    pub(crate) fn has_pending_custom_message(&self) -> bool {
+        self.message.custom_message.is_some()
+    }
+
     /// Increments the sequence number and returns the current message.
     ///
     /// # Returns
@@ -47,6 +53,42 @@ impl NextMessage {
         current_msg
     }
 
+    /// Puts back a popped message that could not be sent, so its changes are included in the next
+    /// one. The fields changed after it was popped are kept, as they are more recent.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The [`AgentToServer`] message returned by [`pop`](NextMessage::pop).
+    #[cfg(feature = "websocket")]
+    pub(crate) fn restore(&mut self, msg: AgentToServer) {
+        // The sequence number is reused unless a later message was popped in the meantime.
+        if self.message.sequence_num == msg.sequence_num {
+            self.message.sequence_num -= 1;
+        }
+        let current = &mut self.message;
+        current.agent_description = current.agent_description.take().or(msg.agent_description);
+        current.health = current.health.take().or(msg.health);
+        current.effective_config = current.effective_config.take().or(msg.effective_config);
+        current.remote_config_status = current
+            .remote_config_status
+            .take()
+            .or(msg.remote_config_status);
+        current.package_statuses = current.package_statuses.take().or(msg.package_statuses);
+        current.custom_capabilities = current
+            .custom_capabilities
+            .take()
+            .or(msg.custom_capabilities);
+        current.custom_message = current.custom_message.take().or(msg.custom_message);
+        current.connection_settings_request = current
+            .connection_settings_request
+            .take()
+            .or(msg.connection_settings_request);
+        current.available_components = current
+            .available_components
+            .take()
+            .or(msg.available_components);
+    }
+
     /// Resets the fields from the message that shouldn't be sent unless changed
     fn reset_message(&mut self) {
         self.message.agent_description = None;
@@ -55,5 +97,53 @@ impl NextMessage {
         self.message.remote_config_status = None;
         self.message.package_statuses = None;
         self.message.custom_capabilities = None;
+        self.message.custom_message = None;
+        self.message.connection_settings_request = None;
+        self.message.available_components = None;
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+    use crate::opamp::proto::{AgentDescription, ComponentHealth};
+
+    #[test]
+    fn restored_message_is_popped_again() {
+        let mut next_message = NextMessage::new(AgentToServer {
+            agent_description: Some(AgentDescription::default()),
+            health: Some(ComponentHealth::default()),
+            ..Default::default()
+        });
+
+        let msg = next_message.pop();
+        next_message.restore(msg.clone());
+
+        assert_eq!(next_message.pop(), msg);
+    }
+
+    #[test]
+    fn restore_keeps_the_fields_changed_after_pop() {
+        let mut next_message = NextMessage::new(AgentToServer {
+            agent_description: Some(AgentDescription::default()),
+            health: Some(ComponentHealth::default()),
+            ..Default::default()
+        });
+        let msg = next_message.pop();
+        let health = ComponentHealth {
+            healthy: true,
+            ..Default::default()
+        };
+        next_message.update(|msg| msg.health = Some(health.clone()));
+
+        next_message.restore(msg);
+        let restored = next_message.pop();
+
+        assert_eq!(restored.sequence_num, 1);
+        assert_eq!(
+            restored.agent_description,
+            Some(AgentDescription::default())
+        );
+        assert_eq!(restored.health, Some(health));
     }
 }
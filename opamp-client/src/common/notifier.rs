@@ -0,0 +1,65 @@
+use crossbeam::channel::{Receiver, Sender, TrySendError, bounded};
+use tracing::{trace, warn};
+
+/// Allows to notify a receiver based on channels.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    name: String,
+    sender: NotifierSender,
+}
+
+#[derive(Clone, Debug)]
+enum NotifierSender {
+    Channel(Sender<()>),
+    // Used by the async clients, which cannot block on channels.
+    #[cfg(feature = "async")]
+    Notify(std::sync::Arc<tokio::sync::Notify>),
+}
+
+impl Notifier {
+    pub fn new(name: String) -> (Self, Receiver<()>) {
+        let (sender, receiver) = bounded::<()>(1);
+        (
+            Self {
+                name,
+                sender: NotifierSender::Channel(sender),
+            },
+            receiver,
+        )
+    }
+
+    /// Returns a notifier whose notifications can be awaited. As with the channel based one, at
+    /// most one notification is kept until it is awaited.
+    #[cfg(feature = "async")]
+    pub fn new_async(name: String) -> (Self, std::sync::Arc<tokio::sync::Notify>) {
+        let notify = std::sync::Arc::new(tokio::sync::Notify::new());
+        (
+            Self {
+                name,
+                sender: NotifierSender::Notify(notify.clone()),
+            },
+            notify,
+        )
+    }
+
+    /// Notify the receiver. Prints a warning if the receiver is disconnected.
+    pub fn notify_or_warn(&self) {
+        match &self.sender {
+            NotifierSender::Channel(sender) => match sender.try_send(()) {
+                Ok(()) => {}
+                // if the channel is full, it means that there is already a notification pending to be read.
+                Err(TrySendError::Full(())) => {
+                    trace!(notifier_name = self.name, "channel full, already notified");
+                }
+                Err(TrySendError::Disconnected(())) => {
+                    warn!(
+                        notifier_name = self.name,
+                        "notification channel disconnected",
+                    );
+                }
+            },
+            #[cfg(feature = "async")]
+            NotifierSender::Notify(notify) => notify.notify_one(),
+        }
+    }
+}
@@ -8,6 +8,10 @@ pub enum ConnectionError {
     /// Error when connecting via HTTP client.
     #[error(transparent)]
     HTTPClientError(#[from] crate::http::HttpClientError),
+    /// Error when connecting via WebSocket client.
+    #[cfg(feature = "websocket")]
+    #[error(transparent)]
+    WsClientError(#[from] crate::ws::WsClientError),
 }
 
 /// Represents errors that can occur in the OpAMP not started client.
@@ -0,0 +1,90 @@
+//! # Asynchronous HTTP Client Module
+use std::future::Future;
+
+use http::Response;
+
+use super::http_client::{HttpClientError, HttpRequest};
+
+/// An asynchronous trait that defines the internal methods for HTTP clients, the counterpart of
+/// [`HttpClient`](super::http_client::HttpClient) for clients driven by an async runtime.
+pub trait AsyncHttpClient {
+    /// An asynchronous function that defines the `post` method for HTTP client.
+    fn post(
+        &self,
+        body: Vec<u8>,
+    ) -> impl Future<Output = Result<Response<Vec<u8>>, HttpClientError>> + Send;
+
+    /// Sends the provided [`HttpRequest`] to the server. The default implementation discards
+    /// everything but the body and calls [`post`](AsyncHttpClient::post). Implementations sending
+    /// the request headers should override it along with
+    /// [`supports_headers`](AsyncHttpClient::supports_headers).
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> impl Future<Output = Result<Response<Vec<u8>>, HttpClientError>> + Send {
+        self.post(request.into_body())
+    }
+
+    /// Returns whether [`send`](AsyncHttpClient::send) includes the request headers. Requests are
+    /// only compressed if it does, since the server cannot decode them without the
+    /// `Content-Encoding` header. Defaults to `false`.
+    fn supports_headers(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        http::http_client::tests::{ResponseParts, response_from_server_to_agent},
+        opamp::proto::{AgentToServer, ServerToAgent},
+    };
+
+    type HttpResponse = Result<Response<Vec<u8>>, HttpClientError>;
+
+    /// An [`AsyncHttpClient`] answering the queued responses, or an empty message once there are
+    /// no more. Clones share the responses and the recorded messages.
+    #[derive(Debug, Default, Clone)]
+    pub(crate) struct FakeAsyncHttpClient {
+        responses: Arc<Mutex<VecDeque<HttpResponse>>>,
+        received: Arc<Mutex<Vec<AgentToServer>>>,
+    }
+
+    impl FakeAsyncHttpClient {
+        pub(crate) fn push_response(&self, response: HttpResponse) {
+            self.responses.lock().unwrap().push_back(response);
+        }
+
+        /// Returns the messages received so far.
+        pub(crate) fn received(&self) -> Vec<AgentToServer> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    impl AsyncHttpClient for FakeAsyncHttpClient {
+        async fn post(&self, body: Vec<u8>) -> Result<Response<Vec<u8>>, HttpClientError> {
+            self.received
+                .lock()
+                .unwrap()
+                .push(AgentToServer::decode(body.as_slice()).unwrap());
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| {
+                    Ok(response_from_server_to_agent(
+                        &ServerToAgent::default(),
+                        ResponseParts::default(),
+                    ))
+                })
+        }
+    }
+}
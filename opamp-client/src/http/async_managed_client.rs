@@ -0,0 +1,685 @@
+//! Asynchronous implementation of the managed HTTP client, whose polling loop is a task driven by
+//! the async runtime instead of a dedicated thread.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::Notify,
+    task::JoinHandle,
+    time::{Instant, sleep, sleep_until},
+};
+use tracing::{Instrument, debug, error, info_span, trace, warn};
+
+use crate::{
+    Client, ClientResult, NotStartedClientResult, StartedClientError, StartedClientResult,
+    common::{client::ClientCore, heartbeat::SharedHeartbeatInterval, notifier::Notifier},
+    opamp::proto::{
+        AgentDescription, AvailableComponents, ComponentHealth, CustomCapabilities, CustomMessage,
+        PackageStatuses, RemoteConfigStatus,
+    },
+    operation::{
+        callbacks::Callbacks, capabilities::ServerCapabilities,
+        connection_settings::ConnectionSettingsHandler, instance_uid::SharedInstanceUid,
+        remote_config::RemoteConfigHandler, settings::StartSettings, state_store::StateStore,
+    },
+};
+
+use super::{
+    async_http_client::AsyncHttpClient,
+    backoff::{Backoff, BackoffPolicy},
+    client::handle_response,
+    managed_client::{
+        DEFAULT_MINIMUM_DURATION_BETWEEN_POLL, DEFAULT_POLLING_INTERVAL, PollIntervals,
+        polling_intervals,
+    },
+    sender::{AsyncHttpSender, RequestCompression},
+};
+
+/// The asynchronous counterpart of [`NotStartedHttpClient`](super::NotStartedHttpClient), using an
+/// [`AsyncHttpClient`] to reach the server.
+///
+/// Unlike the synchronous client, it has no `with_connection_settings_factory`: the OpAMP
+/// connection settings offered by the server are never applied nor persisted, and
+/// [`on_opamp_connection_settings`](Callbacks::on_opamp_connection_settings) is never called. The
+/// agent should not set the `AcceptsOpAmpConnectionSettings` capability when using it.
+pub struct NotStartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    opamp_client: AsyncOpAMPHttpClient<CB, HC>,
+    poll_interval: Duration,
+    min_duration_between_poll: Duration,
+    has_pending_msg: Arc<Notify>,
+    perform_startup_check: bool,
+    backoff_policy: BackoffPolicy,
+}
+
+/// A [`Client`] that frequently polls for OpAMP remote updates in a task spawned on the async
+/// runtime. It is stopped through [`stop`](StartedAsyncHttpClient::stop), which sends the
+/// `AgentDisconnect` message. If it is dropped instead, the task is signaled to stop and sends the
+/// `AgentDisconnect` message in the background.
+pub struct StartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    // handle for the spawned polling task
+    handle: JoinHandle<()>,
+
+    // stop the polling task
+    shutdown_notifier: Notifier,
+
+    opamp_client: Arc<AsyncOpAMPHttpClient<CB, HC>>,
+}
+
+// Sends the messages through the async sender and processes the responses.
+struct AsyncOpAMPHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync,
+    HC: AsyncHttpClient + Send + Sync,
+{
+    sender: AsyncHttpSender<HC>,
+    core: ClientCore<CB>,
+}
+
+impl<CB, HC> AsyncOpAMPHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync,
+    HC: AsyncHttpClient + Send + Sync,
+{
+    // Sends the next AgentToServer message and processes the received ServerToAgent message.
+    async fn poll(&self) -> ClientResult<()> {
+        let msg = self.core.pop_message()?;
+        trace!("Send payload: {:?}", msg);
+        let result =
+            handle_response(&self.core, self.sender.send(msg).await).and_then(|server_to_agent| {
+                let _span = info_span!("process_message").entered();
+                self.core.process(server_to_agent)
+            });
+        // The remote config whose `Applying` status was sent can be applied now.
+        self.core.apply_sent_remote_config()?;
+        result
+    }
+
+    // By OpAMP protocol, AgentDisconnect must be sent in the last message.
+    async fn disconnect(&self) {
+        let instance_uid = &self.core.instance_uid;
+        match self.core.pop_disconnect_message() {
+            Ok(msg) => {
+                let _ = self.sender.send(msg).await.inspect_err(|err| {
+                    error!(%err, %instance_uid, "sending disconnect OpAMP message");
+                });
+
+                debug!(%instance_uid, "AsyncOpAMPHttpClient disconnected from server");
+            }
+            Err(err) => {
+                error!(%err, %instance_uid, "assembling disconnect OpAMP message");
+            }
+        }
+    }
+}
+
+impl<CB, HC> NotStartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    /// Creates a new instance of [`NotStartedAsyncHttpClient`] with provided parameters.
+    pub fn new(
+        http_client: HC,
+        callbacks: CB,
+        start_settings: StartSettings,
+    ) -> NotStartedClientResult<Self> {
+        let (pending_msg_notifier, has_pending_msg) =
+            Notifier::new_async("pending_msg".to_string());
+
+        let core = ClientCore::new(callbacks, start_settings, pending_msg_notifier)?;
+        let opamp_client = AsyncOpAMPHttpClient {
+            sender: AsyncHttpSender::new(http_client, core.instance_uid.clone()),
+            core,
+        };
+
+        Ok(Self {
+            opamp_client,
+            poll_interval: DEFAULT_POLLING_INTERVAL,
+            min_duration_between_poll: DEFAULT_MINIMUM_DURATION_BETWEEN_POLL,
+            has_pending_msg,
+            perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
+        })
+    }
+
+    /// Returns a new instance with the specified interval for polling. If the interval is smaller
+    /// than the minimum, a warning message will be printed and the minimum value will be used.
+    ///
+    /// If the agent has the `ReportsHeartbeat` capability, the heartbeat interval offered by the
+    /// server in the OpAMP connection settings replaces this interval once received.
+    pub fn with_interval(self, interval: Duration) -> Self {
+        let (poll_interval, min_duration_between_poll) = polling_intervals(interval);
+        Self {
+            poll_interval,
+            min_duration_between_poll,
+            ..self
+        }
+    }
+
+    /// Returns a new instance with the startup check disabled. The client's start method will not fail if
+    /// there is an error sending the first message. As a result, the client will keep trying to send messages
+    /// as scheduled according the polling interval or as requested due any status change.
+    pub fn with_startup_check_disabled(self) -> Self {
+        Self {
+            perform_startup_check: false,
+            ..self
+        }
+    }
+
+    /// Returns a new instance which compresses the requests sent to the server as defined by the
+    /// provided [`RequestCompression`]. Requests are sent uncompressed if the [`AsyncHttpClient`] doesn't
+    /// [support headers](AsyncHttpClient::supports_headers), as the `Content-Encoding` header is required.
+    pub fn with_request_compression(mut self, compression: RequestCompression) -> Self {
+        self.opamp_client
+            .sender
+            .set_request_compression(compression);
+        self
+    }
+
+    /// Returns a new instance with the provided [`BackoffPolicy`], used to delay the next poll when
+    /// the server is unavailable or cannot be reached.
+    pub fn with_backoff_policy(self, backoff_policy: BackoffPolicy) -> Self {
+        Self {
+            backoff_policy,
+            ..self
+        }
+    }
+
+    /// Returns a new instance which persists the client state in the provided [`StateStore`]. The
+    /// stored state is restored right away: the stored instance uid replaces the one in the
+    /// [`StartSettings`] and the stored `RemoteConfigStatus` is reported in the first message.
+    pub fn with_state_store<S>(mut self, store: S) -> Self
+    where
+        S: StateStore + 'static,
+    {
+        let _ = self
+            .opamp_client
+            .core
+            .set_state_store(store)
+            .inspect_err(|err| error!(%err, "restoring the persisted client state"));
+        self
+    }
+
+    /// Returns a new instance which offers the telemetry and other connection settings sent by the
+    /// server to the provided [`ConnectionSettingsHandler`], instead of surfacing them through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`connection_settings`](crate::operation::connection_settings) for details.
+    pub fn with_connection_settings_handler<H>(mut self, handler: H) -> Self
+    where
+        H: ConnectionSettingsHandler + 'static,
+    {
+        self.opamp_client
+            .core
+            .set_connection_settings_handler(handler);
+        self
+    }
+
+    /// Returns a new instance which applies the remote config offered by the server through the
+    /// provided [`RemoteConfigHandler`], reporting its status, instead of surfacing it through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`remote_config`](crate::operation::remote_config) for details.
+    pub fn with_remote_config_handler<H>(mut self, handler: H) -> Self
+    where
+        H: RemoteConfigHandler + 'static,
+    {
+        self.opamp_client.core.set_remote_config_handler(handler);
+        self
+    }
+
+    /// Sends the first message and spawns the polling task in the current runtime. It fails if
+    /// the first message cannot be sent, unless the startup check is disabled.
+    pub async fn start(self) -> NotStartedClientResult<StartedAsyncHttpClient<CB, HC>> {
+        let opamp_client = Arc::new(self.opamp_client);
+        let instance_uid = opamp_client.core.instance_uid.clone();
+        let span = info_span!("opamp", %instance_uid);
+
+        debug!(parent: &span, "sending first AgentToServer message");
+        let first_poll = match opamp_client.poll().instrument(span.clone()).await {
+            Err(err) if self.perform_startup_check => return Err(err.into()),
+            result => result.inspect_err(
+                |err| error!(parent: &span, %err, "error sending first AgentToServer message"),
+            ),
+        };
+
+        let (shutdown_notifier, exit) = Notifier::new_async("shut_down".to_string());
+
+        let handle = tokio::spawn(poll_loop(
+            opamp_client.clone(),
+            PollSchedule {
+                intervals: PollIntervals {
+                    poll_interval: self.poll_interval,
+                    min_duration_between_poll: self.min_duration_between_poll,
+                    heartbeat_interval: None,
+                },
+                heartbeat_interval: opamp_client.core.heartbeat_interval.clone(),
+                backoff: Backoff::new(self.backoff_policy),
+            },
+            first_poll,
+            self.has_pending_msg,
+            exit,
+            instance_uid,
+        ));
+
+        Ok(StartedAsyncHttpClient {
+            handle,
+            shutdown_notifier,
+            opamp_client,
+        })
+    }
+}
+
+// Defines when the next poll is performed.
+struct PollSchedule {
+    intervals: PollIntervals,
+    // Heartbeat interval requested by the server, which replaces the polling interval.
+    heartbeat_interval: SharedHeartbeatInterval,
+    backoff: Backoff,
+}
+
+impl PollSchedule {
+    // Returns the instant of the next scheduled poll given the result of the last one. The poll is
+    // delayed according to the backoff if it failed because the server is unavailable or cannot be
+    // reached.
+    fn next_poll(&mut self, result: &ClientResult<()>) -> Instant {
+        self.intervals.update_heartbeat(&self.heartbeat_interval);
+        let delay = match self.backoff.delay_after(result) {
+            Some(delay) => {
+                warn!(?delay, "server unavailable, delaying next poll");
+                delay
+            }
+            None => self.intervals.poll_interval,
+        };
+        // A huge interval requested by the server must not overflow the instant
+        let now = Instant::now();
+        now.checked_add(delay)
+            .unwrap_or_else(|| now + self.backoff.max_interval())
+    }
+}
+
+async fn poll_loop<CB, HC>(
+    opamp_client: Arc<AsyncOpAMPHttpClient<CB, HC>>,
+    mut schedule: PollSchedule,
+    first_poll: ClientResult<()>,
+    has_pending_msg: Arc<Notify>,
+    exit: Arc<Notify>,
+    instance_uid: SharedInstanceUid,
+) where
+    CB: Callbacks + Send + Sync,
+    HC: AsyncHttpClient + Send + Sync,
+{
+    let mut next_poll = schedule.next_poll(&first_poll);
+    loop {
+        // Each opamp cycle is traced in a separate span, propagating the current instance_uid
+        let span = info_span!("opamp", %instance_uid);
+        tokio::select! {
+            biased;
+            _ = exit.notified() => {
+                debug!(parent: &span, "gracefully shutting down the polling task");
+                break;
+            }
+            // Pending messages wait for the backoff delay too, they will be included in the next
+            // poll anyway.
+            _ = has_pending_msg.notified(), if !schedule.backoff.is_active() => {
+                let result = async {
+                    debug!("sending requested AgentToServer message");
+                    opamp_client
+                        .poll()
+                        .await
+                        .inspect_err(|err| error!(%err, "error while polling message"))
+                }
+                .instrument(span.clone())
+                .await;
+                next_poll = span.in_scope(|| schedule.next_poll(&result));
+
+                // wait for the minimum duration between polls
+                sleep(schedule.intervals.min_duration_between_poll).await;
+            }
+            _ = sleep_until(next_poll) => {
+                if schedule.backoff.is_active() {
+                    // The retry includes the changes of any message requested meanwhile.
+                    std::pin::pin!(has_pending_msg.notified()).enable();
+                }
+                let result = async {
+                    debug!("sending scheduled status report AgentToServer message");
+                    opamp_client
+                        .poll()
+                        .await
+                        .inspect_err(|err| error!(%err, "error while polling message"))
+                }
+                .instrument(span.clone())
+                .await;
+                next_poll = span.in_scope(|| schedule.next_poll(&result));
+            }
+        }
+    }
+    opamp_client.disconnect().await;
+    debug!("polling task stopped");
+}
+
+impl<CB, HC> StartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    /// Stops the polling task, waiting for it to finish, and sends the `AgentDisconnect` message.
+    ///
+    /// Until then, the [`Callbacks`], the [`ConnectionSettingsHandler`] and the
+    /// [`RemoteConfigHandler`] are called synchronously from the polling task, blocking the runtime worker thread running it. Handlers which perform
+    /// long or blocking operations should hand them over, for instance through
+    /// [`tokio::task::spawn_blocking`].
+    pub async fn stop(mut self) -> StartedClientResult<()> {
+        self.shutdown_notifier.notify_or_warn();
+        // The task sends the `AgentDisconnect` message before finishing.
+        (&mut self.handle)
+            .await
+            .map_err(|_| StartedClientError::JoinError)
+    }
+}
+
+impl<CB, HC> Drop for StartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        // The task is not awaited, it disconnects in the background unless it already finished.
+        if !self.handle.is_finished() {
+            self.shutdown_notifier.notify_or_warn();
+        }
+    }
+}
+
+impl<CB, HC> Client for StartedAsyncHttpClient<CB, HC>
+where
+    CB: Callbacks + Send + Sync + 'static,
+    HC: AsyncHttpClient + Send + Sync + 'static,
+{
+    fn set_agent_description(&self, description: AgentDescription) -> ClientResult<()> {
+        self.opamp_client.core.set_agent_description(description)
+    }
+
+    fn get_agent_description(&self) -> ClientResult<AgentDescription> {
+        self.opamp_client.core.get_agent_description()
+    }
+
+    fn set_health(&self, health: ComponentHealth) -> ClientResult<()> {
+        self.opamp_client.core.set_health(health)
+    }
+
+    fn set_component_health(&self, path: &[&str], health: ComponentHealth) -> ClientResult<()> {
+        self.opamp_client.core.set_component_health(path, health)
+    }
+
+    fn update_effective_config(&self) -> ClientResult<()> {
+        self.opamp_client.core.update_effective_config()
+    }
+
+    fn set_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()> {
+        self.opamp_client.core.set_remote_config_status(status)
+    }
+
+    fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()> {
+        self.opamp_client
+            .core
+            .set_custom_capabilities(custom_capabilities)
+    }
+
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()> {
+        self.opamp_client.core.set_package_statuses(statuses)
+    }
+
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()> {
+        self.opamp_client.core.send_custom_message(message)
+    }
+
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()> {
+        self.opamp_client.core.request_client_certificate(csr)
+    }
+
+    fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>> {
+        self.opamp_client.core.server_capabilities()
+    }
+
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()> {
+        self.opamp_client.core.set_available_components(components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        ClientError, NotStartedClientError, capabilities,
+        http::{
+            HttpClientError,
+            async_http_client::tests::FakeAsyncHttpClient,
+            http_client::tests::{ResponseParts, response_from_server_to_agent},
+        },
+        opamp::proto::{
+            AgentCapabilities, ConnectionSettingsOffers, OpAmpConnectionSettings, ServerToAgent,
+        },
+        operation::callbacks::tests::MockCallbacksMockall,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn next_poll_does_not_overflow() {
+        let policy = BackoffPolicy::default();
+        let mut schedule = PollSchedule {
+            intervals: PollIntervals {
+                poll_interval: Duration::MAX,
+                min_duration_between_poll: Duration::ZERO,
+                heartbeat_interval: None,
+            },
+            heartbeat_interval: SharedHeartbeatInterval::default(),
+            backoff: Backoff::new(policy),
+        };
+        let max_poll = Instant::now() + Backoff::new(policy).max_interval();
+
+        let unavailable = Err(ClientError::ServerUnavailable(Some(Duration::MAX)));
+        assert!(schedule.next_poll(&unavailable) <= max_poll);
+        assert_eq!(schedule.next_poll(&Ok(())), max_poll);
+    }
+
+    fn reports_health() -> StartSettings {
+        StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsHealth),
+            ..Default::default()
+        }
+    }
+
+    fn connected_callbacks() -> MockCallbacksMockall {
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.expect_on_connect().return_const(());
+        callbacks.expect_on_message().return_const(());
+        callbacks
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_polls_and_stop_sends_disconnect() {
+        let http_client = FakeAsyncHttpClient::default();
+        let client = NotStartedAsyncHttpClient::new(
+            http_client.clone(),
+            connected_callbacks(),
+            StartSettings::default(),
+        )
+        .unwrap()
+        .with_interval(Duration::from_secs(10));
+
+        let client = client.start().await.unwrap();
+        assert_eq!(http_client.received().len(), 1);
+
+        // scheduled polls
+        sleep(Duration::from_secs(25)).await;
+        assert_eq!(http_client.received().len(), 3);
+
+        client.stop().await.unwrap();
+        let received = http_client.received();
+        assert_eq!(received.len(), 4);
+        assert!(received[3].agent_disconnect.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_stops_polling_and_sends_disconnect() {
+        let http_client = FakeAsyncHttpClient::default();
+        let client = NotStartedAsyncHttpClient::new(
+            http_client.clone(),
+            connected_callbacks(),
+            StartSettings::default(),
+        )
+        .unwrap()
+        .with_interval(Duration::from_secs(10))
+        .start()
+        .await
+        .unwrap();
+
+        drop(client);
+        sleep(Duration::from_secs(1)).await;
+        let received = http_client.received();
+        assert_eq!(received.len(), 2);
+        assert!(received[1].agent_disconnect.is_some());
+
+        // No more polls are performed
+        sleep(Duration::from_secs(30)).await;
+        assert_eq!(http_client.received().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_interval_replaces_poll_interval() {
+        let http_client = FakeAsyncHttpClient::default();
+        http_client.push_response(Ok(response_from_server_to_agent(
+            &ServerToAgent {
+                connection_settings: Some(ConnectionSettingsOffers {
+                    opamp: Some(OpAmpConnectionSettings {
+                        heartbeat_interval_seconds: 60,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ResponseParts::default(),
+        )));
+        let client = NotStartedAsyncHttpClient::new(
+            http_client.clone(),
+            connected_callbacks(),
+            StartSettings {
+                capabilities: capabilities!(AgentCapabilities::ReportsHeartbeat),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .with_interval(Duration::from_secs(10))
+        .start()
+        .await
+        .unwrap();
+        assert_eq!(http_client.received().len(), 1);
+
+        // The poll interval is not used anymore
+        sleep(Duration::from_secs(55)).await;
+        assert_eq!(http_client.received().len(), 1);
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(http_client.received().len(), 2);
+
+        client.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_fails_if_server_is_not_reachable() {
+        let http_client = FakeAsyncHttpClient::default();
+        http_client.push_response(Err(HttpClientError::TransportError("error".to_string())));
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_connect_failed();
+
+        let result = NotStartedAsyncHttpClient::new(
+            http_client.clone(),
+            callbacks,
+            StartSettings::default(),
+        )
+        .unwrap()
+        .start()
+        .await;
+
+        assert_matches!(result.err(), Some(NotStartedClientError::ClientError(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_changes_are_sent() {
+        let http_client = FakeAsyncHttpClient::default();
+        let client = NotStartedAsyncHttpClient::new(
+            http_client.clone(),
+            connected_callbacks(),
+            reports_health(),
+        )
+        .unwrap()
+        .start()
+        .await
+        .unwrap();
+
+        let health = ComponentHealth {
+            healthy: true,
+            ..Default::default()
+        };
+        client.set_health(health.clone()).unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        let received = http_client.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].health, Some(health));
+
+        client.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unavailable_server_delays_next_poll() {
+        let http_client = FakeAsyncHttpClient::default();
+        http_client.push_response(Ok(response_from_server_to_agent(
+            &ServerToAgent::default(),
+            ResponseParts {
+                status: http::StatusCode::SERVICE_UNAVAILABLE,
+                ..Default::default()
+            },
+        )));
+        let mut callbacks = connected_callbacks();
+        callbacks.expect_on_connect_failed().return_const(());
+
+        let client =
+            NotStartedAsyncHttpClient::new(http_client.clone(), callbacks, reports_health())
+                .unwrap()
+                .with_startup_check_disabled()
+                .with_backoff_policy(
+                    BackoffPolicy::default()
+                        .with_initial_interval(Duration::from_secs(2))
+                        .with_jitter(0.0),
+                )
+                .start()
+                .await
+                .unwrap();
+
+        // Status changes wait for the backoff delay
+        client
+            .set_health(ComponentHealth {
+                healthy: true,
+                ..Default::default()
+            })
+            .unwrap();
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(http_client.received().len(), 1);
+
+        sleep(Duration::from_secs(2)).await;
+        let received = http_client.received();
+        assert_eq!(received.len(), 2);
+        assert!(received[1].health.is_some());
+
+        client.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Exponential backoff applied by the managed HTTP client when the server cannot process requests.
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::{Duration, Instant},
+};
+
+use crate::{ClientError, ClientResult};
+
+// Default backoff policy values
+const DEFAULT_INITIAL_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_JITTER: f64 = 0.5;
+// Limits the exponent so the computed interval never overflows
+const MAX_EXPONENT: u32 = 32;
+
+/// Defines how the managed HTTP client delays the next poll when the server is unavailable
+/// (`ServerErrorResponseType::Unavailable`) or cannot be reached.
+///
+/// The delay grows exponentially with each consecutive failure up to `max_interval`, and it is
+/// randomized by the `jitter` factor, so a fleet of agents doesn't retry at the same time. When the
+/// server provides a `RetryInfo` or a `Retry-After` header, its delay is used instead, up to
+/// `max_interval`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    initial_interval: Duration,
+    max_interval: Duration,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial_interval: DEFAULT_INITIAL_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            multiplier: DEFAULT_MULTIPLIER,
+            jitter: DEFAULT_JITTER,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Returns a new policy with the provided delay after the first failure.
+    pub fn with_initial_interval(self, initial_interval: Duration) -> Self {
+        Self {
+            initial_interval,
+            ..self
+        }
+    }
+
+    /// Returns a new policy with the provided maximum delay between retries.
+    pub fn with_max_interval(self, max_interval: Duration) -> Self {
+        Self {
+            max_interval,
+            ..self
+        }
+    }
+
+    /// Returns a new policy with the provided factor the delay is multiplied by after each failure.
+    /// Values smaller than 1 are treated as 1.
+    pub fn with_multiplier(self, multiplier: f64) -> Self {
+        Self {
+            multiplier: multiplier.max(1.0),
+            ..self
+        }
+    }
+
+    /// Returns a new policy with the provided randomization factor, the delay will be randomly
+    /// chosen in the range `[delay * (1 - jitter), delay * (1 + jitter)]`. The value is clamped to `[0, 1]`.
+    pub fn with_jitter(self, jitter: f64) -> Self {
+        Self {
+            jitter: jitter.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    // Returns the delay corresponding to the provided number of consecutive failures (starting at 0).
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.min(MAX_EXPONENT) as i32;
+        let interval = (self.initial_interval.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_interval.as_secs_f64());
+        let jitter = interval * self.jitter * (2.0 * random_unit() - 1.0);
+        Duration::from_secs_f64((interval + jitter).max(0.0))
+    }
+}
+
+// Returns a pseudo-random value in the range [0, 1].
+fn random_unit() -> f64 {
+    RandomState::new().hash_one(Instant::now()) as f64 / u64::MAX as f64
+}
+
+/// Keeps track of consecutive failed polls to compute the delay before the next one.
+#[derive(Debug)]
+pub(super) struct Backoff {
+    policy: BackoffPolicy,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub(super) fn new(policy: BackoffPolicy) -> Self {
+        Self { policy, attempt: 0 }
+    }
+
+    /// Returns the maximum delay between retries.
+    #[cfg(feature = "async")]
+    pub(super) fn max_interval(&self) -> Duration {
+        self.policy.max_interval
+    }
+
+    /// Returns `true` if the last poll failed with a retryable error.
+    pub(super) fn is_active(&self) -> bool {
+        self.attempt > 0
+    }
+
+    /// Returns the delay to wait before the next poll given the result of the last one, or
+    /// [`None`] if the regular schedule applies.
+    pub(super) fn delay_after(&mut self, result: &ClientResult<()>) -> Option<Duration> {
+        let delay = match result {
+            // The server provided delay is capped, so it cannot stall the client indefinitely
+            Err(ClientError::ServerUnavailable(Some(retry_after))) => {
+                (*retry_after).min(self.policy.max_interval)
+            }
+            Err(ClientError::ServerUnavailable(None) | ClientError::ConnectFailedCallback(_)) => {
+                self.policy.delay(self.attempt)
+            }
+            // The server was reached or the error is not related to its availability
+            Ok(()) | Err(_) => {
+                self.attempt = 0;
+                return None;
+            }
+        };
+        self.attempt = self.attempt.saturating_add(1);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_exponentially_up_to_max_interval() {
+        let policy = BackoffPolicy::default()
+            .with_initial_interval(Duration::from_secs(1))
+            .with_max_interval(Duration::from_secs(10))
+            .with_jitter(0.0);
+
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(4), Duration::from_secs(10));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn delay_is_randomized_within_jitter() {
+        let policy = BackoffPolicy::default()
+            .with_initial_interval(Duration::from_secs(10))
+            .with_jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay(0);
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(15));
+        }
+    }
+
+    #[test]
+    fn backoff_after_poll_results() {
+        let policy = BackoffPolicy::default()
+            .with_initial_interval(Duration::from_secs(1))
+            .with_jitter(0.0);
+        let mut backoff = Backoff::new(policy);
+
+        assert_eq!(backoff.delay_after(&Ok(())), None);
+        assert!(!backoff.is_active());
+
+        // Errors not related to the server availability don't back off
+        assert_eq!(backoff.delay_after(&Err(ClientError::PoisonError)), None);
+        assert!(!backoff.is_active());
+
+        let connect_failed = Err(ClientError::ConnectFailedCallback("error".to_string()));
+        assert_eq!(
+            backoff.delay_after(&connect_failed),
+            Some(Duration::from_secs(1))
+        );
+        assert!(backoff.is_active());
+        assert_eq!(
+            backoff.delay_after(&Err(ClientError::ServerUnavailable(None))),
+            Some(Duration::from_secs(2))
+        );
+        // The delay provided by the server has precedence
+        assert_eq!(
+            backoff.delay_after(&Err(ClientError::ServerUnavailable(Some(
+                Duration::from_millis(100)
+            )))),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            backoff.delay_after(&connect_failed),
+            Some(Duration::from_secs(8))
+        );
+
+        // A successful poll resets the backoff
+        assert_eq!(backoff.delay_after(&Ok(())), None);
+        assert!(!backoff.is_active());
+        assert_eq!(
+            backoff.delay_after(&connect_failed),
+            Some(Duration::from_secs(1))
+        );
+
+        // The delay provided by the server is capped to the maximum interval
+        assert_eq!(
+            backoff.delay_after(&Err(ClientError::ServerUnavailable(Some(Duration::MAX)))),
+            Some(DEFAULT_MAX_INTERVAL)
+        );
+
+        // So does an error not related to the server availability
+        assert_eq!(backoff.delay_after(&Err(ClientError::PoisonError)), None);
+        assert!(!backoff.is_active());
+        assert_eq!(
+            backoff.delay_after(&connect_failed),
+            Some(Duration::from_secs(1))
+        );
+    }
+}
@@ -1,23 +1,30 @@
 //! # Synchronous HTTP Client implementation for the OpAMP trait.
 
-use std::sync::{Arc, RwLock};
-
 use crate::{
-    Client, ClientError, ClientResult,
-    common::{
-        clientstate::ClientSyncedState, message_processor::ProcessResult, nextmessage::NextMessage,
+    Client, ClientError, ClientResult, OpampSenderResult,
+    common::{client::ClientCore, heartbeat::SharedHeartbeatInterval, notifier::Notifier},
+    opamp::proto::{
+        AgentDescription, AvailableComponents, ComponentHealth, CustomCapabilities, CustomMessage,
+        OpAmpConnectionSettings, PackageStatuses, RemoteConfigStatus, ServerToAgent,
+    },
+    operation::{
+        callbacks::Callbacks, capabilities::ServerCapabilities,
+        connection_settings::ConnectionSettingsHandler, instance_uid::SharedInstanceUid,
+        remote_config::RemoteConfigHandler, settings::StartSettings, state_store::StateStore,
     },
-    opamp::proto::{AgentCapabilities, AgentDisconnect, AgentToServer, CustomCapabilities},
-    operation::{callbacks::Callbacks, capabilities::Capabilities, settings::StartSettings},
 };
 
-use super::{http_client::HttpClient, managed_client::Notifier, sender::HttpSender};
-use tracing::{debug, error, info_span, trace};
+use super::{
+    HttpClientError,
+    http_client::{HttpClient, HttpClientFactory},
+    sender::{HttpSender, RequestCompression},
+};
+use tracing::{debug, error, info_span, trace, warn};
 
 /// A trait for clients that do not manage their own polling.
 pub trait UnManagedClient: Client {
     /// Executes a complete roundtrip of the opamp protocol.
-    /// Sends a [`AgentToServer`] message, receives a [`ServerToAgent`](crate::opamp::proto::ServerToAgent) message, and processes it.
+    /// Sends a [`AgentToServer`](crate::opamp::proto::AgentToServer) message, receives a [`ServerToAgent`](crate::opamp::proto::ServerToAgent) message, and processes it.
     fn poll(&self) -> ClientResult<()>;
 }
 
@@ -28,18 +35,10 @@ where
     L: HttpClient + Send + Sync,
 {
     sender: HttpSender<L>,
-    callbacks: C,
-    message: Arc<RwLock<NextMessage>>,
-    synced_state: ClientSyncedState,
-    capabilities: Capabilities,
-    pending_msg: Notifier,
-    instance_uid: String,
+    core: ClientCore<C>,
+    connection_settings_factory: Option<Box<dyn HttpClientFactory<Client = L> + Send + Sync>>,
 }
 
-/// Synchronous HTTP implementation of the Client trait.
-// If there is an error sending, the syncState should still be updated, we want it to be consistent
-// with the agent status, and we leave the responsibility to the OpAMP server to call ReportFullState
-// if it detects a gap on sequence numbers.
 impl<C, L> OpAMPHttpClient<C, L>
 where
     C: Callbacks + Send + Sync,
@@ -52,54 +51,89 @@ where
         http_client: L,
         pending_msg: Notifier,
     ) -> ClientResult<Self> {
-        let capabilities = start_settings.capabilities;
-        let instance_uid = start_settings.instance_uid.clone();
-
-        let (initial_message, synced_state) = Self::initial_message_and_state(start_settings)?;
+        let core = ClientCore::new(callbacks, start_settings, pending_msg)?;
 
         Ok(Self {
-            sender: HttpSender::new(http_client, instance_uid.clone()),
-            callbacks,
-            message: Arc::new(RwLock::new(NextMessage::new(initial_message))),
-            synced_state,
-            capabilities,
-            pending_msg,
-            instance_uid: instance_uid.to_string(),
+            sender: HttpSender::new(http_client, core.instance_uid.clone()),
+            core,
+            connection_settings_factory: None,
         })
     }
 
-    /// Helper to build the initial [AgentToServer] message to be sent to the server and the corresponding
-    /// internal state to keep track of sent fields (check [ClientSyncedState] for details).
-    fn initial_message_and_state(
-        start_settings: StartSettings,
-    ) -> ClientResult<(AgentToServer, ClientSyncedState)> {
-        // Destructured to get compile errors if any field is added to StartSettings
-        let StartSettings {
-            instance_uid,
-            capabilities,
-            custom_capabilities,
-            agent_description,
-        } = start_settings;
-
-        // Store initial state fields
-        let initial_state = ClientSyncedState::default();
-        if !agent_description.is_empty() {
-            initial_state.set_agent_description(agent_description.clone().into())?;
-        }
-        if let Some(custom_capabilities) = custom_capabilities.as_ref() {
-            initial_state.set_custom_capabilities(custom_capabilities.clone())?;
-        }
+    /// Returns the instance uid used by the client, it changes if the server assigns a new one.
+    pub(super) fn instance_uid(&self) -> SharedInstanceUid {
+        self.core.instance_uid.clone()
+    }
 
-        // build initial message
-        let initial_message = AgentToServer {
-            instance_uid: instance_uid.into(),
-            agent_description: Some(agent_description.into()),
-            capabilities: capabilities.into(),
-            custom_capabilities,
-            ..Default::default()
+    /// Returns the heartbeat interval requested by the server, which changes as new connection
+    /// settings are received.
+    pub(super) fn heartbeat_interval(&self) -> SharedHeartbeatInterval {
+        self.core.heartbeat_interval.clone()
+    }
+
+    /// Sets the compression applied to the requests sent to the server.
+    pub(super) fn set_request_compression(&mut self, compression: RequestCompression) {
+        self.sender.set_request_compression(compression);
+    }
+
+    /// Sets the factory used to build the HTTP client from the OpAMP connection settings offered
+    /// by the server.
+    pub(super) fn set_connection_settings_factory<F>(&mut self, factory: F)
+    where
+        F: HttpClientFactory<Client = L> + Send + Sync + 'static,
+    {
+        self.connection_settings_factory = Some(Box::new(factory));
+        self.restore_opamp_connection_settings();
+    }
+
+    /// Sets the store used to persist the client state, restoring the state it holds.
+    pub(super) fn set_state_store<S>(&mut self, store: S) -> ClientResult<()>
+    where
+        S: StateStore + 'static,
+    {
+        self.core.set_state_store(store)?;
+        self.restore_opamp_connection_settings();
+        Ok(())
+    }
+
+    /// Sets the handler of the telemetry and other connection settings offered by the server.
+    pub(super) fn set_connection_settings_handler<H>(&mut self, handler: H)
+    where
+        H: ConnectionSettingsHandler + 'static,
+    {
+        self.core.set_connection_settings_handler(handler);
+    }
+
+    /// Sets the handler applying the remote config offered by the server.
+    pub(super) fn set_remote_config_handler<H>(&mut self, handler: H)
+    where
+        H: RemoteConfigHandler + 'static,
+    {
+        self.core.set_remote_config_handler(handler);
+    }
+
+    // Replaces the HTTP client with one built from the persisted OpAMP connection settings, if
+    // both the state store and the factory are set. They were verified before being persisted.
+    fn restore_opamp_connection_settings(&self) {
+        let Some((factory, settings)) = self.connection_settings_factory.as_ref().zip(
+            self.core
+                .persisted_state()
+                .and_then(|state| state.opamp_connection_settings),
+        ) else {
+            return;
         };
 
-        Ok((initial_message, initial_state))
+        match factory.build(&settings) {
+            Ok(client) => {
+                debug!("restoring the persisted OpAMP connection settings");
+                let _ = self.sender.set_client(client).inspect_err(
+                    |err| error!(%err, "restoring the persisted OpAMP connection settings"),
+                );
+            }
+            Err(err) => {
+                warn!(%err, "building HTTP client from the persisted OpAMP connection settings")
+            }
+        }
     }
 }
 
@@ -109,7 +143,10 @@ where
     L: HttpClient + Send + Sync,
 {
     fn poll(&self) -> ClientResult<()> {
-        self.send_process()
+        let result = self.send_process();
+        // The remote config whose `Applying` status was sent can be applied now.
+        self.core.apply_sent_remote_config()?;
+        result
     }
 }
 
@@ -123,61 +160,118 @@ where
     // whether to resend or remain synced.
     fn send_process(&self) -> ClientResult<()> {
         // send message
-        let msg = self
-            .message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .pop();
+        let msg = self.core.pop_message()?;
         trace!("Send payload: {:?}", msg);
-        let server_to_agent = self.sender.send(msg).map_err(|e| {
-            let err_msg = e.to_string();
-            self.callbacks.on_connect_failed(e.into());
-            ClientError::ConnectFailedCallback(err_msg)
-        })?;
+        let server_to_agent = handle_response(&self.core, self.sender.send(msg))?;
+        let offered_settings = self
+            .core
+            .opamp_connection_settings_offer(&server_to_agent)?;
+
+        {
+            let _span = info_span!("process_message").entered();
+            self.core.process(server_to_agent)?;
+        }
 
-        // We consider it connected if we receive 2XX status from the Server.
-        self.callbacks.on_connect();
+        match offered_settings {
+            Some((settings, hash)) => self.apply_opamp_connection_settings(settings, hash),
+            None => Ok(()),
+        }
+    }
 
-        trace!("Received payload: {:?}", server_to_agent);
+    // Verifies the OpAMP connection settings offered by the server by sending the next message
+    // with a client built from them. If the server can be reached the new client replaces the
+    // current one and the offer is remembered as accepted, otherwise the message is sent with the
+    // current client. Either way, the same offer is not handled again until it changes.
+    fn apply_opamp_connection_settings(
+        &self,
+        settings: OpAmpConnectionSettings,
+        hash: Vec<u8>,
+    ) -> ClientResult<()> {
+        self.core.opamp_connection_settings_handled(&hash)?;
 
-        let _span = info_span!("process_message").entered();
-        if let ProcessResult::NeedsResend = crate::common::message_processor::process_message(
-            server_to_agent,
-            &self.callbacks,
-            &self.synced_state,
-            self.capabilities,
-            self.message.clone(),
-        )? {
-            self.pending_msg.notify_or_warn();
+        if let Err(err) = self.core.callbacks.on_opamp_connection_settings(&settings) {
+            warn!(%err, "OpAMP connection settings offer rejected by the agent");
+            return Ok(());
         }
 
-        Ok(())
+        let Some(factory) = self.connection_settings_factory.as_ref() else {
+            warn!("ignoring OpAMP connection settings offer, no HTTP client factory was provided");
+            return Ok(());
+        };
+
+        let client = match factory.build(&settings) {
+            Ok(client) => client,
+            Err(err) => {
+                warn!(%err, "building HTTP client from the offered OpAMP connection settings");
+                return Ok(());
+            }
+        };
+
+        let msg = self.core.pop_message()?;
+        let server_to_agent = match self.sender.send_with(&client, msg.clone()) {
+            Ok(server_to_agent) => {
+                self.sender
+                    .set_client(client)
+                    .map_err(|_| ClientError::PoisonError)?;
+                self.core.callbacks.on_connect();
+                self.core
+                    .callbacks
+                    .on_opamp_connection_settings_accepted(&settings);
+                self.core.accept_opamp_connection_settings(settings, hash)?;
+                server_to_agent
+            }
+            Err(err) => {
+                warn!(%err, "connecting with the offered OpAMP connection settings, keeping the previous ones");
+                handle_response(&self.core, self.sender.send(msg))?
+            }
+        };
+
+        let _span = info_span!("process_message").entered();
+        self.core.process(server_to_agent)
     }
 }
 
+// Reports the connection result through the callbacks and returns the received message.
+pub(super) fn handle_response<C: Callbacks>(
+    core: &ClientCore<C>,
+    response: OpampSenderResult<ServerToAgent>,
+) -> ClientResult<ServerToAgent> {
+    let server_to_agent = response.map_err(|e| {
+        let err = match &e {
+            HttpClientError::Throttled { retry_after, .. } => {
+                ClientError::ServerUnavailable(*retry_after)
+            }
+            _ => ClientError::ConnectFailedCallback(e.to_string()),
+        };
+        core.callbacks.on_connect_failed(e.into());
+        err
+    })?;
+
+    // We consider it connected if we receive 2XX status from the Server.
+    core.callbacks.on_connect();
+
+    trace!("Received payload: {:?}", server_to_agent);
+    Ok(server_to_agent)
+}
+
 impl<C, L> Drop for OpAMPHttpClient<C, L>
 where
     C: Callbacks + Send + Sync,
     L: HttpClient + Send + Sync,
 {
     fn drop(&mut self) {
+        let instance_uid = &self.core.instance_uid;
         // By OpAMP protocol, AgentDisconnect must be sent in the last message.
-        match self.message.write() {
-            Ok(mut next_message) => {
-                let mut msg = next_message.pop();
-                msg.agent_disconnect = Some(AgentDisconnect::default());
-
+        match self.core.pop_disconnect_message() {
+            Ok(msg) => {
                 let _ = self.sender.send(msg).inspect_err(|err| {
-                    error!(%err, instance_id=self.instance_uid, "sending disconnect OpAMP message");
+                    error!(%err, %instance_uid, "sending disconnect OpAMP message");
                 });
 
-                debug!(
-                    instance_uid = self.instance_uid,
-                    "OpAMPHttpClient disconnected from server"
-                );
+                debug!(%instance_uid, "OpAMPHttpClient disconnected from server");
             }
             Err(err) => {
-                error!(%err, instance_id=self.instance_uid, "assembling disconnect OpAMP message");
+                error!(%err, %instance_uid, "assembling disconnect OpAMP message");
             }
         };
     }
@@ -188,167 +282,52 @@ where
     C: Callbacks + Send + Sync,
     L: HttpClient + Send + Sync,
 {
-    /// Sets the agent description of the Agent.
-    ///
-    /// It uses compression and will only modify the message if there is a change.
-    fn set_agent_description(
-        &self,
-        description: crate::opamp::proto::AgentDescription,
-    ) -> ClientResult<()> {
-        if self
-            .synced_state
-            .agent_description_unchanged(&description)?
-        {
-            return Ok(());
-        }
-        self.synced_state
-            .set_agent_description(description.clone())?;
-
-        // update message
-        self.message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .update(|msg| {
-                msg.agent_description = Some(description);
-            });
-
-        debug!(
-            instance_uid = self.instance_uid,
-            "sending AgentToServer with provided description"
-        );
-        self.pending_msg.notify_or_warn();
-        Ok(())
-    }
-    /// Returns the agent description from the synced state.
-    fn get_agent_description(&self) -> ClientResult<crate::opamp::proto::AgentDescription> {
-        match self.synced_state.agent_description() {
-            Ok(Some(description)) => Ok(description),
-            Err(e) => Err(e.into()),
-            _ => Ok(crate::opamp::proto::AgentDescription::default()),
-        }
+    fn set_agent_description(&self, description: AgentDescription) -> ClientResult<()> {
+        self.core.set_agent_description(description)
     }
 
-    /// Sets the health status of the Agent.
-    ///
-    /// It uses compression and will only modify the message if there is a change.
-    fn set_health(&self, health: crate::opamp::proto::ComponentHealth) -> ClientResult<()> {
-        if !self
-            .capabilities
-            .has_capability(AgentCapabilities::ReportsHealth)
-        {
-            return Err(ClientError::UnsetHealthCapability);
-        }
+    fn get_agent_description(&self) -> ClientResult<AgentDescription> {
+        self.core.get_agent_description()
+    }
 
-        if self.synced_state.health_unchanged(&health)? {
-            return Ok(());
-        }
-        self.synced_state.set_health(health.clone())?;
-
-        // update message
-        self.message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .update(|msg| {
-                msg.health = Some(health);
-            });
+    fn set_health(&self, health: ComponentHealth) -> ClientResult<()> {
+        self.core.set_health(health)
+    }
 
-        debug!(
-            instance_uid = self.instance_uid,
-            "sending AgentToServer with provided health"
-        );
-        self.pending_msg.notify_or_warn();
-        Ok(())
+    fn set_component_health(&self, path: &[&str], health: ComponentHealth) -> ClientResult<()> {
+        self.core.set_component_health(path, health)
     }
 
-    /// Fetches the current local effective config using
-    /// [`get_effective_config`](Callbacks::get_effective_config) callback and sends it to the Server.
     fn update_effective_config(&self) -> ClientResult<()> {
-        if !self
-            .capabilities
-            .has_capability(AgentCapabilities::ReportsEffectiveConfig)
-        {
-            return Err(ClientError::UnsetEffectConfigCapability);
-        }
-
-        let config = self
-            .callbacks
-            .get_effective_config()
-            .map_err(|_| ClientError::EffectiveConfigError)?;
-
-        // update message
-        self.message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .update(|msg| {
-                msg.effective_config = Some(config);
-            });
+        self.core.update_effective_config()
+    }
 
-        debug!(
-            instance_uid = self.instance_uid,
-            "sending AgentToServer with fetched effective config"
-        );
-        self.pending_msg.notify_or_warn();
-        Ok(())
+    fn set_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()> {
+        self.core.set_remote_config_status(status)
     }
 
-    /// Sends the status of the remote config
-    /// that was previously received from the Server.
-    ///
-    /// It uses compression and will only modify the message if there is a change.
-    fn set_remote_config_status(
-        &self,
-        status: crate::opamp::proto::RemoteConfigStatus,
-    ) -> ClientResult<()> {
-        if !self
-            .capabilities
-            .has_capability(AgentCapabilities::ReportsRemoteConfig)
-        {
-            return Err(ClientError::UnsetRemoteConfigStatusCapability);
-        }
+    fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()> {
+        self.core.set_custom_capabilities(custom_capabilities)
+    }
 
-        if self.synced_state.remote_config_status_unchanged(&status)? {
-            return Ok(());
-        }
-        self.synced_state.set_remote_config_status(status.clone())?;
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()> {
+        self.core.set_package_statuses(statuses)
+    }
 
-        self.message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .update(|msg| {
-                msg.remote_config_status = Some(status);
-            });
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()> {
+        self.core.send_custom_message(message)
+    }
 
-        debug!(
-            instance_uid = self.instance_uid,
-            "sending AgentToServer with remote"
-        );
-        self.pending_msg.notify_or_warn();
-        Ok(())
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()> {
+        self.core.request_client_certificate(csr)
     }
 
-    fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()> {
-        if self
-            .synced_state
-            .custom_capabilities_unchanged(&custom_capabilities)?
-        {
-            return Ok(());
-        }
-        self.synced_state
-            .set_custom_capabilities(custom_capabilities.clone())?;
-
-        self.message
-            .write()
-            .map_err(|_| ClientError::PoisonError)?
-            .update(|msg| {
-                msg.custom_capabilities = Some(custom_capabilities);
-            });
+    fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>> {
+        self.core.server_capabilities()
+    }
 
-        debug!(
-            instance_uid = self.instance_uid,
-            "sending AgentToServer with custom capabilities"
-        );
-        self.pending_msg.notify_or_warn();
-        Ok(())
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()> {
+        self.core.set_available_components(components)
     }
 }
 
@@ -356,10 +335,13 @@ where
 pub(crate) mod tests {
     use super::*;
     use assert_matches::assert_matches;
+    use http::Response;
     use http::StatusCode;
-    use mockall::mock;
+    use mockall::{Sequence, mock};
+    use prost::Message;
     use rstest::rstest;
     use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
     use tracing_test::traced_test;
 
     use super::super::http_client::tests::{
@@ -367,14 +349,26 @@ pub(crate) mod tests {
     };
 
     use crate::http::HttpClientError;
-    use crate::opamp::proto::AgentDescription;
     use crate::opamp::proto::any_value::Value;
     use crate::opamp::proto::{
-        AgentConfigFile, AgentConfigMap, AnyValue, ComponentHealth, EffectiveConfig, KeyValue,
-        RemoteConfigStatus,
+        AgentCapabilities, AgentDescription, AgentIdentification, AgentToServer,
+        AgentToServerFlags, CertificateRequest, ConnectionSettingsOffers,
+        ConnectionSettingsRequest, OpAmpConnectionSettingsRequest, RemoteConfigStatuses,
+        ServerToAgentFlags, TelemetryConnectionSettings, TlsCertificate,
+    };
+    use crate::opamp::proto::{
+        AgentConfigFile, AgentConfigMap, AgentRemoteConfig, AnyValue, ComponentDetails,
+        ComponentHealth, EffectiveConfig, KeyValue, RemoteConfigStatus,
+    };
+    use crate::operation::connection_settings::{
+        ConnectionSettingsRejected, tests::MockConnectionSettingsHandlerMockall,
     };
     use crate::operation::instance_uid::InstanceUid;
+    use crate::operation::remote_config::{
+        RemoteConfigError, tests::MockRemoteConfigHandlerMockall,
+    };
     use crate::operation::settings::DescriptionValueType;
+    use crate::operation::state_store::{PersistedState, tests::InMemoryStateStore};
     use crate::{
         capabilities,
         opamp::proto::ServerToAgent,
@@ -392,9 +386,15 @@ pub(crate) mod tests {
             fn set_agent_description(&self, description: AgentDescription) -> ClientResult<()>;
             fn get_agent_description(&self) -> ClientResult<AgentDescription>;
             fn set_health(&self, health: proto::proto::ComponentHealth) -> ClientResult<()>;
+            fn set_component_health<'a>(&self, path: &[&'a str], health: ComponentHealth) -> ClientResult<()>;
             fn update_effective_config(&self) -> ClientResult<()>;
             fn set_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()>;
             fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()>;
+            fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()>;
+            fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()>;
+            fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()>;
+            fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>>;
+            fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()>;
         }
     }
 
@@ -474,6 +474,8 @@ pub(crate) mod tests {
             capabilities,
             custom_capabilities: Some(custom_capabilities.clone()),
             agent_description: agent_description.clone(),
+            request_instance_uid: true,
+            derive_parent_health: true,
         };
 
         let (pending_msg, _) = Notifier::new("name".to_string());
@@ -486,7 +488,7 @@ pub(crate) mod tests {
         .expect("Client creation expects to succeed");
 
         // Check initial message
-        let message = client.message.write().unwrap().pop();
+        let message = client.core.message.write().unwrap().pop();
         assert_eq!(
             message.custom_capabilities,
             Some(custom_capabilities.clone())
@@ -497,13 +499,14 @@ pub(crate) mod tests {
         );
         assert_eq!(message.capabilities, u64::from(capabilities));
         assert_eq!(message.instance_uid, Vec::<u8>::from(instance_uid));
+        assert_eq!(message.flags, AgentToServerFlags::RequestInstanceUid as u64);
         // Check state
         assert_eq!(
-            client.synced_state.custom_capabilities().unwrap(),
+            client.core.synced_state.custom_capabilities().unwrap(),
             Some(custom_capabilities)
         );
         assert_eq!(
-            client.synced_state.agent_description().unwrap(),
+            client.core.synced_state.agent_description().unwrap(),
             Some(agent_description.into())
         );
     }
@@ -570,7 +573,7 @@ pub(crate) mod tests {
         client.poll().unwrap();
 
         // After poll, the next message has its fields compressed away.
-        let message = client.message.write().unwrap().pop();
+        let message = client.core.message.write().unwrap().pop();
         assert_eq!(message.agent_description, None);
         assert_eq!(message.health, None);
         assert_eq!(message.remote_config_status, None);
@@ -578,12 +581,12 @@ pub(crate) mod tests {
 
         // Synced state retains the values.
         assert_eq!(
-            client.synced_state.agent_description().unwrap(),
+            client.core.synced_state.agent_description().unwrap(),
             Some(agent_description.into()),
         );
-        assert_eq!(client.synced_state.health().unwrap(), Some(health));
+        assert_eq!(client.core.synced_state.health().unwrap(), Some(health));
         assert_eq!(
-            client.synced_state.remote_config_status().unwrap(),
+            client.core.synced_state.remote_config_status().unwrap(),
             Some(remote_config_status),
         );
     }
@@ -663,7 +666,7 @@ pub(crate) mod tests {
         client.poll().unwrap();
 
         // this pop should return a current message with the attributes reset
-        let message = client.message.write().unwrap().pop();
+        let message = client.core.message.write().unwrap().pop();
 
         // Asserts the next message is going to be sent is compressed
         let expected = AgentToServer::default();
@@ -675,16 +678,16 @@ pub(crate) mod tests {
 
         // Asserts the state contains the last set values
         assert_eq!(
-            client.synced_state.agent_description().unwrap(),
+            client.core.synced_state.agent_description().unwrap(),
             Some(agent_description)
         );
-        assert_eq!(client.synced_state.health().unwrap(), Some(health));
+        assert_eq!(client.core.synced_state.health().unwrap(), Some(health));
         assert_eq!(
-            client.synced_state.remote_config_status().unwrap(),
+            client.core.synced_state.remote_config_status().unwrap(),
             Some(remote_config_status)
         );
         assert_eq!(
-            client.synced_state.custom_capabilities().unwrap(),
+            client.core.synced_state.custom_capabilities().unwrap(),
             Some(custom_capabilities)
         );
     }
@@ -729,7 +732,12 @@ pub(crate) mod tests {
         assert_matches!(result.unwrap_err(), ClientError::ConnectFailedCallback(_));
 
         assert_eq!(
-            client.synced_state.remote_config_status().unwrap().unwrap(),
+            client
+                .core
+                .synced_state
+                .remote_config_status()
+                .unwrap()
+                .unwrap(),
             remote_config_status
         );
     }
@@ -753,6 +761,42 @@ pub(crate) mod tests {
         assert!(logs_contain("OpAMPHttpClient disconnected from server"));
         assert!(logs_contain(instance_uid.to_string().as_str()));
     }
+    #[test]
+    fn throttled_response_returns_server_unavailable() {
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.should_post(response_from_server_to_agent(
+            &ServerToAgent::default(),
+            ResponseParts {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                headers: HashMap::from([("Retry-After".to_string(), "10".to_string())]),
+            },
+        ));
+        // disconnect message
+        mock_client.expect_post().once().returning(|_| {
+            Ok(response_from_server_to_agent(
+                &ServerToAgent::default(),
+                ResponseParts::default(),
+            ))
+        });
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.should_on_connect_failed();
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            mock_callbacks,
+            StartSettings::default(),
+            mock_client,
+            pending_msg,
+        )
+        .unwrap();
+
+        assert_matches!(
+            client.poll().unwrap_err(),
+            ClientError::ServerUnavailable(Some(delay)) if delay == std::time::Duration::from_secs(10)
+        );
+    }
+
     #[traced_test]
     #[test]
     fn test_fail_to_drop() {
@@ -781,6 +825,10 @@ pub(crate) mod tests {
         Box::new(|c: &TestClient| c.set_health(ComponentHealth::default())) as ClientAction,
         Box::new(|e| assert_matches!(e, ClientError::UnsetHealthCapability)),
     )]
+    #[case::component_health(
+        Box::new(|c: &TestClient| c.set_component_health(&["pipelines"], ComponentHealth::default())) as ClientAction,
+        Box::new(|e| assert_matches!(e, ClientError::UnsetHealthCapability)),
+    )]
     #[case::effective_config(
         Box::new(|c: &TestClient| c.update_effective_config()) as ClientAction,
         Box::new(|e| assert_matches!(e, ClientError::UnsetEffectConfigCapability)),
@@ -789,6 +837,14 @@ pub(crate) mod tests {
         Box::new(|c: &TestClient| c.set_remote_config_status(RemoteConfigStatus::default())) as ClientAction,
         Box::new(|e| assert_matches!(e, ClientError::UnsetRemoteConfigStatusCapability)),
     )]
+    #[case::package_statuses(
+        Box::new(|c: &TestClient| c.set_package_statuses(PackageStatuses::default())) as ClientAction,
+        Box::new(|e| assert_matches!(e, ClientError::UnsetPackageStatusesCapability)),
+    )]
+    #[case::available_components(
+        Box::new(|c: &TestClient| c.set_available_components(AvailableComponents::default())) as ClientAction,
+        Box::new(|e| assert_matches!(e, ClientError::UnsetAvailableComponentsCapability)),
+    )]
     fn setter_without_capability_returns_error(
         #[case] action: ClientAction,
         #[case] assert_expected_error: Box<dyn Fn(ClientError)>,
@@ -831,6 +887,16 @@ pub(crate) mod tests {
             ..Default::default()
         })) as ClientAction,
     )]
+    #[case::component_health(
+        StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsHealth),
+            ..Default::default()
+        },
+        Box::new(|c: &TestClient| c.set_component_health(&["pipelines", "traces"], ComponentHealth {
+            healthy: true,
+            ..Default::default()
+        })) as ClientAction,
+    )]
     #[case::remote_config_status(
         StartSettings {
             capabilities: capabilities!(AgentCapabilities::ReportsRemoteConfig),
@@ -841,12 +907,32 @@ pub(crate) mod tests {
             ..Default::default()
         })) as ClientAction,
     )]
+    #[case::package_statuses(
+        StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsPackageStatuses),
+            ..Default::default()
+        },
+        Box::new(|c: &TestClient| c.set_package_statuses(PackageStatuses {
+            server_provided_all_packages_hash: vec![1, 2, 3],
+            ..Default::default()
+        })) as ClientAction,
+    )]
     #[case::custom_capabilities(
         StartSettings::default(),
         Box::new(|c: &TestClient| c.set_custom_capabilities(CustomCapabilities {
             capabilities: vec!["custom.cap".to_string()],
         })) as ClientAction,
     )]
+    #[case::available_components(
+        StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsAvailableComponents),
+            ..Default::default()
+        },
+        Box::new(|c: &TestClient| c.set_available_components(AvailableComponents {
+            hash: vec![1, 2, 3],
+            ..Default::default()
+        })) as ClientAction,
+    )]
     fn setter_with_unchanged_value_skips_resend_notification(
         #[case] settings: StartSettings,
         #[case] action: ClientAction,
@@ -874,6 +960,52 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn set_available_components_sends_only_the_hash() {
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            StartSettings {
+                capabilities: capabilities!(AgentCapabilities::ReportsAvailableComponents),
+                ..Default::default()
+            },
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+
+        assert_matches!(
+            client
+                .set_available_components(AvailableComponents::default())
+                .unwrap_err(),
+            ClientError::EmptyAvailableComponentsHash
+        );
+
+        let components = AvailableComponents {
+            components: HashMap::from([("receiver".to_string(), ComponentDetails::default())]),
+            hash: b"hash".to_vec(),
+        };
+        client.set_available_components(components.clone()).unwrap();
+
+        assert_eq!(
+            client.core.pop_message().unwrap().available_components,
+            Some(AvailableComponents {
+                hash: components.hash.clone(),
+                ..Default::default()
+            })
+        );
+        // The hash is not sent again unless the components change
+        assert_eq!(
+            client.core.pop_message().unwrap().available_components,
+            None
+        );
+        // The full components are sent if the server requests them
+        assert_eq!(
+            client.core.synced_state.available_components().unwrap(),
+            Some(components)
+        );
+    }
+
     #[test]
     fn get_agent_description_returns_synced_value() {
         let agent_description = crate::operation::settings::AgentDescription::testing_non_empty();
@@ -913,6 +1045,78 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn send_custom_message() {
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.expect_post().times(2).returning(|_| {
+            Ok(response_from_server_to_agent(
+                &ServerToAgent::default(),
+                ResponseParts::default(),
+            ))
+        });
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().once().return_const(());
+        mock_callbacks.expect_on_message().once().return_const(());
+
+        let settings = StartSettings {
+            custom_capabilities: Some(CustomCapabilities {
+                capabilities: vec!["foo_capability".to_string()],
+            }),
+            ..Default::default()
+        };
+        let (pending_msg, has_pending_msg) = Notifier::new("msg".to_string());
+        let client =
+            OpAMPHttpClient::new(mock_callbacks, settings, mock_client, pending_msg).unwrap();
+
+        let custom_message = CustomMessage {
+            capability: "foo_capability".to_string(),
+            r#type: "foo_type".to_string(),
+            data: b"foo".to_vec(),
+        };
+        client.send_custom_message(custom_message.clone()).unwrap();
+        has_pending_msg.try_recv().unwrap();
+
+        // The previous message has not been sent yet.
+        assert_matches!(
+            client.send_custom_message(custom_message.clone()),
+            Err(ClientError::CustomMessagePending)
+        );
+
+        client.poll().unwrap();
+
+        // Once sent, a new custom message can be queued.
+        client.send_custom_message(custom_message.clone()).unwrap();
+        let message = client.core.message.write().unwrap().pop();
+        assert_eq!(message.custom_message, Some(custom_message));
+    }
+
+    #[test]
+    fn send_custom_message_with_unsupported_capability() {
+        let settings = StartSettings {
+            custom_capabilities: Some(CustomCapabilities {
+                capabilities: vec!["foo_capability".to_string()],
+            }),
+            ..Default::default()
+        };
+        let (pending_msg, has_pending_msg) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            settings,
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+
+        assert_matches!(
+            client.send_custom_message(CustomMessage {
+                capability: "bar_capability".to_string(),
+                ..Default::default()
+            }),
+            Err(ClientError::UnsupportedCustomCapability(capability)) if capability == "bar_capability"
+        );
+        assert!(has_pending_msg.try_recv().is_err());
+    }
+
     #[test]
     fn update_effective_config_callback_error_maps_to_effective_config_error() {
         use crate::operation::callbacks::tests::CallbacksMockError;
@@ -937,4 +1141,1018 @@ pub(crate) mod tests {
             ClientError::EffectiveConfigError
         );
     }
+
+    #[test]
+    fn update_effective_config_with_unchanged_config_skips_resend() {
+        let effective_config = EffectiveConfig {
+            config_map: Some(AgentConfigMap {
+                config_map: HashMap::from([(
+                    "config.yaml".to_string(),
+                    AgentConfigFile {
+                        body: b"key: value".to_vec(),
+                        content_type: "text/yaml".to_string(),
+                    },
+                )]),
+            }),
+        };
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks
+            .expect_get_effective_config()
+            .times(3)
+            .returning({
+                let effective_config = effective_config.clone();
+                move || Ok(effective_config.clone())
+            });
+        mock_callbacks.expect_on_message().once().return_const(());
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsEffectiveConfig),
+            ..Default::default()
+        };
+        let (pending_msg, has_pending_msg) = Notifier::new("msg".to_string());
+        let client =
+            OpAMPHttpClient::new(mock_callbacks, settings, drop_only_http_mock(), pending_msg)
+                .unwrap();
+
+        client.update_effective_config().unwrap();
+        has_pending_msg
+            .try_recv()
+            .expect("first call should notify");
+        assert_eq!(
+            client.core.pop_message().unwrap().effective_config,
+            Some(effective_config.clone())
+        );
+
+        client.update_effective_config().unwrap();
+        assert!(has_pending_msg.try_recv().is_err());
+        assert_eq!(client.core.pop_message().unwrap().effective_config, None);
+
+        // The full state includes it anyway
+        client
+            .core
+            .process(ServerToAgent {
+                flags: ServerToAgentFlags::ReportFullState as u64,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            client.core.pop_message().unwrap().effective_config,
+            Some(effective_config)
+        );
+    }
+
+    fn opamp_connection_settings() -> OpAmpConnectionSettings {
+        OpAmpConnectionSettings {
+            destination_endpoint: "http://localhost:4320/v1/opamp".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Response including an offer of the settings returned by `opamp_connection_settings`.
+    fn connection_settings_offer_response() -> Response<Vec<u8>> {
+        response_from_server_to_agent(
+            &ServerToAgent {
+                connection_settings: Some(ConnectionSettingsOffers {
+                    hash: b"offer-hash".to_vec(),
+                    opamp: Some(opamp_connection_settings()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ResponseParts::default(),
+        )
+    }
+
+    // Factory returning the provided client once.
+    fn factory_once(
+        http_client: MockHttpClientMockall,
+    ) -> impl Fn(&OpAmpConnectionSettings) -> Result<MockHttpClientMockall, HttpClientError> {
+        let http_client = Mutex::new(Some(http_client));
+        move |settings: &OpAmpConnectionSettings| {
+            assert_eq!(settings, &opamp_connection_settings());
+            Ok(http_client.lock().unwrap().take().unwrap())
+        }
+    }
+
+    fn accepts_opamp_connection_settings() -> StartSettings {
+        StartSettings {
+            capabilities: capabilities!(AgentCapabilities::AcceptsOpAmpConnectionSettings),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn opamp_connection_settings_offer_accepted() {
+        let mut current_client = MockHttpClientMockall::new();
+        current_client.should_post(connection_settings_offer_response());
+        // The new client sends the verification message, the next poll and the disconnect one.
+        // The offer received again is already accepted, so it is not applied again.
+        let mut new_client = MockHttpClientMockall::new();
+        new_client
+            .expect_post()
+            .times(3)
+            .returning(|_| Ok(connection_settings_offer_response()));
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(3).return_const(());
+        mock_callbacks.expect_on_message().times(3).return_const(());
+        mock_callbacks.should_on_opamp_connection_settings(&opamp_connection_settings());
+        mock_callbacks.should_on_opamp_connection_settings_accepted(&opamp_connection_settings());
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            mock_callbacks,
+            accepts_opamp_connection_settings(),
+            current_client,
+            pending_msg,
+        )
+        .unwrap();
+        client.set_connection_settings_factory(factory_once(new_client));
+
+        client.poll().unwrap();
+        client.poll().unwrap();
+    }
+
+    #[test]
+    fn opamp_connection_settings_offer_connection_failure() {
+        let mut seq = Sequence::new();
+        let mut current_client = MockHttpClientMockall::new();
+        current_client
+            .expect_post()
+            .once()
+            .in_sequence(&mut seq)
+            .return_once(|_| Ok(connection_settings_offer_response()));
+        // The current client sends the message again after the verification failure, the next
+        // poll and the disconnect message. The offer received again is not applied again, so the
+        // factory is only called once.
+        current_client
+            .expect_post()
+            .times(3)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(connection_settings_offer_response()));
+        let mut new_client = MockHttpClientMockall::new();
+        new_client.should_not_post(HttpClientError::TransportError("unreachable".to_string()));
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(3).return_const(());
+        mock_callbacks.expect_on_message().times(3).return_const(());
+        mock_callbacks.should_on_opamp_connection_settings(&opamp_connection_settings());
+        mock_callbacks
+            .expect_on_opamp_connection_settings_accepted()
+            .never();
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            mock_callbacks,
+            accepts_opamp_connection_settings(),
+            current_client,
+            pending_msg,
+        )
+        .unwrap();
+        client.set_connection_settings_factory(factory_once(new_client));
+
+        client.poll().unwrap();
+        client.poll().unwrap();
+    }
+
+    #[test]
+    fn opamp_connection_settings_offer_without_factory() {
+        let mut current_client = MockHttpClientMockall::new();
+        current_client
+            .expect_post()
+            .times(3)
+            .returning(|_| Ok(connection_settings_offer_response()));
+
+        // The offer is surfaced once, even if it cannot be applied.
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(2).return_const(());
+        mock_callbacks.expect_on_message().times(2).return_const(());
+        mock_callbacks.should_on_opamp_connection_settings(&opamp_connection_settings());
+        mock_callbacks
+            .expect_on_opamp_connection_settings_accepted()
+            .never();
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            mock_callbacks,
+            accepts_opamp_connection_settings(),
+            current_client,
+            pending_msg,
+        )
+        .unwrap();
+
+        client.poll().unwrap();
+        client.poll().unwrap();
+    }
+
+    #[rstest]
+    #[case::rejected_by_the_agent(accepts_opamp_connection_settings(), true)]
+    #[case::not_capable(StartSettings::default(), false)]
+    fn opamp_connection_settings_offer_ignored(
+        #[case] start_settings: StartSettings,
+        #[case] offer_expected: bool,
+    ) {
+        use crate::operation::callbacks::tests::CallbacksMockError;
+
+        let mut current_client = MockHttpClientMockall::new();
+        current_client
+            .expect_post()
+            .times(3)
+            .returning(|_| Ok(connection_settings_offer_response()));
+
+        // The rejected offer received again is not offered to the agent again.
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(2).return_const(());
+        mock_callbacks.expect_on_message().times(2).return_const(());
+        mock_callbacks
+            .expect_on_opamp_connection_settings()
+            .times(usize::from(offer_expected))
+            .returning(|_| Err(CallbacksMockError));
+        mock_callbacks
+            .expect_on_opamp_connection_settings_accepted()
+            .never();
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client =
+            OpAMPHttpClient::new(mock_callbacks, start_settings, current_client, pending_msg)
+                .unwrap();
+        client.set_connection_settings_factory(
+            |_: &OpAmpConnectionSettings| -> Result<MockHttpClientMockall, HttpClientError> {
+                unreachable!("the offer should not be applied")
+            },
+        );
+
+        client.poll().unwrap();
+        client.poll().unwrap();
+    }
+
+    #[test]
+    fn request_client_certificate() {
+        let (pending_msg, has_pending_msg) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            accepts_opamp_connection_settings(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+
+        // The server capabilities are unknown until the first response is received.
+        let accepts_csr = crate::opamp::proto::ServerCapabilities::AcceptsConnectionSettingsRequest;
+        assert_matches!(
+            client.request_client_certificate(b"csr".to_vec()),
+            Err(ClientError::UnsupportedServerCapability(capability)) if capability == accepts_csr
+        );
+        assert!(has_pending_msg.try_recv().is_err());
+
+        client
+            .core
+            .synced_state
+            .set_server_capabilities(ServerCapabilities::new(vec![accepts_csr]))
+            .unwrap();
+        client.request_client_certificate(b"csr".to_vec()).unwrap();
+        has_pending_msg.try_recv().unwrap();
+
+        let message = client.core.message.write().unwrap().pop();
+        assert_eq!(
+            message.connection_settings_request,
+            Some(ConnectionSettingsRequest {
+                opamp: Some(OpAmpConnectionSettingsRequest {
+                    certificate_request: Some(CertificateRequest {
+                        csr: b"csr".to_vec()
+                    }),
+                }),
+            })
+        );
+        assert!(
+            client
+                .core
+                .synced_state
+                .take_certificate_requested()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn request_client_certificate_without_capability() {
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            StartSettings::default(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+
+        assert_matches!(
+            client.request_client_certificate(b"csr".to_vec()),
+            Err(ClientError::UnsetOpAmpConnectionSettingsCapability)
+        );
+    }
+
+    #[rstest]
+    #[case::accepted(
+        vec![
+            crate::opamp::proto::ServerCapabilities::AcceptsStatus,
+            crate::opamp::proto::ServerCapabilities::AcceptsEffectiveConfig,
+            crate::opamp::proto::ServerCapabilities::AcceptsPackagesStatus,
+        ],
+        true,
+        true
+    )]
+    #[case::not_accepted(
+        vec![crate::opamp::proto::ServerCapabilities::AcceptsStatus],
+        false,
+        true
+    )]
+    #[case::status_not_accepted(
+        vec![
+            crate::opamp::proto::ServerCapabilities::AcceptsEffectiveConfig,
+            crate::opamp::proto::ServerCapabilities::AcceptsPackagesStatus,
+        ],
+        true,
+        false
+    )]
+    fn fields_sent_according_to_server_capabilities(
+        #[case] server_capabilities: Vec<crate::opamp::proto::ServerCapabilities>,
+        #[case] fields_expected: bool,
+        #[case] status_expected: bool,
+    ) {
+        let server_capabilities = ServerCapabilities::new(server_capabilities);
+
+        let mut seq = Sequence::new();
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client
+            .expect_post()
+            .once()
+            .in_sequence(&mut seq)
+            .return_once(move |_| {
+                Ok(response_from_server_to_agent(
+                    &ServerToAgent {
+                        capabilities: server_capabilities.into(),
+                        ..Default::default()
+                    },
+                    ResponseParts::default(),
+                ))
+            });
+        mock_client
+            .expect_post()
+            .once()
+            .in_sequence(&mut seq)
+            .return_once(move |body| {
+                let msg = AgentToServer::decode(body.as_slice()).unwrap();
+                assert_eq!(msg.effective_config.is_some(), fields_expected);
+                assert_eq!(msg.package_statuses.is_some(), fields_expected);
+                assert_eq!(msg.remote_config_status.is_some(), status_expected);
+                Ok(response_from_server_to_agent(
+                    &ServerToAgent::default(),
+                    ResponseParts::default(),
+                ))
+            });
+        // disconnect message
+        mock_client
+            .expect_post()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_| {
+                Ok(response_from_server_to_agent(
+                    &ServerToAgent::default(),
+                    ResponseParts::default(),
+                ))
+            });
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(2).return_const(());
+        mock_callbacks.expect_on_message().times(2).return_const(());
+        mock_callbacks.should_get_effective_config();
+
+        let settings = StartSettings {
+            capabilities: capabilities!(
+                AgentCapabilities::ReportsEffectiveConfig,
+                AgentCapabilities::ReportsPackageStatuses,
+                AgentCapabilities::ReportsRemoteConfig
+            ),
+            ..Default::default()
+        };
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client =
+            OpAMPHttpClient::new(mock_callbacks, settings, mock_client, pending_msg).unwrap();
+
+        assert_eq!(client.server_capabilities().unwrap(), None);
+        client.poll().unwrap();
+        assert_eq!(
+            client.server_capabilities().unwrap(),
+            Some(server_capabilities)
+        );
+
+        let package_statuses = PackageStatuses {
+            server_provided_all_packages_hash: b"hash".to_vec(),
+            ..Default::default()
+        };
+        client.update_effective_config().unwrap();
+        client
+            .set_package_statuses(package_statuses.clone())
+            .unwrap();
+        client
+            .set_remote_config_status(RemoteConfigStatus {
+                last_remote_config_hash: b"hash".to_vec(),
+                status: RemoteConfigStatuses::Applied as i32,
+                ..Default::default()
+            })
+            .unwrap();
+        client.poll().unwrap();
+
+        // The synced state keeps the fields, even if they were not sent.
+        assert_eq!(
+            client.core.synced_state.package_statuses().unwrap(),
+            Some(package_statuses)
+        );
+    }
+
+    #[test]
+    fn state_store_restores_persisted_state() {
+        let instance_uid = InstanceUid::create();
+        let remote_config_status = RemoteConfigStatus {
+            last_remote_config_hash: b"hash".to_vec(),
+            status: RemoteConfigStatuses::Applied as i32,
+            ..Default::default()
+        };
+        let store = InMemoryStateStore::default();
+        store
+            .store(&PersistedState {
+                instance_uid: Some(instance_uid.clone()),
+                remote_config_status: Some(remote_config_status.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsRemoteConfig),
+            request_instance_uid: true,
+            ..Default::default()
+        };
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            settings,
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+        client.set_state_store(store).unwrap();
+
+        let message = client.core.pop_message().unwrap();
+        assert_eq!(message.instance_uid, Vec::<u8>::from(instance_uid));
+        // The restored instance uid is not requested again
+        assert_eq!(message.flags, AgentToServerFlags::Unspecified as u64);
+        assert_eq!(
+            message.remote_config_status,
+            Some(remote_config_status.clone())
+        );
+        // The restored status is not sent again if it doesn't change
+        client
+            .set_remote_config_status(remote_config_status)
+            .unwrap();
+        assert_eq!(
+            client.core.pop_message().unwrap().remote_config_status,
+            None
+        );
+    }
+
+    #[test]
+    fn state_store_persists_state_changes() {
+        let new_instance_uid = InstanceUid::create();
+        let response = response_from_server_to_agent(
+            &ServerToAgent {
+                agent_identification: Some(AgentIdentification {
+                    new_instance_uid: new_instance_uid.clone().into(),
+                }),
+                ..Default::default()
+            },
+            ResponseParts::default(),
+        );
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.should_post(response);
+        mock_client.should_post(response_from_server_to_agent(
+            &ServerToAgent::default(),
+            ResponseParts::default(),
+        ));
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.should_on_connect();
+        mock_callbacks.expect_on_message().once().return_const(());
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsRemoteConfig),
+            ..Default::default()
+        };
+        let start_instance_uid = settings.instance_uid.clone();
+        let store = InMemoryStateStore::default();
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client =
+            OpAMPHttpClient::new(mock_callbacks, settings, mock_client, pending_msg).unwrap();
+        client.set_state_store(store.clone()).unwrap();
+
+        // The instance uid is persisted right away
+        assert_eq!(
+            store.stored().unwrap().instance_uid,
+            Some(start_instance_uid)
+        );
+
+        let failed = RemoteConfigStatus {
+            last_remote_config_hash: b"failed".to_vec(),
+            status: RemoteConfigStatuses::Failed as i32,
+            ..Default::default()
+        };
+        client.set_remote_config_status(failed.clone()).unwrap();
+        let stored = store.stored().unwrap();
+        assert_eq!(stored.remote_config_status, Some(failed));
+        assert_eq!(stored.remote_config_hash, Some(b"failed".to_vec()));
+
+        let applied = RemoteConfigStatus {
+            last_remote_config_hash: b"applied".to_vec(),
+            status: RemoteConfigStatuses::Applied as i32,
+            ..Default::default()
+        };
+        client.set_remote_config_status(applied.clone()).unwrap();
+        let stored = store.stored().unwrap();
+        assert_eq!(stored.remote_config_status, Some(applied));
+        assert_eq!(stored.remote_config_hash, Some(b"applied".to_vec()));
+
+        client.poll().unwrap();
+        assert_eq!(store.stored().unwrap().instance_uid, Some(new_instance_uid));
+    }
+
+    #[test]
+    fn state_store_persists_and_restores_opamp_connection_settings() {
+        let store = InMemoryStateStore::default();
+        {
+            let mut current_client = MockHttpClientMockall::new();
+            current_client.should_post(connection_settings_offer_response());
+            let mut new_client = MockHttpClientMockall::new();
+            new_client.expect_post().times(2).returning(|_| {
+                Ok(response_from_server_to_agent(
+                    &ServerToAgent::default(),
+                    ResponseParts::default(),
+                ))
+            });
+
+            let mut mock_callbacks = MockCallbacksMockall::new();
+            mock_callbacks.expect_on_connect().times(2).return_const(());
+            mock_callbacks.expect_on_message().times(2).return_const(());
+            mock_callbacks.should_on_opamp_connection_settings(&opamp_connection_settings());
+            mock_callbacks
+                .should_on_opamp_connection_settings_accepted(&opamp_connection_settings());
+
+            let (pending_msg, _) = Notifier::new("msg".to_string());
+            let mut client = OpAMPHttpClient::new(
+                mock_callbacks,
+                accepts_opamp_connection_settings(),
+                current_client,
+                pending_msg,
+            )
+            .unwrap();
+            client.set_connection_settings_factory(factory_once(new_client));
+            client.set_state_store(store.clone()).unwrap();
+
+            client.poll().unwrap();
+        }
+        let stored = store.stored().unwrap();
+        assert_eq!(
+            stored.opamp_connection_settings,
+            Some(opamp_connection_settings())
+        );
+        assert_eq!(
+            stored.opamp_connection_settings_hash,
+            Some(b"offer-hash".to_vec())
+        );
+
+        // After a restart, the client is built from the persisted settings. The current client
+        // is not expected to be used.
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            MockCallbacksMockall::new(),
+            accepts_opamp_connection_settings(),
+            MockHttpClientMockall::new(),
+            pending_msg,
+        )
+        .unwrap();
+        client.set_state_store(store).unwrap();
+        client.set_connection_settings_factory(factory_once(drop_only_http_mock()));
+    }
+
+    fn reports_own_telemetry() -> StartSettings {
+        StartSettings {
+            capabilities: capabilities!(
+                AgentCapabilities::ReportsOwnMetrics,
+                AgentCapabilities::ReportsOwnLogs
+            ),
+            ..Default::default()
+        }
+    }
+
+    // Offer of own telemetry connection settings identified by the provided hash.
+    fn telemetry_connection_settings_offer(hash: &[u8]) -> ServerToAgent {
+        let settings = |signal: &str| TelemetryConnectionSettings {
+            destination_endpoint: format!("http://localhost:4318/v1/{signal}"),
+            ..Default::default()
+        };
+        ServerToAgent {
+            connection_settings: Some(ConnectionSettingsOffers {
+                hash: hash.to_vec(),
+                own_metrics: Some(settings("metrics")),
+                own_traces: Some(settings("traces")),
+                own_logs: Some(settings("logs")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn telemetry_connection_settings_only_surfaced_when_changed() {
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        let mut seq = Sequence::new();
+        for surfaced in [true, false, true, true, true] {
+            mock_callbacks
+                .expect_on_message()
+                .once()
+                .in_sequence(&mut seq)
+                .withf(move |data| {
+                    data.own_metrics.is_some() == surfaced && data.own_logs.is_some() == surfaced
+                })
+                .return_const(());
+        }
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            mock_callbacks,
+            reports_own_telemetry(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+
+        for hash in [b"first".as_slice(), b"first", b"second", b"", b""] {
+            client
+                .core
+                .process(telemetry_connection_settings_offer(hash))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn opamp_connection_settings_dropped_once_accepted() {
+        // The certificate is only surfaced if the OpAMP connection settings reach the message
+        // processing.
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_message().times(2).return_const(());
+        mock_callbacks
+            .expect_on_client_certificate()
+            .once()
+            .return_const(());
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let client = OpAMPHttpClient::new(
+            mock_callbacks,
+            accepts_opamp_connection_settings(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+        client
+            .core
+            .accept_opamp_connection_settings(opamp_connection_settings(), b"accepted".to_vec())
+            .unwrap();
+
+        for hash in [b"accepted".as_slice(), b"changed"] {
+            client
+                .core
+                .synced_state
+                .set_certificate_requested()
+                .unwrap();
+            client
+                .core
+                .process(ServerToAgent {
+                    connection_settings: Some(ConnectionSettingsOffers {
+                        hash: hash.to_vec(),
+                        opamp: Some(OpAmpConnectionSettings {
+                            certificate: Some(TlsCertificate::default()),
+                            ..opamp_connection_settings()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    #[traced_test]
+    #[test]
+    fn connection_settings_handler_accepts_or_rejects_the_offer() {
+        let mut handler = MockConnectionSettingsHandlerMockall::new();
+        let mut seq = Sequence::new();
+        // The offer is handled again until all the settings are accepted
+        for logs_accepted in [false, true] {
+            handler
+                .expect_on_own_metrics()
+                .once()
+                .in_sequence(&mut seq)
+                .withf(|settings| settings.destination_endpoint.ends_with("metrics"))
+                .returning(|_| Ok(()));
+            handler
+                .expect_on_own_logs()
+                .once()
+                .in_sequence(&mut seq)
+                .returning(move |_| {
+                    if logs_accepted {
+                        Ok(())
+                    } else {
+                        Err(ConnectionSettingsRejected("invalid endpoint".to_string()))
+                    }
+                });
+        }
+        // Not capable
+        handler.expect_on_own_traces().never();
+        handler.expect_on_other_connection_settings().never();
+
+        // The settings are not surfaced through `on_message` when there is a handler
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks
+            .expect_on_message()
+            .times(3)
+            .withf(|data| {
+                data.own_metrics.is_none() && data.own_traces.is_none() && data.own_logs.is_none()
+            })
+            .return_const(());
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            mock_callbacks,
+            reports_own_telemetry(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+        client.set_connection_settings_handler(handler);
+
+        for _ in 0..3 {
+            client
+                .core
+                .process(telemetry_connection_settings_offer(b"hash"))
+                .unwrap();
+        }
+        assert!(logs_contain(
+            "connection settings offer rejected by the agent"
+        ));
+    }
+
+    #[test]
+    fn state_store_persists_and_restores_connection_settings_hash() {
+        let store = InMemoryStateStore::default();
+        for surfaced in [true, false] {
+            let mut mock_callbacks = MockCallbacksMockall::new();
+            mock_callbacks
+                .expect_on_message()
+                .once()
+                .withf(move |data| data.own_metrics.is_some() == surfaced)
+                .return_const(());
+
+            let (pending_msg, _) = Notifier::new("msg".to_string());
+            let mut client = OpAMPHttpClient::new(
+                mock_callbacks,
+                reports_own_telemetry(),
+                drop_only_http_mock(),
+                pending_msg,
+            )
+            .unwrap();
+            client.set_state_store(store.clone()).unwrap();
+
+            // After the restart, the offer sent again is recognized as accepted
+            client
+                .core
+                .process(telemetry_connection_settings_offer(b"hash"))
+                .unwrap();
+            assert_eq!(
+                store.stored().unwrap().connection_settings_hash,
+                Some(b"hash".to_vec())
+            );
+        }
+    }
+
+    fn handles_remote_config() -> StartSettings {
+        StartSettings {
+            capabilities: capabilities!(
+                AgentCapabilities::AcceptsRemoteConfig,
+                AgentCapabilities::ReportsRemoteConfig,
+                AgentCapabilities::ReportsEffectiveConfig
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn remote_config_offer() -> ServerToAgent {
+        ServerToAgent {
+            remote_config: Some(AgentRemoteConfig {
+                config_hash: b"hash".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::applied(Ok(()), true, RemoteConfigStatuses::Applied, "", true)]
+    #[case::applied_without_effective_config(
+        Ok(()),
+        false,
+        RemoteConfigStatuses::Applied,
+        "",
+        false
+    )]
+    #[case::failed(
+        Err(RemoteConfigError("invalid config".to_string())),
+        true,
+        RemoteConfigStatuses::Failed,
+        "invalid config",
+        false
+    )]
+    fn remote_config_handler_reports_status(
+        #[case] apply_result: Result<(), RemoteConfigError>,
+        #[case] update_effective_config: bool,
+        #[case] expected_status: RemoteConfigStatuses,
+        #[case] expected_error_message: &str,
+        #[case] effective_config_sent: bool,
+    ) {
+        let mut handler = MockRemoteConfigHandlerMockall::new();
+        handler
+            .expect_apply()
+            .once()
+            .withf(|config| config.config_hash == b"hash")
+            .return_const(apply_result);
+        handler
+            .expect_update_effective_config()
+            .return_const(update_effective_config);
+
+        // Every response offers the remote config, the received messages are kept in order.
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.expect_post().times(4).returning({
+            let received = received.clone();
+            move |body| {
+                received
+                    .lock()
+                    .unwrap()
+                    .push(AgentToServer::decode(body.as_slice()).unwrap());
+                Ok(response_from_server_to_agent(
+                    &remote_config_offer(),
+                    ResponseParts::default(),
+                ))
+            }
+        });
+
+        // The remote config is not surfaced through `on_message` when there is a handler
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_connect().times(3).return_const(());
+        mock_callbacks
+            .expect_on_message()
+            .times(3)
+            .withf(|data| data.remote_config.is_none())
+            .return_const(());
+        if effective_config_sent {
+            mock_callbacks.should_get_effective_config();
+        } else {
+            mock_callbacks.should_not_get_effective_config();
+        }
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            mock_callbacks,
+            handles_remote_config(),
+            mock_client,
+            pending_msg,
+        )
+        .unwrap();
+        client.set_remote_config_handler(handler);
+
+        // The remote config is received, sent as `Applying`, applied and its final status sent
+        client.poll().unwrap();
+        client.poll().unwrap();
+        client.poll().unwrap();
+        drop(client);
+
+        let received = received.lock().unwrap();
+        let statuses = received
+            .iter()
+            .map(|msg| msg.remote_config_status.clone())
+            .collect::<Vec<_>>();
+        let status = |status: RemoteConfigStatuses, error_message: &str| {
+            Some(RemoteConfigStatus {
+                last_remote_config_hash: b"hash".to_vec(),
+                status: status as i32,
+                error_message: error_message.to_string(),
+            })
+        };
+        assert_eq!(
+            statuses,
+            vec![
+                None,
+                status(RemoteConfigStatuses::Applying, ""),
+                status(expected_status, expected_error_message),
+                // The same remote config is not applied again
+                None,
+            ]
+        );
+        assert_eq!(
+            received[2].effective_config.is_some(),
+            effective_config_sent
+        );
+    }
+
+    #[rstest]
+    #[case::applied(RemoteConfigStatuses::Applied, false)]
+    #[case::failed(RemoteConfigStatuses::Failed, false)]
+    #[case::interrupted_while_applying(RemoteConfigStatuses::Applying, true)]
+    fn remote_config_handler_after_restart(
+        #[case] stored_status: RemoteConfigStatuses,
+        #[case] applied_again: bool,
+    ) {
+        let store = InMemoryStateStore::default();
+        store
+            .store(&PersistedState {
+                remote_config_hash: (!applied_again).then(|| b"hash".to_vec()),
+                remote_config_status: Some(RemoteConfigStatus {
+                    last_remote_config_hash: b"hash".to_vec(),
+                    status: stored_status as i32,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut handler = MockRemoteConfigHandlerMockall::new();
+        handler
+            .expect_apply()
+            .times(usize::from(applied_again))
+            .return_const(Ok(()));
+        handler.expect_update_effective_config().return_const(false);
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_message().once().return_const(());
+
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client = OpAMPHttpClient::new(
+            mock_callbacks,
+            handles_remote_config(),
+            drop_only_http_mock(),
+            pending_msg,
+        )
+        .unwrap();
+        client.set_state_store(store.clone()).unwrap();
+        client.set_remote_config_handler(handler);
+
+        client.core.process(remote_config_offer()).unwrap();
+        client.core.pop_message().unwrap();
+        client.core.apply_sent_remote_config().unwrap();
+        let expected_status = if applied_again {
+            RemoteConfigStatuses::Applied
+        } else {
+            stored_status
+        };
+        assert_eq!(
+            store.stored().unwrap().remote_config_status.unwrap().status,
+            expected_status as i32
+        );
+    }
+
+    #[test]
+    fn remote_config_handler_without_reporting_status() {
+        let mut handler = MockRemoteConfigHandlerMockall::new();
+        handler.expect_apply().once().return_const(Ok(()));
+        handler.expect_update_effective_config().return_const(true);
+
+        let mut mock_callbacks = MockCallbacksMockall::new();
+        mock_callbacks.expect_on_message().times(2).return_const(());
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::AcceptsRemoteConfig),
+            ..Default::default()
+        };
+        let store = InMemoryStateStore::default();
+        let (pending_msg, _) = Notifier::new("msg".to_string());
+        let mut client =
+            OpAMPHttpClient::new(mock_callbacks, settings, drop_only_http_mock(), pending_msg)
+                .unwrap();
+        client.set_state_store(store.clone()).unwrap();
+        client.set_remote_config_handler(handler);
+
+        // The same remote config is not applied again, even if no status is reported
+        client.core.process(remote_config_offer()).unwrap();
+        client.core.process(remote_config_offer()).unwrap();
+
+        let stored = store.stored().unwrap();
+        assert_eq!(stored.remote_config_hash, Some(b"hash".to_vec()));
+        assert_eq!(stored.remote_config_status, None);
+    }
 }
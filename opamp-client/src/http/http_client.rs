@@ -1,7 +1,16 @@
 //! # Synchronous HTTP Client Module
-use http::Response;
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+use std::marker::PhantomData;
+use std::time::Duration;
 
-use crate::common::compression::{CompressorError, DecoderError, EncoderError};
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+use http::HeaderMap;
+use http::{Request, Response};
+
+use crate::{
+    common::compression::{CompressorError, DecoderError, EncoderError},
+    opamp::proto::OpAmpConnectionSettings,
+};
 
 /// An enumeration of potential errors related to the HTTP client.
 #[derive(thiserror::Error, Debug)]
@@ -12,6 +21,14 @@ pub enum HttpClientError {
     /// Unsuccessful HTTP response.
     #[error("status code: '{0}' canonical reason: '{1}'")]
     UnsuccessfulResponse(u16, String),
+    /// The server is throttling requests (HTTP 429 or 503).
+    #[error("status code: '{status}' server is throttling requests, retry after: {retry_after:?}")]
+    Throttled {
+        /// Status code of the response.
+        status: u16,
+        /// Delay requested by the server through the `Retry-After` header, if any.
+        retry_after: Option<Duration>,
+    },
     /// Represents a decode error.
     #[error("decoding server response: `{source}`. response body: `{body}`")]
     DecoderError {
@@ -30,12 +47,197 @@ pub enum HttpClientError {
     /// Represents an http crate consume body error.
     #[error("{0}")]
     HTTPBodyError(String),
+    /// Error acquiring the lock of the HTTP client.
+    #[error("acquiring read-write lock")]
+    PoisonError,
 }
 
+/// Request sent to the OpAMP server. It includes the method, the headers set by the client (such
+/// as `Content-Type`, `Content-Encoding` and `Accept-Encoding`) and the encoded message as body.
+/// The URI is left unset, as the endpoint is known by the [`HttpClient`] implementation.
+pub type HttpRequest = Request<Vec<u8>>;
+
 /// A synchronous trait that defines the internal methods for HTTP clients.
 pub trait HttpClient {
     /// A synchronous function that defines the `post` method for HTTP client.
     fn post(&self, body: Vec<u8>) -> Result<Response<Vec<u8>>, HttpClientError>;
+
+    /// Sends the provided [`HttpRequest`] to the server. The default implementation discards
+    /// everything but the body and calls [`post`](HttpClient::post). Implementations sending the
+    /// request headers should override it along with [`supports_headers`](HttpClient::supports_headers).
+    fn send(&self, request: HttpRequest) -> Result<Response<Vec<u8>>, HttpClientError> {
+        self.post(request.into_body())
+    }
+
+    /// Returns whether [`send`](HttpClient::send) includes the request headers. Requests are only
+    /// compressed if it does, since the server cannot decode them without the `Content-Encoding`
+    /// header. Defaults to `false`.
+    fn supports_headers(&self) -> bool {
+        false
+    }
+}
+
+/// Builds the [`HttpClient`] used to reach the OpAMP server from the [`OpAmpConnectionSettings`]
+/// offered by the server.
+///
+/// The built client must send the requests to the `destination_endpoint`, including the offered
+/// `headers` and using the TLS `certificate` if any. It is used to verify the offered settings
+/// and, if the server can be reached, it replaces the current client.
+///
+/// It is implemented for any `Fn(&OpAmpConnectionSettings) -> Result<impl HttpClient, HttpClientError>`.
+pub trait HttpClientFactory {
+    /// The type of the built client.
+    type Client: HttpClient;
+
+    /// Builds a new client from the provided settings.
+    fn build(&self, settings: &OpAmpConnectionSettings) -> Result<Self::Client, HttpClientError>;
+}
+
+impl<F, C> HttpClientFactory for F
+where
+    F: Fn(&OpAmpConnectionSettings) -> Result<C, HttpClientError>,
+    C: HttpClient,
+{
+    type Client = C;
+
+    fn build(&self, settings: &OpAmpConnectionSettings) -> Result<Self::Client, HttpClientError> {
+        self(settings)
+    }
+}
+
+/// Default timeout of the requests sent to the server.
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Options used to build the [`HttpClient`] implementations provided by the `reqwest` and `ureq`
+/// features, `C` being the built client. Use the aliases exported by each of them, such as
+/// `ReqwestHttpClientConfig`.
+///
+/// It also implements [`HttpClientFactory`], building clients from the OpAMP connection settings
+/// offered by the server on top of these options: the offered endpoint replaces the configured
+/// one, the offered headers are added to the configured ones and the offered certificate is used
+/// as client certificate.
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig<C> {
+    pub(super) endpoint: String,
+    pub(super) headers: HeaderMap,
+    pub(super) timeout: Duration,
+    pub(super) proxy: Option<String>,
+    pub(super) ca_certificates: Vec<Vec<u8>>,
+    pub(super) client_certificate: Option<Vec<u8>>,
+    client: PhantomData<fn() -> C>,
+}
+
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+impl<C> HttpClientConfig<C> {
+    /// Returns the default options to reach the OpAMP server at the provided endpoint, such as
+    /// `https://example.com/v1/opamp`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            headers: HeaderMap::default(),
+            timeout: DEFAULT_TIMEOUT,
+            proxy: None,
+            ca_certificates: Vec::default(),
+            client_certificate: None,
+            client: PhantomData,
+        }
+    }
+
+    /// Returns a new instance which includes the provided headers in every request.
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        Self { headers, ..self }
+    }
+
+    /// Returns a new instance with the provided timeout for the requests, which applies from
+    /// the moment the connection starts until the response body is read.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Returns a new instance which sends the requests through the proxy with the provided URL.
+    /// Otherwise, the proxy defined by the environment variables (such as `HTTPS_PROXY`) is used.
+    pub fn with_proxy(self, proxy: impl Into<String>) -> Self {
+        Self {
+            proxy: Some(proxy.into()),
+            ..self
+        }
+    }
+
+    /// Returns a new instance which trusts the provided PEM-encoded CA certificates. The PEM may
+    /// contain several certificates.
+    ///
+    /// The `reqwest` client trusts them besides the system ones. The `ureq` client only trusts
+    /// the provided ones once any is set, and the Mozilla root certificates bundled with `ureq`
+    /// otherwise.
+    pub fn with_ca_certificate(mut self, pem: Vec<u8>) -> Self {
+        self.ca_certificates.push(pem);
+        self
+    }
+
+    /// Returns a new instance which authenticates with the provided PEM-encoded client
+    /// certificate and private key.
+    pub fn with_client_certificate(
+        self,
+        certificate_pem: Vec<u8>,
+        private_key_pem: Vec<u8>,
+    ) -> Self {
+        Self {
+            client_certificate: Some([certificate_pem, private_key_pem].join(&b'\n')),
+            ..self
+        }
+    }
+
+    /// Returns a new instance with the provided OpAMP connection settings applied.
+    pub(super) fn with_opamp_connection_settings(
+        mut self,
+        settings: &OpAmpConnectionSettings,
+    ) -> Result<Self, HttpClientError> {
+        if !settings.destination_endpoint.is_empty() {
+            self.endpoint = settings.destination_endpoint.clone();
+        }
+        for (name, value) in offered_headers(settings)? {
+            self.headers.insert(name, value);
+        }
+        // The CA of the offered certificate is not trusted, as recommended by the specification.
+        if let Some(certificate) = settings
+            .certificate
+            .as_ref()
+            .filter(|certificate| !certificate.public_key.is_empty())
+        {
+            self = self.with_client_certificate(
+                certificate.public_key.clone(),
+                certificate.private_key.clone(),
+            );
+        }
+        Ok(self)
+    }
+}
+
+/// Returns the headers offered in the [`OpAmpConnectionSettings`], failing if any of them is not
+/// a valid HTTP header.
+#[cfg(any(feature = "reqwest", feature = "ureq"))]
+fn offered_headers(
+    settings: &OpAmpConnectionSettings,
+) -> Result<Vec<(http::HeaderName, http::HeaderValue)>, HttpClientError> {
+    settings
+        .headers
+        .iter()
+        .flat_map(|headers| &headers.headers)
+        .map(|header| {
+            let invalid = |err: &dyn std::error::Error| {
+                HttpClientError::TransportError(format!(
+                    "invalid offered header `{}`: {err}",
+                    header.key
+                ))
+            };
+            Ok((
+                http::HeaderName::try_from(header.key.as_str()).map_err(|err| invalid(&err))?,
+                http::HeaderValue::try_from(header.value.as_str()).map_err(|err| invalid(&err))?,
+            ))
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -91,10 +293,21 @@ pub(crate) mod tests {
     }
     impl HttpClient for HttpClientImpl {
         fn post(&self, body: Vec<u8>) -> Result<Response<Vec<u8>>, HttpClientError> {
+            self.send(HttpRequest::new(body))
+        }
+
+        fn supports_headers(&self) -> bool {
+            true
+        }
+
+        fn send(&self, request: HttpRequest) -> Result<Response<Vec<u8>>, HttpClientError> {
+            let (parts, body) = request.into_parts();
+            let mut headers = self.headers.clone();
+            headers.extend(parts.headers);
             let request = self
                 .client
                 .request(Method::POST, self.url.clone())
-                .headers(self.headers.clone())
+                .headers(headers)
                 .body(body)
                 .build()
                 .unwrap();
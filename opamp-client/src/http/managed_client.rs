@@ -1,44 +1,55 @@
 //! Implementation of the [`NotStartedClient`] and [`StartedClient`] traits for OpAMP
 
-use crossbeam::channel::{Receiver, Sender, TrySendError, bounded, select_biased, tick};
+use crossbeam::channel::{Receiver, after, never, select_biased, tick};
 use std::{
     sync::Arc,
     thread::{JoinHandle, sleep, spawn},
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tracing::{debug, error, info_span, instrument, trace, warn};
+use tracing::{debug, error, info, info_span, instrument, warn};
 
 use crate::{
     Client, ClientResult, NotStartedClient, NotStartedClientResult,
-    operation::{callbacks::Callbacks, settings::StartSettings},
+    common::{heartbeat::SharedHeartbeatInterval, notifier::Notifier},
+    operation::{
+        callbacks::Callbacks, capabilities::ServerCapabilities,
+        connection_settings::ConnectionSettingsHandler, instance_uid::SharedInstanceUid,
+        remote_config::RemoteConfigHandler, settings::StartSettings, state_store::StateStore,
+    },
 };
 use crate::{
     StartedClient, StartedClientError, StartedClientResult,
-    opamp::proto::{CustomCapabilities, RemoteConfigStatus},
+    opamp::proto::{
+        AvailableComponents, CustomCapabilities, CustomMessage, PackageStatuses, RemoteConfigStatus,
+    },
 };
 
 use super::{
+    backoff::{Backoff, BackoffPolicy},
     client::{OpAMPHttpClient, UnManagedClient},
-    http_client::HttpClient,
+    http_client::{HttpClient, HttpClientFactory},
+    sender::RequestCompression,
 };
 
 // Default and minimum interval for OpAMP
-const DEFAULT_POLLING_INTERVAL: Duration = Duration::from_secs(30);
+pub(super) const DEFAULT_POLLING_INTERVAL: Duration = Duration::from_secs(30);
 const MINIMUM_POLLING_INTERVAL: Duration = Duration::from_secs(1);
 // Minimum time between polls in case of multiple notifications too close to each other
-const DEFAULT_MINIMUM_DURATION_BETWEEN_POLL: Duration = Duration::from_secs(5);
+pub(super) const DEFAULT_MINIMUM_DURATION_BETWEEN_POLL: Duration = Duration::from_secs(5);
 
 /// Implements the [`NotStartedClient`] trait for HTTP.
 pub struct NotStartedHttpClient<C>
 where
     C: UnManagedClient,
 {
-    opamp_client: Arc<C>,
+    opamp_client: C,
     poll_interval: Duration,
     min_duration_between_poll: Duration,
     has_pending_msg: Receiver<()>,
-    instance_uid: String,
+    instance_uid: SharedInstanceUid,
+    heartbeat_interval: SharedHeartbeatInterval,
     perform_startup_check: bool,
+    backoff_policy: BackoffPolicy,
 }
 /// An [`HttpClient`] that frequently polls for OpAMP remote updates in a background thread
 /// using HTTP transport for connections.
@@ -70,14 +81,10 @@ where
     ) -> NotStartedClientResult<Self> {
         let (pending_msg_notifier, has_pending_msg) = Notifier::new("pending_msg".to_string());
 
-        let instance_uid = start_settings.instance_uid.to_string();
-
-        let opamp_client: Arc<OpAMPHttpClient<CB, HC>> = Arc::new(OpAMPHttpClient::new(
-            callbacks,
-            start_settings,
-            http_client,
-            pending_msg_notifier,
-        )?);
+        let opamp_client =
+            OpAMPHttpClient::new(callbacks, start_settings, http_client, pending_msg_notifier)?;
+        let instance_uid = opamp_client.instance_uid();
+        let heartbeat_interval = opamp_client.heartbeat_interval();
 
         Ok(Self {
             opamp_client,
@@ -85,34 +92,23 @@ where
             min_duration_between_poll: DEFAULT_MINIMUM_DURATION_BETWEEN_POLL,
             has_pending_msg,
             instance_uid,
+            heartbeat_interval,
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         })
     }
 
     /// Returns a new instance of the [`NotStartedHttpClient`] with the specified interval for polling
     /// if the interval is smaller than default, a warning message will be printed and default
     /// value will be used
+    ///
+    /// If the agent has the `ReportsHeartbeat` capability, the heartbeat interval offered by the
+    /// server in the OpAMP connection settings replaces this interval once received.
     pub fn with_interval(
         self,
         interval: Duration,
     ) -> NotStartedHttpClient<OpAMPHttpClient<CB, HC>> {
-        let interval = if interval.le(&MINIMUM_POLLING_INTERVAL) {
-            warn!(
-                interval = interval.as_secs(),
-                default_inverval = MINIMUM_POLLING_INTERVAL.as_secs(),
-                "polling interval smaller than minimum. Falling back to minimum interval."
-            );
-            MINIMUM_POLLING_INTERVAL
-        } else {
-            interval
-        };
-
-        // make sure that the minimum duration between polls is less than the interval
-        let min_duration_between_poll = if interval.le(&DEFAULT_MINIMUM_DURATION_BETWEEN_POLL) {
-            MINIMUM_POLLING_INTERVAL
-        } else {
-            DEFAULT_MINIMUM_DURATION_BETWEEN_POLL
-        };
+        let (interval, min_duration_between_poll) = polling_intervals(interval);
 
         NotStartedHttpClient {
             poll_interval: interval,
@@ -130,33 +126,76 @@ where
             ..self
         }
     }
-}
 
-/// Allows to notify a receiver based on channels.
-#[derive(Clone, Debug)]
-pub struct Notifier {
-    name: String,
-    sender: Sender<()>,
-}
-impl Notifier {
-    pub fn new(name: String) -> (Self, Receiver<()>) {
-        let (sender, receiver) = bounded::<()>(1);
-        (Self { name, sender }, receiver)
-    }
-    /// Notify the receiver. Prints a warning if the receiver is disconnected.
-    pub fn notify_or_warn(&self) {
-        match self.sender.try_send(()) {
-            Ok(()) => {}
-            // if the channel is full, it means that there is already a notification pending to be read.
-            Err(TrySendError::Full(())) => {
-                trace!(notifier_name = self.name, "channel full, already notified");
-            }
-            Err(TrySendError::Disconnected(())) => {
-                warn!(
-                    notifier_name = self.name,
-                    "notification channel disconnected",
-                );
-            }
+    /// Returns a new instance which compresses the requests sent to the server as defined by the
+    /// provided [`RequestCompression`]. Requests are sent uncompressed if the [`HttpClient`] doesn't
+    /// [support headers](HttpClient::supports_headers), as the `Content-Encoding` header is required.
+    pub fn with_request_compression(mut self, compression: RequestCompression) -> Self {
+        self.opamp_client.set_request_compression(compression);
+        self
+    }
+
+    /// Returns a new instance which applies the OpAMP connection settings offered by the server,
+    /// if the agent has the `AcceptsOpAmpConnectionSettings` capability. Once the offer is accepted
+    /// through [`on_opamp_connection_settings`](Callbacks::on_opamp_connection_settings), the
+    /// provided [`HttpClientFactory`] builds a new [`HttpClient`] from the offered settings. If the
+    /// server can be reached with it, the new client replaces the current one and
+    /// [`on_opamp_connection_settings_accepted`](Callbacks::on_opamp_connection_settings_accepted)
+    /// is called. Otherwise, the current client keeps being used.
+    pub fn with_connection_settings_factory<F>(mut self, factory: F) -> Self
+    where
+        F: HttpClientFactory<Client = HC> + Send + Sync + 'static,
+    {
+        self.opamp_client.set_connection_settings_factory(factory);
+        self
+    }
+
+    /// Returns a new instance which persists the client state in the provided [`StateStore`]. The
+    /// stored state is restored right away: the stored instance uid replaces the one in the
+    /// [`StartSettings`], the stored `RemoteConfigStatus` is reported in the first message and,
+    /// once the [`HttpClientFactory`] is set, the HTTP client is built from the stored OpAMP
+    /// connection settings.
+    pub fn with_state_store<S>(mut self, store: S) -> Self
+    where
+        S: StateStore + 'static,
+    {
+        let _ = self
+            .opamp_client
+            .set_state_store(store)
+            .inspect_err(|err| error!(%err, "restoring the persisted client state"));
+        self
+    }
+
+    /// Returns a new instance which offers the telemetry and other connection settings sent by the
+    /// server to the provided [`ConnectionSettingsHandler`], instead of surfacing them through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`connection_settings`](crate::operation::connection_settings) for details.
+    pub fn with_connection_settings_handler<H>(mut self, handler: H) -> Self
+    where
+        H: ConnectionSettingsHandler + 'static,
+    {
+        self.opamp_client.set_connection_settings_handler(handler);
+        self
+    }
+
+    /// Returns a new instance which applies the remote config offered by the server through the
+    /// provided [`RemoteConfigHandler`], reporting its status, instead of surfacing it through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`remote_config`](crate::operation::remote_config) for details.
+    pub fn with_remote_config_handler<H>(mut self, handler: H) -> Self
+    where
+        H: RemoteConfigHandler + 'static,
+    {
+        self.opamp_client.set_remote_config_handler(handler);
+        self
+    }
+
+    /// Returns a new instance with the provided [`BackoffPolicy`], used to delay the next poll when
+    /// the server is unavailable or cannot be reached.
+    pub fn with_backoff_policy(self, backoff_policy: BackoffPolicy) -> Self {
+        Self {
+            backoff_policy,
+            ..self
         }
     }
 }
@@ -171,24 +210,44 @@ where
     fn start(self) -> NotStartedClientResult<Self::StartedClient> {
         // use poll method to send an initial message
         debug!("sending first AgentToServer message");
-        if let Err(err) = self.opamp_client.poll() {
-            if self.perform_startup_check {
-                return Err(err.into());
+        let opamp_client = Arc::new(self.opamp_client);
+        let first_poll = match opamp_client.poll() {
+            Err(err) if self.perform_startup_check => return Err(err.into()),
+            result => {
+                result.inspect_err(|err| error!(%err, "error sending first AgentToServer message"))
             }
-            error!(%err, "error sending first AgentToServer message");
-        }
+        };
 
         let (shutdown_notifier, exit) = Notifier::new("shut_down".to_string());
 
         let handle = spawn({
-            let opamp_client = self.opamp_client.clone();
-            let mut status_report_ticker = tick(self.poll_interval);
+            let opamp_client = opamp_client.clone();
+            let mut backoff = Backoff::new(self.backoff_policy);
+            let mut intervals = PollIntervals {
+                poll_interval: self.poll_interval,
+                min_duration_between_poll: self.min_duration_between_poll,
+                heartbeat_interval: None,
+            };
+            intervals.update_heartbeat(&self.heartbeat_interval);
+            let mut status_report_ticker = next_ticker(
+                &mut backoff,
+                &first_poll,
+                tick(intervals.poll_interval),
+                intervals.poll_interval,
+            );
             move || {
                 loop {
                     // We are in a separated thread so we create another span for each opamp
                     // cycle. This will trace each cycle in a separate span and will propagate the
                     // instance_uid
                     let _guard = info_span!("opamp", instance_uid = %self.instance_uid).entered();
+                    // Pending messages wait for the backoff delay too, they will be included in
+                    // the next poll anyway.
+                    let has_pending_msg = if backoff.is_active() {
+                        never()
+                    } else {
+                        self.has_pending_msg.clone()
+                    };
                     // Create a span to track the time the opamp cycle is waiting
                     let _guard_waiting = info_span!("waiting").entered();
                     select_biased! {
@@ -197,22 +256,28 @@ where
                             debug!("gracefully shutting down the polling task");
                             break;
                         }
-                        recv(self.has_pending_msg) -> res => {
+                        recv(has_pending_msg) -> res => {
                             _guard_waiting.exit();
                             if let Err(err) = res {
                                 error!(%err, "pending message channel error");
                                 break;
                             }
                             debug!("sending requested AgentToServer message");
-                            let _ = opamp_client
+                            let result = opamp_client
                                 .poll()
                                 .inspect_err(|err| error!(%err, "error while polling message"));
 
                             // reset the ticker so next status report is sent after the interval
-                            status_report_ticker = tick(self.poll_interval);
+                            intervals.update_heartbeat(&self.heartbeat_interval);
+                            status_report_ticker = next_ticker(
+                                &mut backoff,
+                                &result,
+                                tick(intervals.poll_interval),
+                                intervals.poll_interval,
+                            );
 
                             // wait for the minimum duration between polls
-                            sleep(self.min_duration_between_poll);
+                            sleep(intervals.min_duration_between_poll);
                         }
                         recv(status_report_ticker) -> res => {
                              _guard_waiting.exit();
@@ -220,10 +285,24 @@ where
                                 error!(%err, "poll interval ticker error");
                                 break;
                             }
+                            if backoff.is_active() {
+                                // The retry includes the changes of any message requested meanwhile.
+                                let _ = self.has_pending_msg.try_recv();
+                            }
                             debug!("sending scheduled status report AgentToServer message");
-                            let _ = opamp_client
+                            let result = opamp_client
                                 .poll()
                                 .inspect_err(|err| error!(%err, "error while polling message"));
+
+                            if intervals.update_heartbeat(&self.heartbeat_interval) {
+                                status_report_ticker = tick(intervals.poll_interval);
+                            }
+                            status_report_ticker = next_ticker(
+                                &mut backoff,
+                                &result,
+                                status_report_ticker,
+                                intervals.poll_interval,
+                            );
                         }
                     }
                 }
@@ -232,12 +311,87 @@ where
         });
         Ok(StartedHttpClient {
             handle,
-            opamp_client: self.opamp_client.clone(),
+            opamp_client,
             shutdown_notifier,
         })
     }
 }
 
+// Intervals used by the polling loop, which follow the heartbeat interval requested by the server.
+pub(super) struct PollIntervals {
+    pub(super) poll_interval: Duration,
+    pub(super) min_duration_between_poll: Duration,
+    // Last heartbeat interval applied.
+    pub(super) heartbeat_interval: Option<Duration>,
+}
+
+impl PollIntervals {
+    // Applies the heartbeat interval requested by the server, if it changed since the last call.
+    // Returns whether the polling interval has to be rescheduled.
+    pub(super) fn update_heartbeat(
+        &mut self,
+        heartbeat_interval: &SharedHeartbeatInterval,
+    ) -> bool {
+        let Some(interval) = heartbeat_interval
+            .get()
+            .filter(|interval| Some(*interval) != self.heartbeat_interval)
+        else {
+            return false;
+        };
+        self.heartbeat_interval = Some(interval);
+        info!(
+            ?interval,
+            "polling at the heartbeat interval requested by the server"
+        );
+        (self.poll_interval, self.min_duration_between_poll) = polling_intervals(interval);
+        true
+    }
+}
+
+// Returns the polling interval to use and the minimum duration between polls for it. Intervals
+// smaller than the minimum fall back to the minimum.
+pub(super) fn polling_intervals(interval: Duration) -> (Duration, Duration) {
+    let interval = if interval.le(&MINIMUM_POLLING_INTERVAL) {
+        warn!(
+            interval = interval.as_secs(),
+            default_inverval = MINIMUM_POLLING_INTERVAL.as_secs(),
+            "polling interval smaller than minimum. Falling back to minimum interval."
+        );
+        MINIMUM_POLLING_INTERVAL
+    } else {
+        interval
+    };
+
+    // make sure that the minimum duration between polls is less than the interval
+    let min_duration_between_poll = if interval.le(&DEFAULT_MINIMUM_DURATION_BETWEEN_POLL) {
+        MINIMUM_POLLING_INTERVAL
+    } else {
+        DEFAULT_MINIMUM_DURATION_BETWEEN_POLL
+    };
+
+    (interval, min_duration_between_poll)
+}
+
+// Returns the ticker for the next scheduled poll given the result of the last one. The poll is
+// delayed according to the backoff if it failed because the server is unavailable or cannot be
+// reached, and the regular schedule is restored once the server answers again.
+fn next_ticker(
+    backoff: &mut Backoff,
+    result: &ClientResult<()>,
+    ticker: Receiver<Instant>,
+    poll_interval: Duration,
+) -> Receiver<Instant> {
+    let was_active = backoff.is_active();
+    match backoff.delay_after(result) {
+        Some(delay) => {
+            warn!(?delay, "server unavailable, delaying next poll");
+            after(delay)
+        }
+        None if was_active => tick(poll_interval),
+        None => ticker,
+    }
+}
+
 impl<C> StartedClient for StartedHttpClient<C>
 where
     C: Client,
@@ -274,6 +428,14 @@ where
         self.opamp_client.set_health(health)
     }
 
+    fn set_component_health(
+        &self,
+        path: &[&str],
+        health: crate::opamp::proto::ComponentHealth,
+    ) -> ClientResult<()> {
+        self.opamp_client.set_component_health(path, health)
+    }
+
     // Fetches the current local effective config using
     // get_effective_config callback and sends it to the Server.
     fn update_effective_config(&self) -> ClientResult<()> {
@@ -290,6 +452,26 @@ where
         self.opamp_client
             .set_custom_capabilities(custom_capabilities)
     }
+
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()> {
+        self.opamp_client.set_package_statuses(statuses)
+    }
+
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()> {
+        self.opamp_client.send_custom_message(message)
+    }
+
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()> {
+        self.opamp_client.request_client_certificate(csr)
+    }
+
+    fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>> {
+        self.opamp_client.server_capabilities()
+    }
+
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()> {
+        self.opamp_client.set_available_components(components)
+    }
 }
 
 #[cfg(test)]
@@ -302,6 +484,7 @@ mod tests {
     use crate::opamp::proto::any_value::Value;
     use crate::opamp::proto::{AgentDescription, AnyValue, KeyValue, ServerToAgent};
     use crate::operation::callbacks::tests::MockCallbacksMockall;
+    use crate::operation::instance_uid::InstanceUid;
     use crate::{ClientError, NotStartedClientError};
     use assert_matches::assert_matches;
     use mockall::{Sequence, predicate};
@@ -336,6 +519,7 @@ mod tests {
             DEFAULT_MINIMUM_DURATION_BETWEEN_POLL
         );
         assert!(opamp_client.perform_startup_check);
+        assert_eq!(opamp_client.backoff_policy, BackoffPolicy::default());
 
         // Bigger interval than minimum should be allowed
         let new_interval = MINIMUM_POLLING_INTERVAL.add(DEFAULT_MINIMUM_DURATION_BETWEEN_POLL);
@@ -387,6 +571,17 @@ mod tests {
         .unwrap()
         .with_startup_check_disabled();
         assert!(!opamp_client.perform_startup_check);
+
+        // Custom backoff policy
+        let backoff_policy = BackoffPolicy::default().with_max_interval(Duration::from_secs(60));
+        let opamp_client = NotStartedHttpClient::new(
+            http_mock(),
+            MockCallbacksMockall::new(),
+            StartSettings::default(),
+        )
+        .unwrap()
+        .with_backoff_policy(backoff_policy);
+        assert_eq!(opamp_client.backoff_policy, backoff_policy);
     }
     #[test]
     fn test_first_message_fails() {
@@ -397,12 +592,14 @@ mod tests {
             .returning(|| Err(ClientError::PoisonError));
 
         let err = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap_err();
@@ -418,12 +615,14 @@ mod tests {
             .returning(|| Err(ClientError::PoisonError));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: false,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .expect("Start should not fail");
@@ -456,12 +655,14 @@ mod tests {
             .returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             min_duration_between_poll: Duration::ZERO,
             has_pending_msg,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -487,12 +688,14 @@ mod tests {
             .returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: Duration::from_millis(100),
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -502,6 +705,43 @@ mod tests {
         // Verify that the thread didn't panic
         started_client.stop().unwrap();
     }
+    #[test]
+    fn test_heartbeat_interval_replaces_poll_interval() {
+        let (_pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
+        let heartbeat_interval = SharedHeartbeatInterval::default();
+        let mut opamp_client = MockUnmanagedClientMockall::new();
+
+        // The server requests a heartbeat every second in the first message
+        opamp_client
+            .expect_poll()
+            .times(1 + 2) // first message + 2 heartbeats
+            .returning({
+                let heartbeat_interval = heartbeat_interval.clone();
+                move || {
+                    heartbeat_interval.set(Duration::from_secs(1));
+                    Ok(())
+                }
+            });
+
+        let started_client = NotStartedHttpClient {
+            opamp_client,
+            poll_interval: DISABLE_POLLING,
+            has_pending_msg,
+            min_duration_between_poll: Duration::ZERO,
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval,
+            perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
+        }
+        .start()
+        .unwrap();
+
+        sleep(Duration::from_millis(2500));
+
+        // Verify that the thread didn't panic
+        started_client.stop().unwrap();
+    }
+
     #[test]
     fn test_stop_exit_signal_precedence() {
         let (pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
@@ -517,12 +757,14 @@ mod tests {
         });
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -539,12 +781,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: SENDING_MESSAGE_TIME,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -567,12 +811,14 @@ mod tests {
         opamp_client.expect_poll().times(1 + 3).returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -599,12 +845,14 @@ mod tests {
         });
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -633,12 +881,14 @@ mod tests {
 
         let min_duration_between_poll = SENDING_MESSAGE_TIME.mul(10);
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -661,12 +911,14 @@ mod tests {
         opamp_client.expect_poll().times(1 + 1).returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: Duration::from_millis(100),
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -681,6 +933,90 @@ mod tests {
         started_client.stop().unwrap();
     }
 
+    #[test]
+    fn test_unavailable_server_delays_next_poll() {
+        let (pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
+        let mut opamp_client = MockUnmanagedClientMockall::new();
+        let mut sequence = Sequence::new();
+        // first message
+        opamp_client
+            .expect_poll()
+            .once()
+            .in_sequence(&mut sequence)
+            .returning(|| Ok(()));
+        // the server asks to retry later
+        opamp_client
+            .expect_poll()
+            .once()
+            .in_sequence(&mut sequence)
+            .returning(|| {
+                Err(ClientError::ServerUnavailable(Some(
+                    SENDING_MESSAGE_TIME.mul(3),
+                )))
+            });
+        // retry after the delay provided by the server
+        opamp_client
+            .expect_poll()
+            .once()
+            .in_sequence(&mut sequence)
+            .returning(|| Ok(()));
+
+        let started_client = NotStartedHttpClient {
+            opamp_client,
+            poll_interval: DISABLE_POLLING,
+            min_duration_between_poll: Duration::ZERO,
+            has_pending_msg,
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
+            perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
+        }
+        .start()
+        .unwrap();
+
+        // This poll gets the unavailable response
+        pending_msg_notifier.notify_or_warn();
+        sleep(SENDING_MESSAGE_TIME);
+        // Pending messages wait for the retry
+        pending_msg_notifier.notify_or_warn();
+        sleep(SENDING_MESSAGE_TIME.mul(3));
+
+        // Verify that the thread didn't panic
+        started_client.stop().unwrap();
+    }
+
+    #[test]
+    fn test_connection_failure_backoff() {
+        let (_pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
+        let mut opamp_client = MockUnmanagedClientMockall::new();
+
+        // first message + 2 retries (100ms and 200ms), the third one would happen after 400ms
+        opamp_client
+            .expect_poll()
+            .times(1 + 2)
+            .returning(|| Err(ClientError::ConnectFailedCallback("error".to_string())));
+
+        let started_client = NotStartedHttpClient {
+            opamp_client,
+            poll_interval: DISABLE_POLLING,
+            min_duration_between_poll: Duration::ZERO,
+            has_pending_msg,
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
+            perform_startup_check: false,
+            backoff_policy: BackoffPolicy::default()
+                .with_initial_interval(Duration::from_millis(100))
+                .with_jitter(0.0),
+        }
+        .start()
+        .unwrap();
+
+        sleep(Duration::from_millis(500));
+
+        // Verify that the thread didn't panic
+        started_client.stop().unwrap();
+    }
+
     //    TEST CLIENT METHODS
     #[test]
     fn test_set_health() {
@@ -701,12 +1037,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -735,12 +1073,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -753,6 +1093,44 @@ mod tests {
         started_client.stop().unwrap();
     }
     #[test]
+    fn test_set_package_statuses() {
+        let (_pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
+        let mut opamp_client = MockUnmanagedClientMockall::new();
+
+        let package_statuses = PackageStatuses {
+            error_message: "test".to_string(),
+            ..Default::default()
+        };
+
+        opamp_client
+            .expect_set_package_statuses()
+            .once()
+            .with(predicate::eq(package_statuses.clone()))
+            .returning(move |_| Ok(()));
+        // first message
+        opamp_client.expect_poll().once().returning(|| Ok(()));
+
+        let started_client = NotStartedHttpClient {
+            opamp_client,
+            poll_interval: DISABLE_POLLING,
+            has_pending_msg,
+            min_duration_between_poll: Duration::ZERO,
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
+            perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
+        }
+        .start()
+        .unwrap();
+
+        started_client
+            .set_package_statuses(package_statuses)
+            .unwrap();
+
+        // Verify that the thread didn't panic
+        started_client.stop().unwrap();
+    }
+    #[test]
     fn test_set_custom_capabilities() {
         let (_pending_msg_notifier, has_pending_msg) = Notifier::new("msg".to_string());
         let mut opamp_client = MockUnmanagedClientMockall::new();
@@ -769,12 +1147,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -816,12 +1196,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -850,12 +1232,14 @@ mod tests {
         opamp_client.expect_poll().once().returning(|| Ok(()));
 
         let started_client = NotStartedHttpClient {
-            opamp_client: Arc::new(opamp_client),
+            opamp_client,
             poll_interval: DISABLE_POLLING,
             has_pending_msg,
             min_duration_between_poll: Duration::ZERO,
-            instance_uid: "instance_uid".to_string(),
+            instance_uid: InstanceUid::create().into(),
+            heartbeat_interval: SharedHeartbeatInterval::default(),
             perform_startup_check: true,
+            backoff_policy: BackoffPolicy::default(),
         }
         .start()
         .unwrap();
@@ -1,12 +1,35 @@
 //! # Synchronous HTTP Client implementation.
+//!
+//! An asynchronous client, whose polling loop is a task driven by the `tokio` runtime, is
+//! available behind the `async` feature.
 
+#[cfg(feature = "async")]
+pub mod async_http_client;
+#[cfg(feature = "async")]
+mod async_managed_client;
+mod backoff;
 pub mod client;
 pub mod http_client;
 mod managed_client;
+#[cfg(feature = "reqwest")]
+pub mod reqwest_client;
 mod sender;
+#[cfg(feature = "ureq")]
+pub mod ureq_client;
 
 // export public structs
 pub use {
+    backoff::BackoffPolicy,
     http_client::HttpClientError,
     managed_client::{NotStartedHttpClient, StartedHttpClient},
+    sender::RequestCompression,
 };
+
+#[cfg(feature = "reqwest")]
+pub use reqwest_client::{ReqwestHttpClient, ReqwestHttpClientConfig};
+
+#[cfg(feature = "ureq")]
+pub use ureq_client::{UreqHttpClient, UreqHttpClientConfig};
+
+#[cfg(feature = "async")]
+pub use async_managed_client::{NotStartedAsyncHttpClient, StartedAsyncHttpClient};
@@ -0,0 +1,302 @@
+//! # [`HttpClient`] implementation based on [`reqwest`]'s blocking client.
+
+use http::{HeaderValue, Method, Response, header};
+use reqwest::{
+    Url,
+    blocking::{Client, ClientBuilder},
+    tls::{Certificate, Identity},
+};
+
+use crate::opamp::proto::OpAmpConnectionSettings;
+
+use super::{
+    http_client::{HttpClient, HttpClientConfig, HttpClientError, HttpClientFactory, HttpRequest},
+    sender::PROTOBUF_CONTENT_TYPE,
+};
+
+/// Options used to build a [`ReqwestHttpClient`], see [`HttpClientConfig`].
+pub type ReqwestHttpClientConfig = HttpClientConfig<ReqwestHttpClient>;
+
+impl HttpClientFactory for ReqwestHttpClientConfig {
+    type Client = ReqwestHttpClient;
+
+    fn build(&self, settings: &OpAmpConnectionSettings) -> Result<Self::Client, HttpClientError> {
+        ReqwestHttpClient::new(self.clone().with_opamp_connection_settings(settings)?)
+    }
+}
+
+/// An [`HttpClient`] sending the requests with [`reqwest`]'s blocking client.
+///
+/// The response is returned as received, including its status and headers, so throttling and
+/// compression are handled by the OpAMP client.
+#[derive(Debug, Clone)]
+pub struct ReqwestHttpClient {
+    client: Client,
+    endpoint: Url,
+}
+
+impl ReqwestHttpClient {
+    /// Builds a new client from the provided [`ReqwestHttpClientConfig`]. It fails if any of the
+    /// options is not valid.
+    pub fn new(config: ReqwestHttpClientConfig) -> Result<Self, HttpClientError> {
+        let endpoint = Url::parse(&config.endpoint).map_err(|err| {
+            HttpClientError::TransportError(format!(
+                "invalid endpoint `{}`: {err}",
+                config.endpoint
+            ))
+        })?;
+
+        let mut builder = ClientBuilder::new()
+            .default_headers(config.headers)
+            .timeout(config.timeout);
+        if let Some(proxy) = config.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(build_error)?);
+        }
+        for pem in config.ca_certificates {
+            let certificates = Certificate::from_pem_bundle(&pem).map_err(build_error)?;
+            if certificates.is_empty() {
+                return Err(HttpClientError::TransportError(
+                    "building HTTP client: no CA certificate found in the provided PEM".to_string(),
+                ));
+            }
+            builder = certificates
+                .into_iter()
+                .fold(builder, ClientBuilder::add_root_certificate);
+        }
+        if let Some(pem) = config.client_certificate {
+            builder = builder.identity(Identity::from_pem(&pem).map_err(build_error)?);
+        }
+
+        Ok(Self {
+            client: builder.build().map_err(build_error)?,
+            endpoint,
+        })
+    }
+}
+
+fn build_error(err: reqwest::Error) -> HttpClientError {
+    HttpClientError::TransportError(format!("building HTTP client: {err}"))
+}
+
+impl HttpClient for ReqwestHttpClient {
+    fn post(&self, body: Vec<u8>) -> Result<Response<Vec<u8>>, HttpClientError> {
+        let mut request = HttpRequest::new(body);
+        *request.method_mut() = Method::POST;
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+        );
+        self.send(request)
+    }
+
+    fn supports_headers(&self) -> bool {
+        true
+    }
+
+    fn send(&self, request: HttpRequest) -> Result<Response<Vec<u8>>, HttpClientError> {
+        let (parts, body) = request.into_parts();
+        // The request headers take precedence over the configured ones.
+        let response = self
+            .client
+            .request(parts.method, self.endpoint.clone())
+            .headers(parts.headers)
+            .body(body)
+            .send()
+            .map_err(|err| HttpClientError::TransportError(err.to_string()))?;
+
+        let mut builder = Response::builder().status(response.status());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = response.headers().clone();
+        }
+        let body = response
+            .bytes()
+            .map_err(|err| HttpClientError::HTTPBodyError(err.to_string()))?;
+        builder
+            .body(body.to_vec())
+            .map_err(|err| HttpClientError::HTTPBodyError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use http::{HeaderMap, HeaderName, StatusCode};
+    use httpmock::{Method::POST, MockServer};
+
+    use super::*;
+    use crate::opamp::proto::{Header, Headers, TlsCertificate};
+
+    #[test]
+    fn post_sends_protobuf_with_configured_headers() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v1/opamp")
+                .header("content-type", PROTOBUF_CONTENT_TYPE)
+                .header("api-key", "secret")
+                .body("message");
+            then.status(200).body("response");
+        });
+
+        let client = ReqwestHttpClient::new(
+            ReqwestHttpClientConfig::new(server.url("/v1/opamp")).with_headers(
+                HeaderMap::from_iter([(
+                    HeaderName::from_static("api-key"),
+                    HeaderValue::from_static("secret"),
+                )]),
+            ),
+        )
+        .unwrap();
+
+        let response = client.post(b"message".to_vec()).unwrap();
+
+        mock.assert();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), b"response");
+    }
+
+    #[test]
+    fn send_includes_request_headers_and_keeps_response_parts() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v1/opamp")
+                .header("content-encoding", "gzip")
+                .header("api-key", "secret");
+            then.status(503).header("retry-after", "10");
+        });
+
+        let client = ReqwestHttpClient::new(
+            ReqwestHttpClientConfig::new(server.url("/v1/opamp")).with_headers(
+                HeaderMap::from_iter([(
+                    HeaderName::from_static("api-key"),
+                    HeaderValue::from_static("secret"),
+                )]),
+            ),
+        )
+        .unwrap();
+
+        let mut request = HttpRequest::new(Vec::default());
+        *request.method_mut() = Method::POST;
+        request
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let response = client.send(request).unwrap();
+
+        mock.assert();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "10");
+    }
+
+    #[test]
+    fn request_timeout() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST);
+            then.status(200).delay(Duration::from_secs(1));
+        });
+
+        let client = ReqwestHttpClient::new(
+            ReqwestHttpClientConfig::new(server.url("/v1/opamp"))
+                .with_timeout(Duration::from_millis(100)),
+        )
+        .unwrap();
+
+        assert_matches!(
+            client.post(Vec::default()).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert_matches!(
+            ReqwestHttpClient::new(ReqwestHttpClientConfig::new("not an url")).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+        assert_matches!(
+            ReqwestHttpClient::new(
+                ReqwestHttpClientConfig::new("https://example.com/v1/opamp")
+                    .with_ca_certificate(b"invalid".to_vec())
+            )
+            .unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+        assert_matches!(
+            ReqwestHttpClient::new(
+                ReqwestHttpClientConfig::new("https://example.com/v1/opamp")
+                    .with_client_certificate(b"invalid".to_vec(), b"invalid".to_vec())
+            )
+            .unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+
+    #[test]
+    fn factory_applies_opamp_connection_settings() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v2/opamp")
+                .header("api-key", "secret")
+                .header("authorization", "token");
+            then.status(200);
+        });
+
+        let config = ReqwestHttpClientConfig::new(server.url("/v1/opamp")).with_headers(
+            HeaderMap::from_iter([(
+                HeaderName::from_static("api-key"),
+                HeaderValue::from_static("secret"),
+            )]),
+        );
+        let settings = OpAmpConnectionSettings {
+            destination_endpoint: server.url("/v2/opamp"),
+            headers: Some(Headers {
+                headers: vec![Header {
+                    key: "authorization".to_string(),
+                    value: "token".to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+
+        let client = HttpClientFactory::build(&config, &settings).unwrap();
+        client.post(Vec::default()).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn factory_rejects_invalid_opamp_connection_settings() {
+        let config = ReqwestHttpClientConfig::new("https://example.com/v1/opamp");
+
+        let invalid_header = OpAmpConnectionSettings {
+            headers: Some(Headers {
+                headers: vec![Header {
+                    key: "invalid key".to_string(),
+                    value: "value".to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+        assert_matches!(
+            HttpClientFactory::build(&config, &invalid_header).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+
+        let invalid_certificate = OpAmpConnectionSettings {
+            certificate: Some(TlsCertificate {
+                public_key: b"invalid".to_vec(),
+                private_key: b"invalid".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_matches!(
+            HttpClientFactory::build(&config, &invalid_certificate).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+}
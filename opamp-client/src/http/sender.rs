@@ -1,12 +1,25 @@
-use super::{HttpClientError, http_client::HttpClient};
-use crate::operation::instance_uid::InstanceUid;
+#[cfg(feature = "async")]
+use super::async_http_client::AsyncHttpClient;
+use super::{
+    HttpClientError,
+    http_client::{HttpClient, HttpRequest},
+};
+use crate::operation::instance_uid::SharedInstanceUid;
 use crate::{
     OpampSenderResult,
-    common::compression::{Compressor, decode_message, encode_message},
+    common::compression::{ACCEPT_ENCODING, Compressor, compress, decode_message, encode_message},
     opamp::proto::AgentToServer,
     opamp::proto::ServerToAgent,
 };
-use tracing::instrument;
+use http::{
+    HeaderMap, HeaderValue, Method, Response, StatusCode,
+    header::{self, RETRY_AFTER},
+};
+use std::{
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+use tracing::{instrument, warn};
 
 /// Max characters of the response body to retain in [`HttpClientError::DecoderError`]
 /// when decoding fails. Bounds error-message size — the diagnostic goal
@@ -14,14 +27,68 @@ use tracing::instrument;
 /// ~hundred characters.
 const RESPONSE_BODY_LOG_LIMIT: usize = 200;
 
+/// Content type of the OpAMP messages sent over HTTP.
+pub(super) const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Default minimum size in bytes of the encoded message to be compressed.
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+
+/// Maximum delay accepted from the `Retry-After` header, so a wrong value cannot stall the client
+/// indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Compression applied to the body of the requests sent to the server.
+///
+/// Messages smaller than the configured minimum size are sent uncompressed, since compressing
+/// them doesn't pay off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestCompression {
+    compressor: Compressor,
+    min_size: usize,
+}
+
+impl RequestCompression {
+    /// Compresses the requests with gzip.
+    pub fn gzip() -> Self {
+        Self {
+            compressor: Compressor::Gzip,
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+        }
+    }
+
+    /// Compresses the requests with the zlib format, as defined for the `deflate` HTTP content coding.
+    #[cfg(feature = "deflate")]
+    pub fn deflate() -> Self {
+        Self {
+            compressor: Compressor::Deflate,
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+        }
+    }
+
+    /// Compresses the requests with zstd.
+    #[cfg(feature = "zstd")]
+    pub fn zstd() -> Self {
+        Self {
+            compressor: Compressor::Zstd,
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+        }
+    }
+
+    /// Returns a new instance which only compresses messages whose encoded size is at least
+    /// `min_size` bytes.
+    pub fn with_min_size(self, min_size: usize) -> Self {
+        Self { min_size, ..self }
+    }
+}
+
 // The HttpSender struct holds the necessary components for sending HTTP messages.
 pub struct HttpSender<C>
 where
     C: HttpClient,
 {
-    compressor: Compressor,
-    client: C,
-    instance_uid: InstanceUid,
+    request_compression: Option<RequestCompression>,
+    client: RwLock<C>,
+    instance_uid: SharedInstanceUid,
 }
 
 impl<C> HttpSender<C>
@@ -29,51 +96,189 @@ where
     C: HttpClient,
 {
     // Initializes a new instance of HttpSender with the provided HTTP client.
-    pub(super) fn new(client: C, instance_uid: InstanceUid) -> Self {
+    pub(super) fn new(client: C, instance_uid: SharedInstanceUid) -> Self {
         Self {
-            compressor: Compressor::Plain,
-            client,
+            request_compression: None,
+            client: RwLock::new(client),
             instance_uid,
         }
     }
 
+    // Sets the compression applied to the requests.
+    pub(super) fn set_request_compression(&mut self, compression: RequestCompression) {
+        self.request_compression = Some(compression);
+    }
+
+    // Replaces the HTTP client used to send the messages.
+    pub(super) fn set_client(&self, client: C) -> OpampSenderResult<()> {
+        *self
+            .client
+            .write()
+            .map_err(|_| HttpClientError::PoisonError)? = client;
+        Ok(())
+    }
+
     // Sends an AgentToServer message using the HttpSender and returns an optional ServerToAgent message as a result.
-    #[instrument(name = "post",fields(instance_uid = %self.instance_uid,sequence_number = msg.sequence_num), skip_all)]
     pub(super) fn send(&self, msg: AgentToServer) -> OpampSenderResult<ServerToAgent> {
-        // Serialize the message to bytes
-        let bytes = encode_message(&self.compressor, &msg)?;
-
-        let response = self.client.post(bytes)?;
-
-        // return error if status code is not within 200-299.
-        if !response.status().is_success() {
-            return Err(HttpClientError::UnsuccessfulResponse(
-                response.status().as_u16(),
-                response
-                    .status()
-                    .canonical_reason()
-                    .unwrap_or_default()
-                    .to_string(),
-            ));
+        let client = self
+            .client
+            .read()
+            .map_err(|_| HttpClientError::PoisonError)?;
+        self.send_with(&client, msg)
+    }
+
+    // Sends an AgentToServer message using the provided HTTP client instead of the current one.
+    #[instrument(name = "post",fields(instance_uid = %self.instance_uid,sequence_number = msg.sequence_num), skip_all)]
+    pub(super) fn send_with(
+        &self,
+        client: &C,
+        msg: AgentToServer,
+    ) -> OpampSenderResult<ServerToAgent> {
+        let request_compression = self
+            .request_compression
+            .filter(|_| client.supports_headers());
+        let request = build_request(request_compression, &msg)?;
+        parse_response(client.send(request)?)
+    }
+}
+
+/// Sends the messages through an [`AsyncHttpClient`], the async counterpart of [`HttpSender`].
+#[cfg(feature = "async")]
+pub(super) struct AsyncHttpSender<C>
+where
+    C: AsyncHttpClient,
+{
+    request_compression: Option<RequestCompression>,
+    client: C,
+    instance_uid: SharedInstanceUid,
+}
+
+#[cfg(feature = "async")]
+impl<C> AsyncHttpSender<C>
+where
+    C: AsyncHttpClient + Sync,
+{
+    // Initializes a new instance of AsyncHttpSender with the provided HTTP client.
+    pub(super) fn new(client: C, instance_uid: SharedInstanceUid) -> Self {
+        Self {
+            request_compression: None,
+            client,
+            instance_uid,
         }
+    }
 
-        let compression = match response.headers().get("Content-Encoding") {
-            Some(algorithm) => Compressor::try_from(algorithm.as_ref())?,
-            None => Compressor::Plain,
-        };
+    // Sets the compression applied to the requests.
+    pub(super) fn set_request_compression(&mut self, compression: RequestCompression) {
+        self.request_compression = Some(compression);
+    }
 
-        let body = response.body();
-        let response = decode_message::<ServerToAgent>(&compression, body).map_err(|source| {
-            HttpClientError::DecoderError {
-                body: String::from_utf8_lossy(body)
-                    .chars()
-                    .take(RESPONSE_BODY_LOG_LIMIT)
-                    .collect(),
-                source,
-            }
-        })?;
+    // Sends an AgentToServer message and returns the ServerToAgent message received.
+    #[instrument(name = "post",fields(instance_uid = %self.instance_uid,sequence_number = msg.sequence_num), skip_all)]
+    pub(super) async fn send(&self, msg: AgentToServer) -> OpampSenderResult<ServerToAgent> {
+        let request_compression = self
+            .request_compression
+            .filter(|_| self.client.supports_headers());
+        let request = build_request(request_compression, &msg)?;
+        parse_response(self.client.send(request).await?)
+    }
+}
+
+// Builds the request to send the message, compressing it if it is big enough.
+pub(super) fn build_request(
+    request_compression: Option<RequestCompression>,
+    msg: &AgentToServer,
+) -> OpampSenderResult<HttpRequest> {
+    let mut request = HttpRequest::default();
+    *request.method_mut() = Method::POST;
+    let headers = request.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+    );
+    headers.insert(
+        header::ACCEPT_ENCODING,
+        HeaderValue::from_static(ACCEPT_ENCODING),
+    );
+
+    // Serialize the message to bytes, compressing them if they are big enough
+    let mut bytes = encode_message(&Compressor::Plain, msg)?;
+    if let Some(compression) =
+        request_compression.filter(|compression| bytes.len() >= compression.min_size)
+    {
+        bytes = compress(&compression.compressor, bytes)?;
+        if let Some(encoding) = compression.compressor.content_encoding() {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+    }
+
+    *request.body_mut() = bytes;
+    Ok(request)
+}
+
+// Decodes the message received from the server, reporting unsuccessful responses as errors.
+pub(super) fn parse_response(response: Response<Vec<u8>>) -> OpampSenderResult<ServerToAgent> {
+    // the server asks to slow down, these responses may include the time to wait before retrying.
+    if matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return Err(HttpClientError::Throttled {
+            status: response.status().as_u16(),
+            retry_after: retry_after(response.headers()),
+        });
+    }
+
+    // return error if status code is not within 200-299.
+    if !response.status().is_success() {
+        return Err(HttpClientError::UnsuccessfulResponse(
+            response.status().as_u16(),
+            response
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+        ));
+    }
+
+    let compression = match response.headers().get("Content-Encoding") {
+        Some(algorithm) => Compressor::try_from(algorithm.as_ref())?,
+        None => Compressor::Plain,
+    };
+
+    let body = response.body();
+    let response = decode_message::<ServerToAgent>(&compression, body).map_err(|source| {
+        HttpClientError::DecoderError {
+            body: String::from_utf8_lossy(body)
+                .chars()
+                .take(RESPONSE_BODY_LOG_LIMIT)
+                .collect(),
+            source,
+        }
+    })?;
+
+    Ok(response)
+}
 
-        Ok(response)
+// Parses the `Retry-After` header, which can either contain the number of seconds to wait or
+// the HTTP-date after which the request can be retried.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER));
+    }
+
+    match httpdate::parse_http_date(value) {
+        // a date in the past means the request can be retried right away
+        Ok(date) => Some(
+            date.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+                .min(MAX_RETRY_AFTER),
+        ),
+        Err(err) => {
+            warn!(%err, value, "invalid Retry-After header");
+            None
+        }
     }
 }
 
@@ -86,12 +291,123 @@ mod tests {
     };
     use crate::opamp::proto::{AgentConfigFile, AgentConfigMap, AgentRemoteConfig};
     use crate::opamp::proto::{AgentToServer, ServerToAgent};
-    use http::{HeaderMap, StatusCode};
+    use crate::operation::instance_uid::InstanceUid;
+    use assert_matches::assert_matches;
     use httpmock::prelude::*;
     use prost::Message;
+    use rstest::rstest;
+    use std::cell::RefCell;
     use std::collections::HashMap;
     use url::Url;
 
+    /// Records the sent request.
+    #[derive(Default)]
+    struct RecordingHttpClient {
+        request: RefCell<Option<HttpRequest>>,
+    }
+
+    impl HttpClient for RecordingHttpClient {
+        fn post(&self, _body: Vec<u8>) -> Result<http::Response<Vec<u8>>, HttpClientError> {
+            unreachable!("the sender should send the whole request")
+        }
+
+        fn supports_headers(&self) -> bool {
+            true
+        }
+
+        fn send(&self, request: HttpRequest) -> Result<http::Response<Vec<u8>>, HttpClientError> {
+            *self.request.borrow_mut() = Some(request);
+            Ok(response_from_server_to_agent(
+                &ServerToAgent::default(),
+                ResponseParts::default(),
+            ))
+        }
+    }
+
+    #[rstest]
+    #[case::disabled(None, None)]
+    #[case::below_min_size(Some(RequestCompression::gzip().with_min_size(usize::MAX)), None)]
+    #[case::gzip(Some(RequestCompression::gzip().with_min_size(0)), Some("gzip"))]
+    #[cfg_attr(
+        feature = "deflate",
+        case::deflate(Some(RequestCompression::deflate().with_min_size(0)), Some("deflate"))
+    )]
+    #[cfg_attr(
+        feature = "zstd",
+        case::zstd(Some(RequestCompression::zstd().with_min_size(0)), Some("zstd"))
+    )]
+    fn request_compression(
+        #[case] compression: Option<RequestCompression>,
+        #[case] expected_encoding: Option<&str>,
+    ) {
+        let msg = AgentToServer {
+            sequence_num: 42,
+            ..Default::default()
+        };
+
+        let mut sender =
+            HttpSender::new(RecordingHttpClient::default(), InstanceUid::create().into());
+        if let Some(compression) = compression {
+            sender.set_request_compression(compression);
+        }
+        sender.send(msg.clone()).unwrap();
+
+        let request = sender.client.read().unwrap().request.take().unwrap();
+        assert_eq!(request.method(), http::Method::POST);
+        let (parts, body) = request.into_parts();
+        let headers = parts.headers;
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            PROTOBUF_CONTENT_TYPE
+        );
+        assert_eq!(
+            headers.get(header::ACCEPT_ENCODING).unwrap(),
+            ACCEPT_ENCODING
+        );
+        assert_eq!(
+            headers
+                .get(header::CONTENT_ENCODING)
+                .map(|v| v.to_str().unwrap()),
+            expected_encoding
+        );
+
+        let compressor = match expected_encoding {
+            Some(encoding) => Compressor::try_from(encoding.as_bytes()).unwrap(),
+            None => Compressor::Plain,
+        };
+        assert_eq!(
+            decode_message::<AgentToServer>(&compressor, &body).unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    fn request_not_compressed_without_headers_support() {
+        let msg = AgentToServer {
+            sequence_num: 42,
+            ..Default::default()
+        };
+
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client
+            .expect_post()
+            .once()
+            .withf({
+                let msg = msg.clone();
+                move |body| AgentToServer::decode(body.as_slice()).unwrap() == msg
+            })
+            .returning(|_| {
+                Ok(response_from_server_to_agent(
+                    &ServerToAgent::default(),
+                    ResponseParts::default(),
+                ))
+            });
+
+        let mut sender = HttpSender::new(mock_client, InstanceUid::create().into());
+        sender.set_request_compression(RequestCompression::gzip().with_min_size(0));
+        sender.send(msg).unwrap();
+    }
+
     #[test]
     fn errors_when_unsupported_compression_is_received() {
         let mut mock_client = MockHttpClientMockall::new();
@@ -107,7 +423,7 @@ mod tests {
         ));
 
         let instance_uid = InstanceUid::create();
-        let sender = HttpSender::new(mock_client, instance_uid);
+        let sender = HttpSender::new(mock_client, instance_uid.into());
         let res = sender.send(AgentToServer::default());
         assert!(res.is_err());
 
@@ -133,7 +449,7 @@ mod tests {
         ));
 
         let instance_uid = InstanceUid::create();
-        let sender = HttpSender::new(mock_client, instance_uid);
+        let sender = HttpSender::new(mock_client, instance_uid.into());
         let res = sender.send(AgentToServer::default());
         assert!(res.is_err());
 
@@ -148,6 +464,75 @@ mod tests {
         }
     }
 
+    #[rstest]
+    #[case::too_many_requests(StatusCode::TOO_MANY_REQUESTS)]
+    #[case::service_unavailable(StatusCode::SERVICE_UNAVAILABLE)]
+    fn throttled_response_carries_retry_after(#[case] status: StatusCode) {
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.should_post(response_from_server_to_agent(
+            &ServerToAgent::default(),
+            ResponseParts {
+                status,
+                headers: HashMap::from([("Retry-After".to_string(), "120".to_string())]),
+            },
+        ));
+
+        let sender = HttpSender::new(mock_client, InstanceUid::create().into());
+        let res = sender.send(AgentToServer::default());
+
+        assert_matches!(
+            res.unwrap_err(),
+            HttpClientError::Throttled { status: s, retry_after: Some(delay) }
+                if s == status.as_u16() && delay == Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn throttled_response_without_retry_after() {
+        let mut mock_client = MockHttpClientMockall::new();
+        mock_client.should_post(response_from_server_to_agent(
+            &ServerToAgent::default(),
+            ResponseParts {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                ..Default::default()
+            },
+        ));
+
+        let sender = HttpSender::new(mock_client, InstanceUid::create().into());
+        let res = sender.send(AgentToServer::default());
+
+        assert_matches!(
+            res.unwrap_err(),
+            HttpClientError::Throttled {
+                status: 429,
+                retry_after: None
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::seconds("30", Some(Duration::from_secs(30)))]
+    #[case::seconds_with_spaces(" 5 ", Some(Duration::from_secs(5)))]
+    #[case::seconds_above_max("18446744073709551615", Some(MAX_RETRY_AFTER))]
+    #[case::future_date("Fri, 31 Dec 9999 23:59:59 GMT", Some(MAX_RETRY_AFTER))]
+    #[case::past_date("Wed, 21 Oct 2015 07:28:00 GMT", Some(Duration::ZERO))]
+    #[case::invalid("soon", None)]
+    #[case::negative_seconds("-1", None)]
+    fn parse_retry_after(#[case] value: &str, #[case] expected: Option<Duration>) {
+        let headers = HeaderMap::from_iter([(RETRY_AFTER, value.parse().unwrap())]);
+        assert_eq!(retry_after(&headers), expected);
+    }
+
+    #[test]
+    fn parse_retry_after_future_date() {
+        let date = SystemTime::now() + Duration::from_secs(3600);
+        let headers =
+            HeaderMap::from_iter([(RETRY_AFTER, httpdate::fmt_http_date(date).parse().unwrap())]);
+
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(3500) && delay <= Duration::from_secs(3600));
+    }
+
     #[test]
     fn assert_message_is_decoded() {
         let mut buf = vec![];
@@ -196,7 +581,7 @@ custom_attributes:
             Url::parse(server.url("/v1/opamp").as_str()).unwrap(),
             headers,
         );
-        let sender = HttpSender::new(http_client, instance_uid);
+        let sender = HttpSender::new(http_client, instance_uid.into());
         let res = sender.send(AgentToServer::default());
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), server_to_agent);
@@ -218,7 +603,7 @@ custom_attributes:
         let mut mock_client = MockHttpClientMockall::new();
         mock_client.should_post(response);
 
-        let sender = HttpSender::new(mock_client, InstanceUid::create());
+        let sender = HttpSender::new(mock_client, InstanceUid::create().into());
         let res = sender.send(AgentToServer::default());
 
         match res.unwrap_err() {
@@ -243,7 +628,7 @@ custom_attributes:
         let mut mock_client = MockHttpClientMockall::new();
         mock_client.should_post(response);
 
-        let sender = HttpSender::new(mock_client, InstanceUid::create());
+        let sender = HttpSender::new(mock_client, InstanceUid::create().into());
         let res = sender.send(AgentToServer::default());
 
         match res.unwrap_err() {
@@ -0,0 +1,332 @@
+//! # [`HttpClient`] implementation based on [`ureq`], for Agents which need a small footprint.
+
+use http::{HeaderMap, HeaderValue, Method, Request, Response, Uri, header};
+use ureq::{
+    Agent, Proxy,
+    tls::{Certificate, ClientCert, PemItem, PrivateKey, RootCerts, TlsConfig, parse_pem},
+};
+
+use crate::opamp::proto::OpAmpConnectionSettings;
+
+use super::{
+    http_client::{HttpClient, HttpClientConfig, HttpClientError, HttpClientFactory, HttpRequest},
+    sender::PROTOBUF_CONTENT_TYPE,
+};
+
+/// Options used to build a [`UreqHttpClient`], see [`HttpClientConfig`].
+pub type UreqHttpClientConfig = HttpClientConfig<UreqHttpClient>;
+
+impl HttpClientFactory for UreqHttpClientConfig {
+    type Client = UreqHttpClient;
+
+    fn build(&self, settings: &OpAmpConnectionSettings) -> Result<Self::Client, HttpClientError> {
+        UreqHttpClient::new(self.clone().with_opamp_connection_settings(settings)?)
+    }
+}
+
+/// An [`HttpClient`] sending the requests with an [`ureq::Agent`].
+///
+/// The response is returned as received, including its status and headers, so throttling and
+/// compression are handled by the OpAMP client.
+#[derive(Debug, Clone)]
+pub struct UreqHttpClient {
+    agent: Agent,
+    endpoint: Uri,
+    headers: HeaderMap,
+}
+
+impl UreqHttpClient {
+    /// Builds a new client from the provided [`UreqHttpClientConfig`]. It fails if any of the
+    /// options is not valid.
+    pub fn new(config: UreqHttpClientConfig) -> Result<Self, HttpClientError> {
+        let endpoint = Uri::try_from(&config.endpoint)
+            .ok()
+            .filter(|uri| uri.scheme().is_some() && uri.authority().is_some())
+            .ok_or_else(|| {
+                HttpClientError::TransportError(format!("invalid endpoint `{}`", config.endpoint))
+            })?;
+
+        let mut tls_config = TlsConfig::builder();
+        if !config.ca_certificates.is_empty() {
+            let mut certificates = Vec::new();
+            for pem in &config.ca_certificates {
+                let (pem_certificates, _) = parse_pem_items(pem)?;
+                if pem_certificates.is_empty() {
+                    return Err(build_error("no CA certificate found in the provided PEM"));
+                }
+                certificates.extend(pem_certificates);
+            }
+            tls_config = tls_config.root_certs(RootCerts::from(certificates));
+        }
+        if let Some(pem) = &config.client_certificate {
+            let (certificates, Some(private_key)) = parse_pem_items(pem)? else {
+                return Err(build_error(
+                    "no private key found in the client certificate PEM",
+                ));
+            };
+            if certificates.is_empty() {
+                return Err(build_error(
+                    "no certificate found in the client certificate PEM",
+                ));
+            }
+            tls_config = tls_config
+                .client_cert(Some(ClientCert::new_with_certs(&certificates, private_key)));
+        }
+
+        let mut agent_config = Agent::config_builder()
+            // Unsuccessful responses are handled by the OpAMP client
+            .http_status_as_error(false)
+            .timeout_global(Some(config.timeout))
+            .tls_config(tls_config.build());
+        if let Some(proxy) = &config.proxy {
+            let proxy = Proxy::new(proxy).map_err(build_error)?;
+            agent_config = agent_config.proxy(Some(proxy));
+        }
+
+        Ok(Self {
+            agent: agent_config.build().new_agent(),
+            endpoint,
+            headers: config.headers,
+        })
+    }
+}
+
+type PemItems = (Vec<Certificate<'static>>, Option<PrivateKey<'static>>);
+
+/// Returns the certificates and the last private key found in the provided PEM.
+fn parse_pem_items(pem: &[u8]) -> Result<PemItems, HttpClientError> {
+    let mut certificates = Vec::new();
+    let mut private_key = None;
+    for item in parse_pem(pem) {
+        match item.map_err(build_error)? {
+            PemItem::Certificate(certificate) => certificates.push(certificate),
+            PemItem::PrivateKey(key) => private_key = Some(key),
+            _ => {}
+        }
+    }
+    Ok((certificates, private_key))
+}
+
+fn build_error(err: impl std::fmt::Display) -> HttpClientError {
+    HttpClientError::TransportError(format!("building HTTP client: {err}"))
+}
+
+impl HttpClient for UreqHttpClient {
+    fn post(&self, body: Vec<u8>) -> Result<Response<Vec<u8>>, HttpClientError> {
+        let mut request = HttpRequest::new(body);
+        *request.method_mut() = Method::POST;
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+        );
+        self.send(request)
+    }
+
+    fn supports_headers(&self) -> bool {
+        true
+    }
+
+    fn send(&self, request: HttpRequest) -> Result<Response<Vec<u8>>, HttpClientError> {
+        let (mut parts, body) = request.into_parts();
+        parts.uri = self.endpoint.clone();
+        // The request headers take precedence over the configured ones.
+        for name in self.headers.keys() {
+            if !parts.headers.contains_key(name) {
+                for value in self.headers.get_all(name) {
+                    parts.headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+
+        let response = self
+            .agent
+            .run(Request::from_parts(parts, body))
+            .map_err(|err| HttpClientError::TransportError(err.to_string()))?;
+
+        let (parts, mut body) = response.into_parts();
+        let body = body
+            .read_to_vec()
+            .map_err(|err| HttpClientError::HTTPBodyError(err.to_string()))?;
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use http::{HeaderName, StatusCode};
+    use httpmock::{Method::POST, MockServer};
+
+    use super::*;
+    use crate::opamp::proto::{Header, Headers, TlsCertificate};
+
+    fn api_key_header() -> HeaderMap {
+        HeaderMap::from_iter([(
+            HeaderName::from_static("api-key"),
+            HeaderValue::from_static("secret"),
+        )])
+    }
+
+    #[test]
+    fn post_sends_protobuf_with_configured_headers() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v1/opamp")
+                .header("content-type", PROTOBUF_CONTENT_TYPE)
+                .header("api-key", "secret")
+                .body("message");
+            then.status(200).body("response");
+        });
+
+        let client = UreqHttpClient::new(
+            UreqHttpClientConfig::new(server.url("/v1/opamp")).with_headers(api_key_header()),
+        )
+        .unwrap();
+
+        let response = client.post(b"message".to_vec()).unwrap();
+
+        mock.assert();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), b"response");
+    }
+
+    #[test]
+    fn send_includes_request_headers_and_keeps_response_parts() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v1/opamp")
+                .header("content-encoding", "gzip")
+                .header("api-key", "override");
+            then.status(503).header("retry-after", "10");
+        });
+
+        let client = UreqHttpClient::new(
+            UreqHttpClientConfig::new(server.url("/v1/opamp")).with_headers(api_key_header()),
+        )
+        .unwrap();
+
+        let mut request = HttpRequest::new(Vec::default());
+        *request.method_mut() = Method::POST;
+        request
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        request
+            .headers_mut()
+            .insert("api-key", HeaderValue::from_static("override"));
+        let response = client.send(request).unwrap();
+
+        mock.assert();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "10");
+    }
+
+    #[test]
+    fn request_timeout() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST);
+            then.status(200).delay(Duration::from_secs(1));
+        });
+
+        let client = UreqHttpClient::new(
+            UreqHttpClientConfig::new(server.url("/v1/opamp"))
+                .with_timeout(Duration::from_millis(100)),
+        )
+        .unwrap();
+
+        assert_matches!(
+            client.post(Vec::default()).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert_matches!(
+            UreqHttpClient::new(UreqHttpClientConfig::new("not an url")).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+        assert_matches!(
+            UreqHttpClient::new(
+                UreqHttpClientConfig::new("https://example.com/v1/opamp")
+                    .with_ca_certificate(b"invalid".to_vec())
+            )
+            .unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+        assert_matches!(
+            UreqHttpClient::new(
+                UreqHttpClientConfig::new("https://example.com/v1/opamp")
+                    .with_client_certificate(b"invalid".to_vec(), b"invalid".to_vec())
+            )
+            .unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+
+    #[test]
+    fn factory_applies_opamp_connection_settings() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/v2/opamp")
+                .header("api-key", "secret")
+                .header("authorization", "token");
+            then.status(200);
+        });
+
+        let config =
+            UreqHttpClientConfig::new(server.url("/v1/opamp")).with_headers(api_key_header());
+        let settings = OpAmpConnectionSettings {
+            destination_endpoint: server.url("/v2/opamp"),
+            headers: Some(Headers {
+                headers: vec![Header {
+                    key: "authorization".to_string(),
+                    value: "token".to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+
+        let client = HttpClientFactory::build(&config, &settings).unwrap();
+        client.post(Vec::default()).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn factory_rejects_invalid_opamp_connection_settings() {
+        let config = UreqHttpClientConfig::new("https://example.com/v1/opamp");
+
+        let invalid_header = OpAmpConnectionSettings {
+            headers: Some(Headers {
+                headers: vec![Header {
+                    key: "invalid key".to_string(),
+                    value: "value".to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+        assert_matches!(
+            HttpClientFactory::build(&config, &invalid_header).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+
+        let invalid_certificate = OpAmpConnectionSettings {
+            certificate: Some(TlsCertificate {
+                public_key: b"invalid".to_vec(),
+                private_key: b"invalid".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_matches!(
+            HttpClientFactory::build(&config, &invalid_certificate).unwrap_err(),
+            HttpClientError::TransportError(_)
+        );
+    }
+}
@@ -5,7 +5,8 @@
 //!
 //! OpAMP allows Agents to report their status to and receive configuration from a Server and to receive agent package updates from the server. The protocol is vendor-agnostic, so the Server can remotely monitor and manage a fleet of different Agents that implement OpAMP, including a fleet of mixed agents from different vendors.
 //!
-//! This crate is an OpAMP client implementation in Rust, using HTTP as transport.
+//! This crate is an OpAMP client implementation in Rust, using HTTP as transport. A WebSocket transport is also
+//! available behind the `websocket` feature.
 //!
 //! ## Getting Started
 //!
@@ -15,6 +16,20 @@
 //!
 //! Calling [`stop`](StartedClient::stop) on this value will shut it down.
 //!
+//! To use WebSocket as transport instead, enable the `websocket` feature and call `ws::NotStartedWsClient::new` with the
+//! server URI, your [`Callbacks`](operation::callbacks::Callbacks) and [`StartSettings`](operation::settings::StartSettings).
+//! The resulting client keeps a connection open, so server updates are received as soon as they are available.
+//!
+//! For `tokio` based Agents, the `async` feature provides `http::NotStartedAsyncHttpClient`, which works with
+//! an `http::async_http_client::AsyncHttpClient` implementation and polls the server in a task spawned on the runtime.
+//! Once started, it is stopped awaiting its `stop` method.
+//!
+//! Instead of implementing [`HttpClient`](http::http_client::HttpClient), the `reqwest` feature provides
+//! `http::ReqwestHttpClient`, built from an `http::ReqwestHttpClientConfig` with the server endpoint, static headers,
+//! timeout, proxy and TLS options. The config is also an [`HttpClientFactory`](http::http_client::HttpClientFactory)
+//! applying the OpAMP connection settings offered by the server.
+//! For Agents which need a small footprint, the `ureq` feature provides the equivalent `http::UreqHttpClient`.
+//!
 //! For more details, please browse the modules of this documentation.
 
 // public exported traits
@@ -30,6 +45,8 @@ pub mod error;
 pub use error::{NotStartedClientError, NotStartedClientResult};
 
 pub mod http;
+#[cfg(feature = "websocket")]
+pub mod ws;
 
 pub mod client;
 pub use client::*;
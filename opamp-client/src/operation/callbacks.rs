@@ -8,8 +8,9 @@ use crate::{
     opamp::proto::{
         AgentIdentification, AgentRemoteConfig, CustomCapabilities, CustomMessage, EffectiveConfig,
         OpAmpConnectionSettings, OtherConnectionSettings, ServerErrorResponse,
-        ServerToAgentCommand, TelemetryConnectionSettings,
+        ServerToAgentCommand, TelemetryConnectionSettings, TlsCertificate,
     },
+    operation::{instance_uid::InstanceUid, packages::PackageSyncer},
 };
 
 /// Structure representing a message received from the server and handled by Callbacks.
@@ -21,9 +22,17 @@ pub struct MessageData {
     /// UpdateEffectiveConfig. [`set_remote_config_status`](crate::client::Client::set_remote_config_status)
     /// and [`update_effective_config`](crate::client::Client::update_effective_config)
     /// may be called from [`on_message`](Callbacks::on_message) handler or after [`on_message`](Callbacks::on_message) returns.
+    ///
+    /// It is not set if a [`RemoteConfigHandler`](crate::operation::remote_config::RemoteConfigHandler)
+    /// was set, as the client applies the remote config and reports its status.
     pub remote_config: Option<AgentRemoteConfig>,
 
     /// Metrics connection settings offered by the Server.
+    ///
+    /// Like the rest of the telemetry and other connection settings, it is only set if the offer
+    /// changed since the last accepted one and no
+    /// [`ConnectionSettingsHandler`](crate::operation::connection_settings::ConnectionSettingsHandler)
+    /// was set, see [`connection_settings`](crate::operation::connection_settings) for details.
     pub own_metrics: Option<TelemetryConnectionSettings>,
     /// Traces connection settings offered by the Server.
     pub own_traces: Option<TelemetryConnectionSettings>,
@@ -37,11 +46,19 @@ pub struct MessageData {
     /// of [`Client`](crate::client::Client).
     pub agent_identification: Option<AgentIdentification>,
 
+    /// The instance uid from the [`agent_identification`](Self::agent_identification), already
+    /// validated and adopted by the client for the next messages.
+    pub instance_uid: Option<InstanceUid>,
+
     /// The capabilities the Server is offering to the Agent.
     pub custom_capabilities: Option<CustomCapabilities>,
 
     /// Custom message received from the Server that the Agent has capability to process.
     pub custom_message: Option<CustomMessage>,
+
+    /// Allows the Agent to synchronize the packages offered by the Server, if the Agent has the
+    /// `AcceptsPackages` capability. See [`PackageSyncer`] for details.
+    pub package_syncer: Option<PackageSyncer>,
 }
 
 /// An interface for the Client to handle messages from the Server.
@@ -83,10 +100,11 @@ pub trait Callbacks {
     /// want to accept the settings (e.g. if the TSL certificate in the settings
     /// cannot be verified).
     ///
-    /// If `on_opamp_connection_settings` returns nil and then the caller will
-    /// attempt to reconnect to the OpAMP Server using the new settings.
-    /// If the connection fails the settings will be rejected and an error will
-    /// be reported to the Server. If the connection succeeds the new settings
+    /// If `on_opamp_connection_settings` returns `Ok` then the caller will
+    /// attempt to reconnect to the OpAMP Server using the new settings, see
+    /// [`with_connection_settings_factory`](crate::http::NotStartedHttpClient::with_connection_settings_factory).
+    /// If the connection fails the settings will be rejected and the client keeps
+    /// using the previous ones. If the connection succeeds the new settings
     /// will be used by the client from that moment on.
     ///
     /// Only one `on_opamp_connection_settings` call can be active at any time.
@@ -102,6 +120,13 @@ pub trait Callbacks {
     /// in the future. Old connection settings should be forgotten.
     fn on_opamp_connection_settings_accepted(&self, settings: &OpAmpConnectionSettings);
 
+    /// This method is called when the Server offers the client certificate requested through
+    /// [`request_client_certificate`](crate::client::Client::request_client_certificate). The
+    /// certificate `public_key` contains the certificate issued from the CSR, the Agent should
+    /// store it along with the private key used to sign the CSR and use them to connect
+    /// from now on. The default implementation ignores the certificate.
+    fn on_client_certificate(&self, _certificate: &TlsCertificate) {}
+
     /// `on_command` is called when the Server requests that the connected Agent perform a command.
     fn on_command(&self, command: &ServerToAgentCommand) -> Result<(), Self::Error>;
 
@@ -134,6 +159,7 @@ pub(crate) mod tests {
             fn on_message(&self, msg: MessageData);
             fn on_opamp_connection_settings(&self,settings: &OpAmpConnectionSettings,) -> Result<(), <Self as Callbacks>::Error>;
             fn on_opamp_connection_settings_accepted(&self, settings: &OpAmpConnectionSettings);
+            fn on_client_certificate(&self, certificate: &TlsCertificate);
             fn on_command(&self, command: &ServerToAgentCommand) -> Result<(), <Self as Callbacks>::Error>;
             fn get_effective_config(&self) -> Result<EffectiveConfig, <Self as Callbacks>::Error>;
       }
@@ -152,6 +178,13 @@ pub(crate) mod tests {
                 .return_const(());
         }
 
+        pub fn should_on_error(&mut self, err: ServerErrorResponse) {
+            self.expect_on_error()
+                .once()
+                .with(predicate::eq(err))
+                .return_const(());
+        }
+
         pub fn should_on_message(&mut self, data: MessageData) {
             self.expect_on_message()
                 .once()
@@ -173,7 +206,6 @@ pub(crate) mod tests {
             self.expect_on_command().never();
         }
 
-        #[allow(dead_code)]
         pub fn should_on_opamp_connection_settings(&mut self, ocs: &OpAmpConnectionSettings) {
             self.expect_on_opamp_connection_settings()
                 .once()
@@ -184,7 +216,6 @@ pub(crate) mod tests {
                 .returning(|_| Ok(()));
         }
 
-        #[allow(dead_code)]
         pub fn should_on_opamp_connection_settings_accepted(
             &mut self,
             ocs: &OpAmpConnectionSettings,
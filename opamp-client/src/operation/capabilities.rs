@@ -1,6 +1,7 @@
-//! Provides an abstraction over the OpAMP [`AgentCapabilities`] protobuffer definition.
+//! Provides an abstraction over the OpAMP [`AgentCapabilities`] and
+//! [`ServerCapabilities`](proto::ServerCapabilities) protobuffer definitions.
 
-use crate::opamp::proto::AgentCapabilities;
+use crate::opamp::proto::{self, AgentCapabilities};
 
 /// A set of capabilities represented as bit flags.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
@@ -86,6 +87,56 @@ impl From<Capabilities> for u64 {
     }
 }
 
+/// A set of the capabilities reported by the Server represented as bit flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerCapabilities(u64);
+
+impl ServerCapabilities {
+    /// Creates a new [`ServerCapabilities`] instance from a vector of
+    /// [`ServerCapabilities`](proto::ServerCapabilities).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use opamp_client::operation::capabilities::ServerCapabilities;
+    /// use opamp_client::opamp::proto;
+    ///
+    /// let caps = ServerCapabilities::new(vec![proto::ServerCapabilities::AcceptsStatus]);
+    /// ```
+    pub fn new(caps: Vec<proto::ServerCapabilities>) -> Self {
+        Self(caps.into_iter().fold(0u64, |c1, c2| c1 | c2 as u64))
+    }
+
+    /// Checks if the Server has a specific capability.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use opamp_client::operation::capabilities::ServerCapabilities;
+    /// use opamp_client::opamp::proto;
+    ///
+    /// let caps = ServerCapabilities::new(vec![proto::ServerCapabilities::AcceptsStatus]);
+    /// assert!(caps.has_capability(proto::ServerCapabilities::AcceptsStatus));
+    /// assert!(!caps.has_capability(proto::ServerCapabilities::OffersRemoteConfig));
+    /// ```
+    pub fn has_capability(self, capability: proto::ServerCapabilities) -> bool {
+        self.0 & capability as u64 != 0
+    }
+}
+
+// `ServerToAgent.capabilities` is `uint64`.
+impl From<u64> for ServerCapabilities {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<ServerCapabilities> for u64 {
+    fn from(value: ServerCapabilities) -> Self {
+        value.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,4 +169,22 @@ mod tests {
         assert_eq!(caps, Capabilities::default());
         assert_eq!(caps, Capabilities(0));
     }
+
+    #[test]
+    fn test_server_capabilities() {
+        let caps = ServerCapabilities::new(vec![
+            proto::ServerCapabilities::AcceptsStatus,
+            proto::ServerCapabilities::AcceptsEffectiveConfig,
+        ]);
+        assert!(caps.has_capability(proto::ServerCapabilities::AcceptsStatus));
+        assert!(caps.has_capability(proto::ServerCapabilities::AcceptsEffectiveConfig));
+        assert!(!caps.has_capability(proto::ServerCapabilities::AcceptsPackagesStatus));
+
+        assert_eq!(u64::from(caps), 5);
+        assert_eq!(ServerCapabilities::from(5), caps);
+        assert_eq!(
+            ServerCapabilities::new(vec![]),
+            ServerCapabilities::default()
+        );
+    }
 }
@@ -0,0 +1,113 @@
+//! Handling of the own telemetry and other connection settings offered by the Server.
+//!
+//! The Server identifies every `ConnectionSettingsOffers` with a hash. The client remembers the
+//! hash of the last offer accepted by the Agent and ignores the telemetry and other connection
+//! settings of the offers with the same hash, so they are only surfaced when they change. The
+//! OpAMP connection settings follow the same rule, using the hash of the last offer whose OpAMP
+//! settings were handled by the transport, even if they could not be applied. If a [`StateStore`](super::state_store::StateStore) is
+//! set, the hashes are persisted and the offers the Server sends again after a restart are
+//! recognized as already accepted.
+//!
+//! By default, the changed settings are surfaced through [`MessageData`](super::callbacks::MessageData)
+//! and considered accepted. Setting a [`ConnectionSettingsHandler`] allows the Agent to accept or
+//! reject the settings of every signal instead.
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::opamp::proto::{OtherConnectionSettings, TelemetryConnectionSettings};
+
+/// Returned by a [`ConnectionSettingsHandler`] to reject the offered connection settings.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("connection settings rejected: `{0}`")]
+pub struct ConnectionSettingsRejected(pub String);
+
+/// Handles the connection settings the Server offers for the telemetry produced by the Agent
+/// itself and for other destinations.
+///
+/// Each method is only called if the Agent has the corresponding capability and the offer
+/// changed since the last accepted one. The hash of the offer is remembered only if all the
+/// settings it includes are accepted, so a rejected offer is handled again when it is received.
+pub trait ConnectionSettingsHandler: Send + Sync {
+    /// Called with the settings offered for the own metrics, if the Agent has the
+    /// `ReportsOwnMetrics` capability.
+    fn on_own_metrics(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected>;
+
+    /// Called with the settings offered for the own traces, if the Agent has the
+    /// `ReportsOwnTraces` capability.
+    fn on_own_traces(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected>;
+
+    /// Called with the settings offered for the own logs, if the Agent has the `ReportsOwnLogs`
+    /// capability.
+    fn on_own_logs(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected>;
+
+    /// Called for every named connection offered by the Server, if the Agent has the
+    /// `AcceptsOtherConnectionSettings` capability.
+    fn on_other_connection_settings(
+        &self,
+        name: &str,
+        settings: &OtherConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected>;
+}
+
+impl<H> ConnectionSettingsHandler for Arc<H>
+where
+    H: ConnectionSettingsHandler + ?Sized,
+{
+    fn on_own_metrics(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected> {
+        (**self).on_own_metrics(settings)
+    }
+
+    fn on_own_traces(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected> {
+        (**self).on_own_traces(settings)
+    }
+
+    fn on_own_logs(
+        &self,
+        settings: &TelemetryConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected> {
+        (**self).on_own_logs(settings)
+    }
+
+    fn on_other_connection_settings(
+        &self,
+        name: &str,
+        settings: &OtherConnectionSettings,
+    ) -> Result<(), ConnectionSettingsRejected> {
+        (**self).on_other_connection_settings(name, settings)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use mockall::mock;
+
+    mock! {
+        pub(crate) ConnectionSettingsHandlerMockall {}
+
+        impl ConnectionSettingsHandler for ConnectionSettingsHandlerMockall {
+            fn on_own_metrics(&self, settings: &TelemetryConnectionSettings) -> Result<(), ConnectionSettingsRejected>;
+            fn on_own_traces(&self, settings: &TelemetryConnectionSettings) -> Result<(), ConnectionSettingsRejected>;
+            fn on_own_logs(&self, settings: &TelemetryConnectionSettings) -> Result<(), ConnectionSettingsRejected>;
+            fn on_other_connection_settings(&self, name: &str, settings: &OtherConnectionSettings) -> Result<(), ConnectionSettingsRejected>;
+        }
+    }
+}
@@ -2,6 +2,7 @@
 //!
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{Display, Formatter};
+use std::sync::{Arc, PoisonError, RwLock};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -77,6 +78,39 @@ impl Display for InstanceUid {
     }
 }
 
+/// An [`InstanceUid`] shared by the components of a client, so all of them switch to a new one at
+/// once when the Server assigns it. Clones share the same underlying value.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedInstanceUid(Arc<RwLock<InstanceUid>>);
+
+impl SharedInstanceUid {
+    /// Returns the current instance uid.
+    pub(crate) fn get(&self) -> InstanceUid {
+        // The value is replaced at once, so it is consistent even if the lock is poisoned.
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the instance uid.
+    pub(crate) fn set(&self, instance_uid: InstanceUid) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = instance_uid;
+    }
+}
+
+impl From<InstanceUid> for SharedInstanceUid {
+    fn from(value: InstanceUid) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+}
+
+impl Display for SharedInstanceUid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,4 +173,18 @@ mod tests {
             test_case.run();
         }
     }
+
+    #[test]
+    fn test_shared_instance_uid() {
+        let instance_uid = InstanceUid::create();
+        let shared = SharedInstanceUid::from(instance_uid.clone());
+        let cloned = shared.clone();
+        assert_eq!(cloned.get(), instance_uid);
+
+        let new_instance_uid = InstanceUid::create();
+        shared.set(new_instance_uid.clone());
+
+        assert_eq!(cloned.get(), new_instance_uid);
+        assert_eq!(cloned.to_string(), new_instance_uid.to_string());
+    }
 }
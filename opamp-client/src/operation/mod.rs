@@ -2,5 +2,9 @@
 
 pub mod callbacks;
 pub mod capabilities;
+pub mod connection_settings;
 pub mod instance_uid;
+pub mod packages;
+pub mod remote_config;
 pub mod settings;
+pub mod state_store;
@@ -0,0 +1,601 @@
+//! Synchronization of the packages offered by the Server.
+//!
+//! See the [OpAMP spec](https://github.com/open-telemetry/opamp-spec/blob/main/specification.md#packages)
+//! for details on how packages are offered and reported.
+
+use std::sync::{Arc, RwLock};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    ClientError, ClientResult,
+    common::{clientstate::ClientSyncedState, nextmessage::NextMessage, notifier::Notifier},
+    opamp::proto::{
+        AgentCapabilities, DownloadableFile, PackageAvailable, PackageStatus, PackageStatusEnum,
+        PackageStatuses, PackagesAvailable,
+    },
+    operation::capabilities::Capabilities,
+};
+
+/// An interface to download the files of the packages offered by the Server.
+pub trait PackageDownloader {
+    /// Associated type to return as downloader error.
+    type Error: std::error::Error;
+
+    /// Downloads the content of the provided [`DownloadableFile`].
+    fn download(&self, file: &DownloadableFile) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// An interface to install the packages offered by the Server and remove the ones it no longer
+/// offers.
+pub trait PackageInstaller {
+    /// Associated type to return as installer error.
+    type Error: std::error::Error;
+
+    /// Installs the provided package, whose file content has been downloaded and verified. The
+    /// error is reported to the Server.
+    fn install(
+        &self,
+        name: &str,
+        package: &PackageAvailable,
+        content: Vec<u8>,
+    ) -> Result<(), Self::Error>;
+
+    /// Removes the package the Agent has that is no longer offered by the Server.
+    fn remove(&self, name: &str) -> Result<(), Self::Error>;
+}
+
+/// Reasons why a package offered by the Server could not be installed. They are reported to the Server
+/// in the `error_message` of the corresponding [`PackageStatus`].
+#[derive(Error, Debug)]
+enum InstallError {
+    #[error("package has no downloadable file")]
+    MissingFile,
+    #[error("downloading package: {0}")]
+    Download(String),
+    #[error("downloaded content does not match the content hash")]
+    ContentHashMismatch,
+    #[error("installing package: {0}")]
+    Install(String),
+}
+
+/// Synchronizes the packages offered by the Server in a [`PackagesAvailable`] message with the
+/// packages the Agent has.
+///
+/// It is handed to the Agent through [`MessageData`](crate::operation::callbacks::MessageData) and
+/// the synchronization only starts when [`sync`](PackageSyncer::sync) is called, so the Agent
+/// decides when (and in which thread) packages are downloaded and installed.
+#[derive(Debug, Clone)]
+pub struct PackageSyncer {
+    packages_available: PackagesAvailable,
+    synced_state: ClientSyncedState,
+    next_message: Arc<RwLock<NextMessage>>,
+    pending_msg: Notifier,
+    capabilities: Capabilities,
+}
+
+// Two syncers are considered equal if they synchronize the same packages.
+impl PartialEq for PackageSyncer {
+    fn eq(&self, other: &Self) -> bool {
+        self.packages_available == other.packages_available
+    }
+}
+
+impl PackageSyncer {
+    pub(crate) fn new(
+        packages_available: PackagesAvailable,
+        synced_state: ClientSyncedState,
+        next_message: Arc<RwLock<NextMessage>>,
+        pending_msg: Notifier,
+        capabilities: Capabilities,
+    ) -> Self {
+        Self {
+            packages_available,
+            synced_state,
+            next_message,
+            pending_msg,
+            capabilities,
+        }
+    }
+
+    /// Returns the packages offered by the Server.
+    pub fn packages_available(&self) -> &PackagesAvailable {
+        &self.packages_available
+    }
+
+    /// Downloads, verifies and installs every offered package the Agent does not have yet, and
+    /// removes the packages the Server no longer offers.
+    ///
+    /// Files are downloaded through the provided [`PackageDownloader`], their content is checked against
+    /// the SHA-256 `content_hash` (when set) and then handed to
+    /// [`PackageInstaller::install`]. The progress of each package is reported to the Server through
+    /// [`PackageStatuses`] if the Agent has the `ReportsPackageStatuses` capability.
+    pub fn sync<D, I>(&self, downloader: &D, installer: &I) -> ClientResult<()>
+    where
+        D: PackageDownloader,
+        I: PackageInstaller,
+    {
+        let mut statuses = self.synced_state.package_statuses()?.unwrap_or_default();
+
+        let removed: Vec<String> = statuses
+            .packages
+            .keys()
+            .filter(|name| !self.packages_available.packages.contains_key(*name))
+            .cloned()
+            .collect();
+
+        let pending: Vec<(&String, &PackageAvailable)> = self
+            .packages_available
+            .packages
+            .iter()
+            .filter(|(name, package)| {
+                statuses
+                    .packages
+                    .get(*name)
+                    .is_none_or(|status| status.agent_has_hash != package.hash)
+            })
+            .collect();
+
+        if pending.is_empty()
+            && removed.is_empty()
+            && statuses.server_provided_all_packages_hash
+                == self.packages_available.all_packages_hash
+        {
+            debug!("packages already synced");
+            return Ok(());
+        }
+
+        statuses
+            .server_provided_all_packages_hash
+            .clone_from(&self.packages_available.all_packages_hash);
+        statuses.error_message.clear();
+        for name in removed {
+            match installer.remove(&name) {
+                Ok(()) => {
+                    statuses.packages.remove(&name);
+                }
+                Err(err) => {
+                    warn!(package = name, %err, "package removal failed");
+                    update_status(&mut statuses, &name, |status| {
+                        status.error_message = format!("removing package: {err}");
+                    });
+                }
+            }
+        }
+        for (name, package) in &pending {
+            let status = statuses
+                .packages
+                .entry(name.to_string())
+                .or_insert_with(|| PackageStatus {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+            status.server_offered_version.clone_from(&package.version);
+            status.server_offered_hash.clone_from(&package.hash);
+            status.status = PackageStatusEnum::InstallPending as i32;
+            status.error_message.clear();
+        }
+        self.report(&statuses)?;
+
+        for (name, package) in pending {
+            update_status(&mut statuses, name, |status| {
+                status.status = PackageStatusEnum::Installing as i32;
+            });
+            self.report(&statuses)?;
+
+            let result = install(name, package, downloader, installer);
+            update_status(&mut statuses, name, |status| match result {
+                Ok(()) => {
+                    status.agent_has_version.clone_from(&package.version);
+                    status.agent_has_hash.clone_from(&package.hash);
+                    status.status = PackageStatusEnum::Installed as i32;
+                }
+                Err(err) => {
+                    warn!(package = name, %err, "package installation failed");
+                    status.status = PackageStatusEnum::InstallFailed as i32;
+                    status.error_message = err.to_string();
+                }
+            });
+            self.report(&statuses)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the provided statuses and sends them to the Server, if the Agent has the
+    /// `ReportsPackageStatuses` capability.
+    fn report(&self, statuses: &PackageStatuses) -> ClientResult<()> {
+        self.synced_state.set_package_statuses(statuses.clone())?;
+        if !self
+            .capabilities
+            .has_capability(AgentCapabilities::ReportsPackageStatuses)
+        {
+            debug!("agent doesn't have the ReportsPackageStatuses capability, not reporting");
+            return Ok(());
+        }
+
+        self.next_message
+            .write()
+            .map_err(|_| ClientError::PoisonError)?
+            .update(|msg| {
+                msg.package_statuses = Some(statuses.clone());
+            });
+
+        debug!("sending AgentToServer with package statuses");
+        self.pending_msg.notify_or_warn();
+        Ok(())
+    }
+}
+
+// Helper to modify the status of an already tracked package.
+fn update_status(statuses: &mut PackageStatuses, name: &str, f: impl FnOnce(&mut PackageStatus)) {
+    if let Some(status) = statuses.packages.get_mut(name) {
+        f(status);
+    }
+}
+
+// Downloads, verifies and installs a single package.
+fn install<D, I>(
+    name: &str,
+    package: &PackageAvailable,
+    downloader: &D,
+    installer: &I,
+) -> Result<(), InstallError>
+where
+    D: PackageDownloader,
+    I: PackageInstaller,
+{
+    let file = package.file.as_ref().ok_or(InstallError::MissingFile)?;
+
+    let content = downloader
+        .download(file)
+        .map_err(|err| InstallError::Download(err.to_string()))?;
+
+    if !file.content_hash.is_empty() && Sha256::digest(&content).as_slice() != file.content_hash {
+        return Err(InstallError::ContentHashMismatch);
+    }
+
+    installer
+        .install(name, package, content)
+        .map_err(|err| InstallError::Install(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mockall::{mock, predicate};
+
+    use super::*;
+    use crate::capabilities;
+    use crate::opamp::proto::AgentToServer;
+    use crate::operation::callbacks::tests::CallbacksMockError;
+
+    mock! {
+        pub(crate) PackageDownloaderMockall {}
+
+        impl PackageDownloader for PackageDownloaderMockall {
+            type Error = CallbacksMockError;
+
+            fn download(&self, file: &DownloadableFile) -> Result<Vec<u8>, CallbacksMockError>;
+        }
+    }
+
+    mock! {
+        pub(crate) PackageInstallerMockall {}
+
+        impl PackageInstaller for PackageInstallerMockall {
+            type Error = CallbacksMockError;
+
+            fn install(&self, name: &str, package: &PackageAvailable, content: Vec<u8>) -> Result<(), CallbacksMockError>;
+            fn remove(&self, name: &str) -> Result<(), CallbacksMockError>;
+        }
+    }
+
+    const CONTENT: &[u8] = b"package content";
+
+    fn package(hash: &[u8], content_hash: Vec<u8>) -> PackageAvailable {
+        PackageAvailable {
+            version: "1.0.0".to_string(),
+            file: Some(DownloadableFile {
+                download_url: "http://localhost/package".to_string(),
+                content_hash,
+                ..Default::default()
+            }),
+            hash: hash.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn syncer(packages: HashMap<String, PackageAvailable>) -> (PackageSyncer, ClientSyncedState) {
+        syncer_with_capabilities(
+            packages,
+            capabilities!(
+                AgentCapabilities::AcceptsPackages,
+                AgentCapabilities::ReportsPackageStatuses
+            ),
+        )
+    }
+
+    fn syncer_with_capabilities(
+        packages: HashMap<String, PackageAvailable>,
+        capabilities: Capabilities,
+    ) -> (PackageSyncer, ClientSyncedState) {
+        let synced_state = ClientSyncedState::default();
+        let (pending_msg, _) = Notifier::new("test".to_string());
+        let syncer = PackageSyncer::new(
+            PackagesAvailable {
+                packages,
+                all_packages_hash: b"all".to_vec(),
+            },
+            synced_state.clone(),
+            Arc::new(RwLock::new(NextMessage::new(AgentToServer::default()))),
+            pending_msg,
+            capabilities,
+        );
+        (syncer, synced_state)
+    }
+
+    fn reported_status(syncer: &PackageSyncer, name: &str) -> PackageStatus {
+        let message = syncer.next_message.write().unwrap().pop();
+        message.package_statuses.unwrap().packages[name].clone()
+    }
+
+    #[test]
+    fn sync_installs_new_package() {
+        let (syncer, synced_state) = syncer(HashMap::from([(
+            "foo".to_string(),
+            package(b"foo-hash", Sha256::digest(CONTENT).to_vec()),
+        )]));
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader
+            .expect_download()
+            .once()
+            .returning(|_| Ok(CONTENT.to_vec()));
+        let mut installer = MockPackageInstallerMockall::new();
+        installer
+            .expect_install()
+            .once()
+            .with(
+                predicate::eq("foo"),
+                predicate::always(),
+                predicate::eq(CONTENT.to_vec()),
+            )
+            .returning(|_, _, _| Ok(()));
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        let expected = PackageStatus {
+            name: "foo".to_string(),
+            agent_has_version: "1.0.0".to_string(),
+            agent_has_hash: b"foo-hash".to_vec(),
+            server_offered_version: "1.0.0".to_string(),
+            server_offered_hash: b"foo-hash".to_vec(),
+            status: PackageStatusEnum::Installed as i32,
+            error_message: String::new(),
+        };
+        assert_eq!(reported_status(&syncer, "foo"), expected);
+
+        let statuses = synced_state.package_statuses().unwrap().unwrap();
+        assert_eq!(statuses.server_provided_all_packages_hash, b"all".to_vec());
+        assert_eq!(statuses.packages["foo"], expected);
+    }
+
+    #[test]
+    fn sync_skips_installed_packages() {
+        let (syncer, synced_state) = syncer(HashMap::from([(
+            "foo".to_string(),
+            package(b"foo-hash", vec![]),
+        )]));
+        synced_state
+            .set_package_statuses(PackageStatuses {
+                packages: HashMap::from([(
+                    "foo".to_string(),
+                    PackageStatus {
+                        name: "foo".to_string(),
+                        agent_has_hash: b"foo-hash".to_vec(),
+                        ..Default::default()
+                    },
+                )]),
+                server_provided_all_packages_hash: b"all".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader.expect_download().never();
+        let mut installer = MockPackageInstallerMockall::new();
+        installer.expect_install().never();
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        assert!(
+            syncer
+                .next_message
+                .write()
+                .unwrap()
+                .pop()
+                .package_statuses
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sync_reports_content_hash_mismatch() {
+        let (syncer, _) = syncer(HashMap::from([(
+            "foo".to_string(),
+            package(b"foo-hash", b"wrong-hash".to_vec()),
+        )]));
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader
+            .expect_download()
+            .once()
+            .returning(|_| Ok(CONTENT.to_vec()));
+        let mut installer = MockPackageInstallerMockall::new();
+        installer.expect_install().never();
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        let status = reported_status(&syncer, "foo");
+        assert_eq!(status.status, PackageStatusEnum::InstallFailed as i32);
+        assert_eq!(
+            status.error_message,
+            "downloaded content does not match the content hash"
+        );
+        assert!(status.agent_has_hash.is_empty());
+    }
+
+    #[test]
+    fn sync_reports_download_error() {
+        let (syncer, _) = syncer(HashMap::from([(
+            "foo".to_string(),
+            package(b"foo-hash", vec![]),
+        )]));
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader
+            .expect_download()
+            .once()
+            .returning(|_| Err(CallbacksMockError));
+        let mut installer = MockPackageInstallerMockall::new();
+        installer.expect_install().never();
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        let status = reported_status(&syncer, "foo");
+        assert_eq!(status.status, PackageStatusEnum::InstallFailed as i32);
+        assert_eq!(
+            status.error_message,
+            "downloading package: callback error mock"
+        );
+    }
+
+    #[test]
+    fn sync_reports_install_error() {
+        let (syncer, _) = syncer(HashMap::from([(
+            "foo".to_string(),
+            package(b"foo-hash", vec![]),
+        )]));
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader
+            .expect_download()
+            .once()
+            .returning(|_| Ok(CONTENT.to_vec()));
+        let mut installer = MockPackageInstallerMockall::new();
+        installer
+            .expect_install()
+            .once()
+            .returning(|_, _, _| Err(CallbacksMockError));
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        let status = reported_status(&syncer, "foo");
+        assert_eq!(status.status, PackageStatusEnum::InstallFailed as i32);
+        assert_eq!(
+            status.error_message,
+            "installing package: callback error mock"
+        );
+    }
+
+    fn installed_status(name: &str) -> PackageStatus {
+        PackageStatus {
+            name: name.to_string(),
+            agent_has_hash: format!("{name}-hash").into_bytes(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sync_removes_packages_no_longer_offered() {
+        let (syncer, synced_state) = syncer(HashMap::new());
+        synced_state
+            .set_package_statuses(PackageStatuses {
+                packages: HashMap::from([
+                    ("foo".to_string(), installed_status("foo")),
+                    ("bar".to_string(), installed_status("bar")),
+                ]),
+                server_provided_all_packages_hash: b"previous".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader.expect_download().never();
+        let mut installer = MockPackageInstallerMockall::new();
+        installer
+            .expect_remove()
+            .with(predicate::eq("foo"))
+            .once()
+            .returning(|_| Ok(()));
+        installer
+            .expect_remove()
+            .with(predicate::eq("bar"))
+            .once()
+            .returning(|_| Err(CallbacksMockError));
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        // The package that could not be removed is still reported
+        let statuses = syncer
+            .next_message
+            .write()
+            .unwrap()
+            .pop()
+            .package_statuses
+            .unwrap();
+        assert_eq!(statuses.server_provided_all_packages_hash, b"all".to_vec());
+        assert_eq!(
+            statuses.packages,
+            HashMap::from([(
+                "bar".to_string(),
+                PackageStatus {
+                    error_message: "removing package: callback error mock".to_string(),
+                    ..installed_status("bar")
+                }
+            )])
+        );
+        assert_eq!(synced_state.package_statuses().unwrap().unwrap(), statuses);
+    }
+
+    #[test]
+    fn sync_does_not_report_without_capability() {
+        let (syncer, synced_state) = syncer_with_capabilities(
+            HashMap::from([("foo".to_string(), package(b"foo-hash", vec![]))]),
+            capabilities!(AgentCapabilities::AcceptsPackages),
+        );
+
+        let mut downloader = MockPackageDownloaderMockall::new();
+        downloader
+            .expect_download()
+            .once()
+            .returning(|_| Ok(CONTENT.to_vec()));
+        let mut installer = MockPackageInstallerMockall::new();
+        installer
+            .expect_install()
+            .once()
+            .returning(|_, _, _| Ok(()));
+
+        syncer.sync(&downloader, &installer).unwrap();
+
+        assert!(
+            syncer
+                .next_message
+                .write()
+                .unwrap()
+                .pop()
+                .package_statuses
+                .is_none()
+        );
+        // The statuses are kept to skip the installed packages
+        let statuses = synced_state.package_statuses().unwrap().unwrap();
+        assert_eq!(
+            statuses.packages["foo"].status,
+            PackageStatusEnum::Installed as i32
+        );
+    }
+}
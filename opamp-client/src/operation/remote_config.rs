@@ -0,0 +1,73 @@
+//! Handling of the remote configuration offered by the Server.
+//!
+//! By default, the remote config is surfaced through
+//! [`MessageData::remote_config`](super::callbacks::MessageData::remote_config) and the Agent is
+//! responsible for reporting its status. Setting a [`RemoteConfigHandler`] makes the client drive
+//! the whole process instead:
+//!
+//! * The remote config is ignored if its hash matches the one of the last config applied or failed
+//!   to be applied, even if its status is not reported. The hash is restored after a restart if a
+//!   [`StateStore`](super::state_store::StateStore) is set.
+//! * The `Applying` status is sent first, and the remote config is applied once the message
+//!   reporting it is sent. Then `Applied` or `Failed` is reported, with the error returned by
+//!   [`apply`](RemoteConfigHandler::apply). The status is only reported if the Agent has the
+//!   `ReportsRemoteConfig` capability, otherwise the remote config is applied right away.
+//! * Once applied, the effective config is updated if the Agent has the `ReportsEffectiveConfig`
+//!   capability, unless [`update_effective_config`](RemoteConfigHandler::update_effective_config)
+//!   returns `false`.
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::opamp::proto::AgentRemoteConfig;
+
+/// Returned by a [`RemoteConfigHandler`] when the remote config cannot be applied. The message is
+/// reported to the Server in the `RemoteConfigStatus`.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{0}")]
+pub struct RemoteConfigError(pub String);
+
+/// Applies the remote config offered by the Server, see the [module](self) documentation for
+/// details.
+pub trait RemoteConfigHandler: Send + Sync {
+    /// Applies the provided remote config. It is only called for the configs which were not
+    /// applied yet, and not called again until it returns.
+    fn apply(&self, config: &AgentRemoteConfig) -> Result<(), RemoteConfigError>;
+
+    /// Returns whether the client updates the effective config through
+    /// [`get_effective_config`](super::callbacks::Callbacks::get_effective_config) once a remote
+    /// config is applied. Defaults to `true`.
+    fn update_effective_config(&self) -> bool {
+        true
+    }
+}
+
+impl<H> RemoteConfigHandler for Arc<H>
+where
+    H: RemoteConfigHandler + ?Sized,
+{
+    fn apply(&self, config: &AgentRemoteConfig) -> Result<(), RemoteConfigError> {
+        (**self).apply(config)
+    }
+
+    fn update_effective_config(&self) -> bool {
+        (**self).update_effective_config()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use mockall::mock;
+
+    mock! {
+        pub(crate) RemoteConfigHandlerMockall {}
+
+        impl RemoteConfigHandler for RemoteConfigHandlerMockall {
+            fn apply(&self, config: &AgentRemoteConfig) -> Result<(), RemoteConfigError>;
+            fn update_effective_config(&self) -> bool;
+        }
+    }
+}
@@ -3,8 +3,8 @@
 use std::collections::HashMap;
 
 use crate::opamp::proto::{
-    AgentDescription as ProtobufAgentDescription, AnyValue, CustomCapabilities, KeyValue,
-    any_value::Value,
+    AgentDescription as ProtobufAgentDescription, AnyValue, ArrayValue, CustomCapabilities,
+    KeyValue, KeyValueList, any_value::Value,
 };
 
 use super::{capabilities::Capabilities, instance_uid::InstanceUid};
@@ -30,8 +30,10 @@ pub enum DescriptionValueType {
     Bool(bool),
     /// [`f64`] type variant
     Float(f64),
-    // Array(Vec<DescType>),
-    // Map(HashMap<String, DescType>),
+    /// Array of values variant
+    Array(Vec<DescriptionValueType>),
+    /// Map of values variant, sent as a list of key-value pairs
+    Map(HashMap<String, DescriptionValueType>),
     /// [`Vec<u8>`] type variant, representing bytes
     Bytes(Vec<u8>),
 }
@@ -61,6 +63,19 @@ impl From<DescriptionValueType> for Option<AnyValue> {
             DescriptionValueType::Bytes(b) => Some(AnyValue {
                 value: Some(Value::BytesValue(b)),
             }),
+            DescriptionValueType::Array(values) => Some(AnyValue {
+                value: Some(Value::ArrayValue(ArrayValue {
+                    values: values
+                        .into_iter()
+                        .filter_map(Option::<AnyValue>::from)
+                        .collect(),
+                })),
+            }),
+            DescriptionValueType::Map(values) => Some(AnyValue {
+                value: Some(Value::KvlistValue(KeyValueList {
+                    values: populate_agent_description(values),
+                })),
+            }),
         }
     }
 }
@@ -101,6 +116,29 @@ impl From<Vec<u8>> for DescriptionValueType {
     }
 }
 
+impl<T> From<Vec<T>> for DescriptionValueType
+where
+    T: Into<DescriptionValueType>,
+{
+    fn from(values: Vec<T>) -> Self {
+        DescriptionValueType::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T> From<HashMap<String, T>> for DescriptionValueType
+where
+    T: Into<DescriptionValueType>,
+{
+    fn from(values: HashMap<String, T>) -> Self {
+        DescriptionValueType::Map(
+            values
+                .into_iter()
+                .map(|(key, value)| (key, value.into()))
+                .collect(),
+        )
+    }
+}
+
 impl From<AgentDescription> for ProtobufAgentDescription {
     fn from(agent_description: AgentDescription) -> Self {
         ProtobufAgentDescription {
@@ -114,6 +152,52 @@ impl From<AgentDescription> for ProtobufAgentDescription {
     }
 }
 
+/// The attributes without value are skipped.
+impl From<ProtobufAgentDescription> for AgentDescription {
+    fn from(agent_description: ProtobufAgentDescription) -> Self {
+        AgentDescription {
+            identifying_attributes: attributes_from_key_values(
+                agent_description.identifying_attributes,
+            ),
+            non_identifying_attributes: attributes_from_key_values(
+                agent_description.non_identifying_attributes,
+            ),
+        }
+    }
+}
+
+fn attributes_from_key_values(key_values: Vec<KeyValue>) -> HashMap<String, DescriptionValueType> {
+    key_values
+        .into_iter()
+        .filter_map(|key_value| {
+            let value = description_value(key_value.value?)?;
+            Some((key_value.key, value))
+        })
+        .collect()
+}
+
+// Returns the value as `DescriptionValueType`, or `None` if it is empty.
+fn description_value(value: AnyValue) -> Option<DescriptionValueType> {
+    let value = match value.value? {
+        Value::StringValue(s) => DescriptionValueType::String(s),
+        Value::IntValue(i) => DescriptionValueType::Int(i),
+        Value::BoolValue(b) => DescriptionValueType::Bool(b),
+        Value::DoubleValue(f) => DescriptionValueType::Float(f),
+        Value::BytesValue(b) => DescriptionValueType::Bytes(b),
+        Value::ArrayValue(array) => DescriptionValueType::Array(
+            array
+                .values
+                .into_iter()
+                .filter_map(description_value)
+                .collect(),
+        ),
+        Value::KvlistValue(list) => {
+            DescriptionValueType::Map(attributes_from_key_values(list.values))
+        }
+    };
+    Some(value)
+}
+
 fn populate_agent_description(attrs: HashMap<String, DescriptionValueType>) -> Vec<KeyValue> {
     let mut result = Vec::new();
     for (key, desc_value) in attrs {
@@ -141,6 +225,17 @@ pub struct StartSettings {
 
     /// Agent's description: <https://github.com/open-telemetry/opamp-spec/blob/main/specification.md#agentdescription-message>
     pub agent_description: AgentDescription,
+
+    /// Requests the Server to assign the instance uid, setting the `RequestInstanceUid` flag until
+    /// the Server provides a valid one through the `AgentIdentification`. The `instance_uid` is used
+    /// in the meantime. The adopted instance uid is reported in
+    /// [`MessageData::instance_uid`](super::callbacks::MessageData::instance_uid).
+    pub request_instance_uid: bool,
+
+    /// Derives the health of the parent components from their components when the health is set
+    /// through [`set_component_health`](crate::client::Client::set_component_health): a parent is
+    /// healthy only if all its components are.
+    pub derive_parent_health: bool,
 }
 
 /// The default implementation creates a new instance_id and sets the default capabilities.
@@ -151,6 +246,8 @@ impl Default for StartSettings {
             capabilities: Capabilities::default(),
             custom_capabilities: Option::default(),
             agent_description: AgentDescription::default(),
+            request_instance_uid: false,
+            derive_parent_health: false,
         }
     }
 }
@@ -163,10 +260,11 @@ mod tests {
     use std::collections::HashMap;
 
     use crate::opamp::proto::any_value::Value::{
-        BoolValue, BytesValue, DoubleValue, IntValue, StringValue,
+        ArrayValue, BoolValue, BytesValue, DoubleValue, IntValue, KvlistValue, StringValue,
     };
     use crate::opamp::proto::{
-        AgentDescription as ProtobufAgentDescription, AnyValue, KeyValue, any_value::Value,
+        AgentDescription as ProtobufAgentDescription, AnyValue, KeyValue, KeyValueList,
+        any_value::Value,
     };
 
     #[test]
@@ -494,4 +592,96 @@ mod tests {
 
         assert_eq!(expected, actual_proto_description);
     }
+
+    #[test]
+    fn description_value_from_collections() {
+        assert_eq!(
+            DescriptionValueType::from(vec!["a", "b"]),
+            DescriptionValueType::Array(vec!["a".into(), "b".into()])
+        );
+        // Bytes are not an array
+        assert_eq!(
+            DescriptionValueType::from(vec![1u8, 2]),
+            DescriptionValueType::Bytes(vec![1, 2])
+        );
+        assert_eq!(
+            DescriptionValueType::from(HashMap::from([("key".to_string(), vec![1i64, 2])])),
+            DescriptionValueType::Map(HashMap::from([(
+                "key".to_string(),
+                DescriptionValueType::Array(vec![1.into(), 2.into()])
+            )]))
+        );
+    }
+
+    #[test]
+    fn nested_description_values_into_any_value() {
+        let value = DescriptionValueType::Array(vec![
+            true.into(),
+            HashMap::from([("key".to_string(), "value")]).into(),
+        ]);
+
+        let expected = AnyValue {
+            value: Some(ArrayValue(crate::opamp::proto::ArrayValue {
+                values: vec![
+                    AnyValue {
+                        value: Some(BoolValue(true)),
+                    },
+                    AnyValue {
+                        value: Some(KvlistValue(KeyValueList {
+                            values: vec![KeyValue {
+                                key: "key".to_string(),
+                                value: Some(AnyValue {
+                                    value: Some(StringValue("value".to_string())),
+                                }),
+                            }],
+                        })),
+                    },
+                ],
+            })),
+        };
+        assert_eq!(Option::<AnyValue>::from(value), Some(expected));
+    }
+
+    #[test]
+    fn agent_description_from_protobuf() {
+        let agent_description = AgentDescription {
+            identifying_attributes: HashMap::from([
+                ("string".to_string(), "some string".into()),
+                ("int".to_string(), 45.into()),
+                ("bool".to_string(), true.into()),
+                ("float".to_string(), 5.6.into()),
+                ("bytes".to_string(), vec![1u8, 2, 3].into()),
+            ]),
+            non_identifying_attributes: HashMap::from([
+                ("array".to_string(), vec!["a", "b"].into()),
+                (
+                    "map".to_string(),
+                    HashMap::from([
+                        ("nested".to_string(), DescriptionValueType::from(vec![1i64])),
+                        ("empty".to_string(), HashMap::<String, bool>::new().into()),
+                    ])
+                    .into(),
+                ),
+            ]),
+        };
+
+        let mut proto_description = ProtobufAgentDescription::from(agent_description.clone());
+        assert_eq!(
+            AgentDescription::from(proto_description.clone()),
+            agent_description
+        );
+
+        // Attributes without value are skipped
+        proto_description.non_identifying_attributes.extend([
+            KeyValue {
+                key: "no value".to_string(),
+                value: None,
+            },
+            KeyValue {
+                key: "empty value".to_string(),
+                value: Some(AnyValue { value: None }),
+            },
+        ]);
+        assert_eq!(AgentDescription::from(proto_description), agent_description);
+    }
 }
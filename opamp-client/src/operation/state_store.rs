@@ -0,0 +1,356 @@
+//! Persistence of the client state across Agent restarts.
+//!
+//! By default, the client state only lives in memory. Setting a [`StateStore`] allows the client to
+//! keep the instance uid, the status of the last remote configuration and the last accepted
+//! connection settings after a restart, so the Server keeps identifying the Agent and doesn't need
+//! to send everything again.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use prost::Message;
+use thiserror::Error;
+use tracing::{debug, error};
+
+use crate::opamp::proto::{OpAmpConnectionSettings, RemoteConfigStatus};
+
+use super::instance_uid::{InstanceUid, InstanceUidError};
+
+/// Holds possible errors that can occur loading or storing the [`PersistedState`].
+#[derive(Error, Debug)]
+pub enum StateStoreError {
+    /// Represents an IO error accessing the stored state.
+    #[error("accessing the stored state: `{0}`")]
+    Io(#[from] io::Error),
+    /// The stored state cannot be decoded.
+    #[error("decoding the stored state: `{0}`")]
+    Decode(#[from] prost::DecodeError),
+    /// The stored instance uid is not valid.
+    #[error("invalid stored instance uid: `{0}`")]
+    InvalidInstanceUid(#[from] InstanceUidError),
+}
+
+/// The client state kept across restarts.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PersistedState {
+    /// Last instance uid used by the client, including the ones assigned by the Server.
+    pub instance_uid: Option<InstanceUid>,
+    /// Hash of the last `AgentRemoteConfig` reported as applied or failed by the Agent. The remote
+    /// config with this hash is not handled again by the
+    /// [`RemoteConfigHandler`](super::remote_config::RemoteConfigHandler).
+    pub remote_config_hash: Option<Vec<u8>>,
+    /// Last [`RemoteConfigStatus`] reported by the Agent.
+    pub remote_config_status: Option<RemoteConfigStatus>,
+    /// Last OpAMP connection settings offered by the Server and accepted by the client.
+    pub opamp_connection_settings: Option<OpAmpConnectionSettings>,
+    /// Hash of the connection settings offer including the last accepted OpAMP connection
+    /// settings.
+    pub opamp_connection_settings_hash: Option<Vec<u8>>,
+    /// Hash of the last connection settings offer accepted by the Agent, see
+    /// [`connection_settings`](super::connection_settings) for details.
+    pub connection_settings_hash: Option<Vec<u8>>,
+}
+
+/// Loads and stores the [`PersistedState`] of the client.
+///
+/// The state is loaded once, when the store is set, and stored every time it changes.
+pub trait StateStore: Send + Sync {
+    /// Returns the stored state, or [`None`] if nothing was stored yet.
+    fn load(&self) -> Result<Option<PersistedState>, StateStoreError>;
+
+    /// Replaces the stored state.
+    fn store(&self, state: &PersistedState) -> Result<(), StateStoreError>;
+}
+
+impl<S> StateStore for Arc<S>
+where
+    S: StateStore + ?Sized,
+{
+    fn load(&self) -> Result<Option<PersistedState>, StateStoreError> {
+        (**self).load()
+    }
+
+    fn store(&self, state: &PersistedState) -> Result<(), StateStoreError> {
+        (**self).store(state)
+    }
+}
+
+/// A [`StateStore`] keeping the state in a file, encoded as protobuf.
+///
+/// The file is replaced atomically on every change. As the connection settings may include a
+/// private key, the file is only readable by its owner on Unix systems.
+#[derive(Debug, Clone)]
+pub struct FileStateStore {
+    path: PathBuf,
+}
+
+impl FileStateStore {
+    /// Creates a new [`FileStateStore`] using the file in the provided path. The file and its
+    /// parent directories are created once the state is stored.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn load(&self) -> Result<Option<PersistedState>, StateStoreError> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(StoredState::decode(data.as_slice())?.try_into()?))
+    }
+
+    fn store(&self, state: &PersistedState) -> Result<(), StateStoreError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        write_owner_only(&tmp_path, &StoredState::from(state.clone()).encode_to_vec())?;
+        Ok(fs::rename(tmp_path, &self.path)?)
+    }
+}
+
+fn write_owner_only(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Protobuf representation of the [`PersistedState`] written by the [`FileStateStore`].
+#[derive(Clone, PartialEq, Message)]
+struct StoredState {
+    #[prost(bytes = "vec", tag = "1")]
+    instance_uid: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    remote_config_hash: Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    remote_config_status: Option<RemoteConfigStatus>,
+    #[prost(message, optional, tag = "4")]
+    opamp_connection_settings: Option<OpAmpConnectionSettings>,
+    #[prost(bytes = "vec", tag = "5")]
+    connection_settings_hash: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    opamp_connection_settings_hash: Vec<u8>,
+}
+
+impl From<PersistedState> for StoredState {
+    fn from(state: PersistedState) -> Self {
+        Self {
+            instance_uid: state.instance_uid.map(Into::into).unwrap_or_default(),
+            remote_config_hash: state.remote_config_hash.unwrap_or_default(),
+            remote_config_status: state.remote_config_status,
+            opamp_connection_settings: state.opamp_connection_settings,
+            connection_settings_hash: state.connection_settings_hash.unwrap_or_default(),
+            opamp_connection_settings_hash: state
+                .opamp_connection_settings_hash
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<StoredState> for PersistedState {
+    type Error = InstanceUidError;
+
+    fn try_from(stored: StoredState) -> Result<Self, Self::Error> {
+        Ok(Self {
+            instance_uid: (!stored.instance_uid.is_empty())
+                .then(|| stored.instance_uid.try_into())
+                .transpose()?,
+            remote_config_hash: (!stored.remote_config_hash.is_empty())
+                .then_some(stored.remote_config_hash),
+            remote_config_status: stored.remote_config_status,
+            opamp_connection_settings: stored.opamp_connection_settings,
+            connection_settings_hash: (!stored.connection_settings_hash.is_empty())
+                .then_some(stored.connection_settings_hash),
+            opamp_connection_settings_hash: (!stored.opamp_connection_settings_hash.is_empty())
+                .then_some(stored.opamp_connection_settings_hash),
+        })
+    }
+}
+
+/// Keeps the [`PersistedState`] of a client in sync with its [`StateStore`].
+pub(crate) struct PersistedStateHandle {
+    store: Box<dyn StateStore>,
+    state: Mutex<PersistedState>,
+}
+
+impl PersistedStateHandle {
+    /// Loads the state from the provided store. Errors are logged and the client starts with an
+    /// empty state, so a corrupted store doesn't prevent the Agent from connecting.
+    pub(crate) fn load(store: Box<dyn StateStore>) -> Self {
+        let state = store
+            .load()
+            .inspect_err(|err| error!(%err, "loading the persisted client state"))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Self {
+            store,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Returns the current state.
+    pub(crate) fn state(&self) -> PersistedState {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Modifies the state with the provided function, storing it if it changed. Errors are logged,
+    /// the client keeps working with the updated state in memory.
+    pub(crate) fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut PersistedState),
+    {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let previous = state.clone();
+        f(&mut state);
+        if *state == previous {
+            return;
+        }
+        match self.store.store(&state) {
+            Ok(()) => debug!("client state persisted"),
+            Err(err) => error!(%err, "persisting the client state"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+    use crate::opamp::proto::{RemoteConfigStatuses, TlsCertificate};
+
+    /// A [`StateStore`] keeping the state in memory, clones share the stored state.
+    #[derive(Debug, Default, Clone)]
+    pub(crate) struct InMemoryStateStore(pub(crate) Arc<Mutex<Option<PersistedState>>>);
+
+    impl InMemoryStateStore {
+        pub(crate) fn stored(&self) -> Option<PersistedState> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl StateStore for InMemoryStateStore {
+        fn load(&self) -> Result<Option<PersistedState>, StateStoreError> {
+            Ok(self.stored())
+        }
+
+        fn store(&self, state: &PersistedState) -> Result<(), StateStoreError> {
+            *self.0.lock().unwrap() = Some(state.clone());
+            Ok(())
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("opamp-state-{}", InstanceUid::create()))
+            .join(name)
+    }
+
+    #[test]
+    fn file_store_roundtrip() {
+        let path = temp_path("state");
+        let store = FileStateStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        let state = PersistedState {
+            instance_uid: Some(InstanceUid::create()),
+            remote_config_hash: Some(b"hash".to_vec()),
+            remote_config_status: Some(RemoteConfigStatus {
+                last_remote_config_hash: b"hash".to_vec(),
+                status: RemoteConfigStatuses::Applied as i32,
+                error_message: String::default(),
+            }),
+            opamp_connection_settings: Some(OpAmpConnectionSettings {
+                destination_endpoint: "https://example.com/v1/opamp".to_string(),
+                certificate: Some(TlsCertificate {
+                    public_key: b"cert".to_vec(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            connection_settings_hash: Some(b"settings-hash".to_vec()),
+            opamp_connection_settings_hash: Some(b"opamp-settings-hash".to_vec()),
+        };
+        store.store(&state).unwrap();
+        assert_eq!(store.load().unwrap(), Some(state));
+
+        // Empty values are restored as None
+        store.store(&PersistedState::default()).unwrap();
+        assert_eq!(store.load().unwrap(), Some(PersistedState::default()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn file_store_invalid_content() {
+        let path = temp_path("state");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let store = FileStateStore::new(&path);
+
+        fs::write(&path, [255, 255]).unwrap();
+        assert_matches!(store.load().unwrap_err(), StateStoreError::Decode(_));
+
+        let invalid_uid = StoredState {
+            instance_uid: b"invalid".to_vec(),
+            ..Default::default()
+        };
+        fs::write(&path, invalid_uid.encode_to_vec()).unwrap();
+        assert_matches!(
+            store.load().unwrap_err(),
+            StateStoreError::InvalidInstanceUid(_)
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn handle_only_stores_changes() {
+        #[derive(Default)]
+        struct CountingStore(Mutex<usize>);
+        impl StateStore for CountingStore {
+            fn load(&self) -> Result<Option<PersistedState>, StateStoreError> {
+                Err(StateStoreError::Io(io::ErrorKind::Other.into()))
+            }
+            fn store(&self, _: &PersistedState) -> Result<(), StateStoreError> {
+                *self.0.lock().unwrap() += 1;
+                Ok(())
+            }
+        }
+
+        let store = Arc::new(CountingStore::default());
+
+        // Load errors start with the empty state
+        let handle = PersistedStateHandle::load(Box::new(store.clone()));
+        assert_eq!(handle.state(), PersistedState::default());
+
+        let hash = Some(b"hash".to_vec());
+        handle.update(|state| state.remote_config_hash = hash.clone());
+        handle.update(|state| state.remote_config_hash = hash.clone());
+
+        assert_eq!(*store.0.lock().unwrap(), 1);
+        assert_eq!(handle.state().remote_config_hash, hash);
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{Receiver, Sender, TryRecvError, bounded, unbounded};
+use http::{HeaderMap, Uri};
+use prost::{
+    Message as _,
+    encoding::{decode_varint, encode_varint},
+};
+use tracing::debug;
+use tungstenite::{HandshakeError, Message, WebSocket, client, client::IntoClientRequest};
+
+use crate::{
+    common::compression::DecoderError,
+    opamp::proto::{AgentToServer, ServerToAgent},
+};
+
+/// Header value of the OpAMP WebSocket messages. It is the only value defined by the specification:
+/// <https://github.com/open-telemetry/opamp-spec/blob/main/specification.md#websocket-message-format>
+const WS_MESSAGE_HEADER: u64 = 0;
+
+/// The only scheme supported, `wss://` would require TLS.
+const WS_SCHEME: &str = "ws";
+
+/// Port used if the uri doesn't include one.
+const DEFAULT_PORT: u16 = 80;
+
+/// Default time to wait for the connection, the handshake and every message sent.
+pub(super) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum time the connection waits for a server message before sending the queued ones.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum time to wait for the server to acknowledge the closing handshake.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An enumeration of potential errors related to the WebSocket client.
+#[derive(thiserror::Error, Debug)]
+pub enum WsClientError {
+    /// Represents a websocket transport error.
+    #[error("{0}")]
+    TransportError(String),
+    /// The uri to connect to has no host.
+    #[error("missing host in uri: `{0}`")]
+    MissingHost(Uri),
+    /// The uri to connect to has a scheme other than `ws`.
+    #[error("unsupported scheme in uri: `{0}`, only `ws` is supported")]
+    UnsupportedScheme(Uri),
+    /// The server closed the connection.
+    #[error("connection closed by the server")]
+    ConnectionClosed,
+    /// The received message has a header not supported by this client.
+    #[error("unsupported message header: {0}")]
+    UnsupportedHeader(u64),
+    /// Represents a decode error.
+    #[error("decoding server message: `{0}`")]
+    DecoderError(#[from] DecoderError),
+}
+
+impl From<tungstenite::Error> for WsClientError {
+    fn from(value: tungstenite::Error) -> Self {
+        match value {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                WsClientError::ConnectionClosed
+            }
+            err => WsClientError::TransportError(err.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for WsClientError {
+    fn from(err: io::Error) -> Self {
+        WsClientError::TransportError(err.to_string())
+    }
+}
+
+/// Holds the information required to open WebSocket connections to the OpAMP server.
+#[derive(Debug, Clone)]
+pub(super) struct WsConnector {
+    pub(super) uri: Uri,
+    pub(super) headers: HeaderMap,
+    pub(super) timeout: Duration,
+}
+
+impl WsConnector {
+    /// Opens a new connection performing the WebSocket handshake.
+    pub(super) fn connect(&self) -> Result<WsConnection, WsClientError> {
+        let host = self
+            .uri
+            .host()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| WsClientError::MissingHost(self.uri.clone()))?;
+        if self.uri.scheme_str() != Some(WS_SCHEME) {
+            return Err(WsClientError::UnsupportedScheme(self.uri.clone()));
+        }
+        // IPv6 hosts are kept in brackets in the uri, but not when resolving the address.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = self.uri.port_u16().unwrap_or(DEFAULT_PORT);
+
+        let mut request = self.uri.clone().into_client_request()?;
+        request.headers_mut().extend(self.headers.clone());
+
+        let stream = self.connect_stream(host, port)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let (socket, _) = client(request, stream).map_err(|err| match err {
+            HandshakeError::Interrupted(_) => {
+                WsClientError::TransportError("timed out performing the handshake".to_string())
+            }
+            HandshakeError::Failure(err) => err.into(),
+        })?;
+        // Reads only wait for the poll interval, so the queued messages are sent in time.
+        socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+        WsConnection::new(socket)
+    }
+
+    /// Connects to the first address the host resolves to which accepts the connection in time.
+    fn connect_stream(&self, host: &str, port: u16) -> Result<TcpStream, WsClientError> {
+        let mut last_err = None;
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.map_or_else(
+            || WsClientError::TransportError(format!("no address found for host `{host}`")),
+            WsClientError::from,
+        ))
+    }
+}
+
+/// Requests handled by the thread owning the socket.
+enum Command {
+    /// Sends the encoded message, replying with the result.
+    Send(Vec<u8>, Sender<Result<(), WsClientError>>),
+    /// Performs the closing handshake.
+    Close,
+}
+
+/// An open WebSocket connection to the OpAMP server.
+///
+/// The socket is owned by a dedicated thread which sends the messages requested through
+/// [`send`](WsConnection::send) and hands the messages sent by the server over through
+/// [`incoming`](WsConnection::incoming). The thread stops once the connection is lost, closed or
+/// dropped.
+pub(super) struct WsConnection {
+    // Dropped to stop the thread, disconnecting the channel.
+    commands: Option<Sender<Command>>,
+    incoming: Receiver<Result<ServerToAgent, WsClientError>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WsConnection {
+    fn new(socket: WebSocket<TcpStream>) -> Result<Self, WsClientError> {
+        let (commands, requested) = unbounded();
+        let (sender, incoming) = unbounded();
+        let worker = Builder::new()
+            .name("opamp-ws-connection".to_string())
+            .spawn(move || run_connection(socket, requested, sender))?;
+
+        Ok(Self {
+            commands: Some(commands),
+            incoming,
+            worker: Some(worker),
+        })
+    }
+
+    /// Sends an [`AgentToServer`] message through the connection.
+    pub(super) fn send(&mut self, msg: &AgentToServer) -> Result<(), WsClientError> {
+        let (result, sent) = bounded(1);
+        self.commands
+            .as_ref()
+            .and_then(|commands| {
+                commands
+                    .send(Command::Send(encode_message(msg), result))
+                    .ok()
+            })
+            .ok_or(WsClientError::ConnectionClosed)?;
+        sent.recv().unwrap_or(Err(WsClientError::ConnectionClosed))
+    }
+
+    /// Returns the channel receiving the [`ServerToAgent`] messages, or the error which caused
+    /// the connection to be lost.
+    pub(super) fn incoming(&self) -> &Receiver<Result<ServerToAgent, WsClientError>> {
+        &self.incoming
+    }
+
+    /// Performs the closing handshake, waiting until the server acknowledges it or the timeout
+    /// expires.
+    pub(super) fn close(mut self) {
+        if let Some(commands) = self.commands.take() {
+            let _ = commands.send(Command::Close);
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for WsConnection {
+    // Stops the thread, which closes the socket, and waits for it.
+    fn drop(&mut self) {
+        self.commands.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Serves the commands and reads the server messages until the connection is lost or closed.
+/// Every error is reported once through `incoming`, which makes the connection unusable.
+fn run_connection(
+    mut socket: WebSocket<TcpStream>,
+    commands: Receiver<Command>,
+    incoming: Sender<Result<ServerToAgent, WsClientError>>,
+) {
+    // Once closing, the socket is only read to complete the closing handshake.
+    let mut closing_deadline = None;
+    loop {
+        match commands.try_recv() {
+            Ok(Command::Send(data, result)) => {
+                let sent = match closing_deadline {
+                    Some(_) => Err(WsClientError::ConnectionClosed),
+                    None => socket
+                        .send(Message::Binary(data.into()))
+                        .map_err(WsClientError::from),
+                };
+                let _ = result.send(sent);
+                continue;
+            }
+            Ok(Command::Close) if closing_deadline.is_none() => {
+                if socket.close(None).is_err() {
+                    break;
+                }
+                closing_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+            }
+            Ok(Command::Close) | Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => break,
+        }
+
+        let result = match socket.read() {
+            Ok(Message::Binary(data)) => decode_message(&data),
+            // The reply is sent automatically by the next read.
+            Ok(Message::Close(_)) => {
+                if closing_deadline.is_none() {
+                    let _ = incoming.send(Err(WsClientError::ConnectionClosed));
+                    closing_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+                }
+                continue;
+            }
+            // Ping messages are answered automatically, any other message is not expected.
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if closing_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break;
+                }
+                continue;
+            }
+            Err(err) => Err(err.into()),
+        };
+        if closing_deadline.is_some() {
+            // Any message received while closing is discarded.
+            if result.is_err() {
+                break;
+            }
+            continue;
+        }
+        let lost = result.is_err();
+        if incoming.send(result).is_err() || lost {
+            break;
+        }
+    }
+    debug!("stopped the connection to the OpAMP server");
+}
+
+/// Encodes the message with the OpAMP WebSocket framing: a varint header followed by the
+/// protobuf encoded message.
+fn encode_message(msg: &AgentToServer) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(msg.encoded_len() + 1);
+    encode_varint(WS_MESSAGE_HEADER, &mut buf);
+    buf.extend(msg.encode_to_vec());
+    buf
+}
+
+/// Decodes a message with the OpAMP WebSocket framing.
+fn decode_message(mut data: &[u8]) -> Result<ServerToAgent, WsClientError> {
+    let header = decode_varint(&mut data).map_err(DecoderError::from)?;
+    if header != WS_MESSAGE_HEADER {
+        return Err(WsClientError::UnsupportedHeader(header));
+    }
+    Ok(ServerToAgent::decode(data).map_err(DecoderError::from)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    /// Encodes a [`ServerToAgent`] message with the OpAMP WebSocket framing.
+    pub(crate) fn encode_server_to_agent(msg: &ServerToAgent) -> Vec<u8> {
+        let mut buf = vec![];
+        encode_varint(WS_MESSAGE_HEADER, &mut buf);
+        buf.extend(msg.encode_to_vec());
+        buf
+    }
+
+    /// Decodes an [`AgentToServer`] message with the OpAMP WebSocket framing.
+    pub(crate) fn decode_agent_to_server(mut data: &[u8]) -> AgentToServer {
+        assert_eq!(decode_varint(&mut data).unwrap(), WS_MESSAGE_HEADER);
+        AgentToServer::decode(data).unwrap()
+    }
+
+    fn connector(uri: &str) -> WsConnector {
+        WsConnector {
+            uri: uri.parse().unwrap(),
+            headers: HeaderMap::default(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    #[test]
+    fn connect_fails_without_host() {
+        assert_matches!(
+            connector("/v1/opamp").connect().err(),
+            Some(WsClientError::MissingHost(_))
+        );
+    }
+
+    #[test]
+    fn connect_fails_with_unsupported_scheme() {
+        assert_matches!(
+            connector("wss://localhost/v1/opamp").connect().err(),
+            Some(WsClientError::UnsupportedScheme(_))
+        );
+    }
+
+    #[test]
+    fn connect_fails_if_handshake_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let connector = WsConnector {
+            timeout: Duration::from_millis(100),
+            ..connector(&format!("ws://{}/v1/opamp", listener.local_addr().unwrap()))
+        };
+        // The connection is accepted by the OS, but the handshake is never answered
+        let err = connector.connect().err();
+
+        assert_matches!(err, Some(WsClientError::TransportError(_)));
+        drop(listener);
+    }
+
+    #[test]
+    fn close_sends_a_single_close_frame() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("ws://{}/v1/opamp", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            let mut close_frames = 0;
+            loop {
+                match socket.read() {
+                    Ok(Message::Close(_)) => close_frames += 1,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+            close_frames
+        });
+
+        let mut connection = connector(&uri).connect().unwrap();
+        connection.send(&AgentToServer::default()).unwrap();
+        connection.close();
+
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn connect_to_ipv6_host_and_receive_messages() {
+        let Ok(listener) = std::net::TcpListener::bind("[::1]:0") else {
+            // IPv6 is not available in this environment
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let msg = ServerToAgent {
+            instance_uid: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let server = std::thread::spawn({
+            let msg = msg.clone();
+            move || {
+                let (stream, _) = listener.accept().unwrap();
+                let mut socket = tungstenite::accept(stream).unwrap();
+                socket
+                    .send(Message::Binary(encode_server_to_agent(&msg).into()))
+                    .unwrap();
+                socket.close(None).unwrap();
+                // Drive the closing handshake
+                while socket.read().is_ok() {}
+            }
+        });
+
+        let connection = connector(&format!("ws://[::1]:{port}/v1/opamp"))
+            .connect()
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            connection
+                .incoming()
+                .recv_timeout(timeout)
+                .unwrap()
+                .unwrap(),
+            msg
+        );
+        assert_matches!(
+            connection.incoming().recv_timeout(timeout).unwrap(),
+            Err(WsClientError::ConnectionClosed)
+        );
+        drop(connection);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn encoded_message_starts_with_header() {
+        let msg = AgentToServer {
+            sequence_num: 42,
+            ..Default::default()
+        };
+
+        let encoded = encode_message(&msg);
+
+        assert_eq!(encoded[0], 0);
+        assert_eq!(decode_agent_to_server(&encoded), msg);
+    }
+
+    #[test]
+    fn decode_framed_message() {
+        let msg = ServerToAgent {
+            instance_uid: vec![1, 2, 3],
+            ..Default::default()
+        };
+
+        let decoded = decode_message(&encode_server_to_agent(&msg)).unwrap();
+
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn decode_unsupported_header() {
+        let mut data = vec![];
+        encode_varint(1, &mut data);
+        data.extend(ServerToAgent::default().encode_to_vec());
+
+        assert_matches!(
+            decode_message(&data).unwrap_err(),
+            WsClientError::UnsupportedHeader(1)
+        );
+    }
+
+    #[test]
+    fn decode_invalid_message() {
+        assert_matches!(
+            decode_message(&[0, 255, 255]).unwrap_err(),
+            WsClientError::DecoderError(_)
+        );
+    }
+}
@@ -0,0 +1,641 @@
+//! Implementation of the [`NotStartedClient`] and [`StartedClient`] traits for OpAMP over WebSocket.
+
+use crossbeam::channel::{Receiver, after, never, select_biased};
+use http::{HeaderMap, Uri};
+use std::{
+    sync::Arc,
+    thread::{JoinHandle, spawn},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info_span, instrument, trace, warn};
+
+use crate::{
+    Client, ClientError, ClientResult, NotStartedClient, NotStartedClientResult, StartedClient,
+    StartedClientError, StartedClientResult,
+    common::{client::ClientCore, notifier::Notifier},
+    opamp::proto::{
+        AgentCapabilities, AgentDescription, AvailableComponents, ComponentHealth,
+        CustomCapabilities, CustomMessage, PackageStatuses, RemoteConfigStatus,
+    },
+    operation::{
+        callbacks::Callbacks, capabilities::ServerCapabilities,
+        connection_settings::ConnectionSettingsHandler, remote_config::RemoteConfigHandler,
+        settings::StartSettings, state_store::StateStore,
+    },
+};
+
+use super::connection::{DEFAULT_TIMEOUT, WsClientError, WsConnection, WsConnector};
+
+// Default and minimum time to wait before trying to reconnect after the connection is lost.
+const DEFAULT_RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+const MINIMUM_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// Interval to send heartbeats at if the server doesn't request one, as defined by the spec.
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Implements the [`NotStartedClient`] trait for WebSocket.
+pub struct NotStartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    opamp_client: ClientCore<C>,
+    connector: WsConnector,
+    reconnect_interval: Duration,
+    has_pending_msg: Receiver<()>,
+    perform_startup_check: bool,
+}
+
+/// A [`Client`] that keeps a WebSocket connection to the OpAMP server open in a background thread,
+/// sending messages as soon as there are changes and processing the messages pushed by the server.
+pub struct StartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    // handle for the spawned connection task
+    handle: JoinHandle<()>,
+
+    // stop the connection thread
+    shutdown_notifier: Notifier,
+
+    opamp_client: Arc<ClientCore<C>>,
+}
+
+impl<C> NotStartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    /// Creates a new instance of [`NotStartedWsClient`] that will connect to the provided `uri`.
+    pub fn new(
+        uri: Uri,
+        callbacks: C,
+        start_settings: StartSettings,
+    ) -> NotStartedClientResult<Self> {
+        let (pending_msg_notifier, has_pending_msg) = Notifier::new("pending_msg".to_string());
+
+        let opamp_client = ClientCore::new(callbacks, start_settings, pending_msg_notifier)?;
+
+        Ok(Self {
+            opamp_client,
+            connector: WsConnector {
+                uri,
+                headers: HeaderMap::default(),
+                timeout: DEFAULT_TIMEOUT,
+            },
+            reconnect_interval: DEFAULT_RECONNECT_INTERVAL,
+            has_pending_msg,
+            perform_startup_check: true,
+        })
+    }
+
+    /// Returns a new instance of the [`NotStartedWsClient`] which will include the provided headers
+    /// in the WebSocket handshake request.
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        Self {
+            connector: WsConnector {
+                headers,
+                ..self.connector
+            },
+            ..self
+        }
+    }
+
+    /// Returns a new instance of the [`NotStartedWsClient`] with the provided timeout to establish
+    /// the connection, perform the WebSocket handshake and send every message.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            connector: WsConnector {
+                timeout,
+                ..self.connector
+            },
+            ..self
+        }
+    }
+
+    /// Returns a new instance of the [`NotStartedWsClient`] with the specified interval to wait before
+    /// reconnecting. If the interval is smaller than the minimum, a warning message will be printed
+    /// and the minimum value will be used.
+    pub fn with_reconnect_interval(self, interval: Duration) -> Self {
+        let reconnect_interval = if interval.lt(&MINIMUM_RECONNECT_INTERVAL) {
+            warn!(
+                interval = interval.as_secs(),
+                minimum_interval = MINIMUM_RECONNECT_INTERVAL.as_secs(),
+                "reconnect interval smaller than minimum. Falling back to minimum interval."
+            );
+            MINIMUM_RECONNECT_INTERVAL
+        } else {
+            interval
+        };
+
+        Self {
+            reconnect_interval,
+            ..self
+        }
+    }
+
+    /// Returns a new instance with the startup check disabled. The client's start method will not fail if
+    /// the first connection cannot be established. As a result, the client will keep trying to connect
+    /// according to the reconnect interval.
+    pub fn with_startup_check_disabled(self) -> Self {
+        Self {
+            perform_startup_check: false,
+            ..self
+        }
+    }
+
+    /// Returns a new instance which persists the client state in the provided [`StateStore`]. The
+    /// stored state is restored right away: the stored instance uid replaces the one in the
+    /// [`StartSettings`] and the stored `RemoteConfigStatus` is reported in the first message.
+    pub fn with_state_store<S>(mut self, store: S) -> Self
+    where
+        S: StateStore + 'static,
+    {
+        let _ = self
+            .opamp_client
+            .set_state_store(store)
+            .inspect_err(|err| error!(%err, "restoring the persisted client state"));
+        self
+    }
+
+    /// Returns a new instance which offers the telemetry and other connection settings sent by the
+    /// server to the provided [`ConnectionSettingsHandler`], instead of surfacing them through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`connection_settings`](crate::operation::connection_settings) for details.
+    pub fn with_connection_settings_handler<H>(mut self, handler: H) -> Self
+    where
+        H: ConnectionSettingsHandler + 'static,
+    {
+        self.opamp_client.set_connection_settings_handler(handler);
+        self
+    }
+
+    /// Returns a new instance which applies the remote config offered by the server through the
+    /// provided [`RemoteConfigHandler`], reporting its status, instead of surfacing it through
+    /// [`on_message`](Callbacks::on_message). See
+    /// [`remote_config`](crate::operation::remote_config) for details.
+    pub fn with_remote_config_handler<H>(mut self, handler: H) -> Self
+    where
+        H: RemoteConfigHandler + 'static,
+    {
+        self.opamp_client.set_remote_config_handler(handler);
+        self
+    }
+}
+
+impl<C> NotStartedClient for NotStartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    type StartedClient = StartedWsClient<C>;
+
+    #[instrument(name = "opamp", fields(instance_uid = %self.opamp_client.instance_uid), skip_all)]
+    fn start(self) -> NotStartedClientResult<Self::StartedClient> {
+        debug!("connecting to the OpAMP server");
+        let opamp_client = Arc::new(self.opamp_client);
+        let connection = match connect(&opamp_client, &self.connector) {
+            Ok(connection) => Some(connection),
+            Err(err) if self.perform_startup_check => return Err(err.into()),
+            Err(err) => {
+                error!(%err, "error sending first AgentToServer message");
+                None
+            }
+        };
+
+        let (shutdown_notifier, exit) = Notifier::new("shut_down".to_string());
+
+        let handle = spawn({
+            let opamp_client = opamp_client.clone();
+            move || {
+                // We are in a separated thread so we create another span which will propagate
+                // the instance_uid
+                let _guard =
+                    info_span!("opamp", instance_uid = %opamp_client.instance_uid).entered();
+                let mut connection = connection;
+                // Time of the last message sent, heartbeats are only needed if nothing else is.
+                let mut last_sent = Instant::now();
+                loop {
+                    let Some(mut current) = connection.take() else {
+                        select_biased! {
+                            recv(exit) -> _ => {
+                                debug!("gracefully shutting down the connection task");
+                                break;
+                            }
+                            default(self.reconnect_interval) => {
+                                debug!("reconnecting to the OpAMP server");
+                                connection = connect(&opamp_client, &self.connector)
+                                    .inspect_err(|err| error!(%err, "error while reconnecting"))
+                                    .ok();
+                                last_sent = Instant::now();
+                                continue;
+                            }
+                        }
+                    };
+
+                    let incoming = current.incoming().clone();
+                    let heartbeat = heartbeat_interval(&opamp_client)
+                        .map(|interval| after(interval.saturating_sub(last_sent.elapsed())))
+                        .unwrap_or_else(never);
+                    select_biased! {
+                        recv(exit) -> _ => {
+                            debug!("gracefully shutting down the connection task");
+                            disconnect(&opamp_client, current);
+                            break;
+                        }
+                        recv(self.has_pending_msg) -> res => {
+                            if let Err(err) = res {
+                                error!(%err, "pending message channel error");
+                                disconnect(&opamp_client, current);
+                                break;
+                            }
+                            debug!("sending requested AgentToServer message");
+                            if let Err(err) = send(&opamp_client, &mut current) {
+                                error!(%err, "error while sending message");
+                                current.close();
+                                continue;
+                            }
+                            last_sent = Instant::now();
+                        }
+                        recv(heartbeat) -> _ => {
+                            debug!("sending heartbeat AgentToServer message");
+                            if let Err(err) = send(&opamp_client, &mut current) {
+                                error!(%err, "error while sending heartbeat");
+                                current.close();
+                                continue;
+                            }
+                            last_sent = Instant::now();
+                        }
+                        recv(incoming) -> res => {
+                            // The reader only stops after reporting why the connection was lost.
+                            match res.unwrap_or(Err(WsClientError::ConnectionClosed)) {
+                                Ok(server_to_agent) => {
+                                    trace!("Received payload: {:?}", server_to_agent);
+                                    let _span = info_span!("process_message").entered();
+                                    let _ = opamp_client.process(server_to_agent).inspect_err(
+                                        |err| error!(%err, "error while processing message"),
+                                    );
+                                }
+                                Err(err) => {
+                                    error!(%err, "connection to the OpAMP server lost");
+                                    opamp_client.callbacks.on_connect_failed(err.into());
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+                    connection = Some(current);
+                }
+                debug!("connection task stopped");
+            }
+        });
+
+        Ok(StartedWsClient {
+            handle,
+            shutdown_notifier,
+            opamp_client,
+        })
+    }
+}
+
+// Returns the interval to send heartbeats at if the agent has the `ReportsHeartbeat` capability:
+// the one requested by the server, or the default one otherwise.
+fn heartbeat_interval<C>(opamp_client: &ClientCore<C>) -> Option<Duration>
+where
+    C: Callbacks,
+{
+    opamp_client
+        .capabilities
+        .has_capability(AgentCapabilities::ReportsHeartbeat)
+        .then(|| {
+            opamp_client
+                .heartbeat_interval
+                .get()
+                .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL)
+        })
+}
+
+// Opens a new connection and sends the next message, which lets the server know about the current
+// Agent status.
+fn connect<C>(opamp_client: &ClientCore<C>, connector: &WsConnector) -> ClientResult<WsConnection>
+where
+    C: Callbacks,
+{
+    let mut connection = connector.connect().map_err(|e| {
+        let err_msg = e.to_string();
+        opamp_client.callbacks.on_connect_failed(e.into());
+        ClientError::ConnectFailedCallback(err_msg)
+    })?;
+
+    // We consider it connected once the handshake is completed.
+    opamp_client.callbacks.on_connect();
+
+    match send(opamp_client, &mut connection) {
+        Ok(()) => Ok(connection),
+        Err(err) => {
+            connection.close();
+            Err(err)
+        }
+    }
+}
+
+// Sends the next message through the provided connection. If it cannot be sent, the message is
+// put back so it is sent once reconnected.
+fn send<C>(opamp_client: &ClientCore<C>, connection: &mut WsConnection) -> ClientResult<()>
+where
+    C: Callbacks,
+{
+    let msg = opamp_client.pop_message()?;
+    trace!("Send payload: {:?}", msg);
+    if let Err(e) = connection.send(&msg) {
+        opamp_client.restore_message(msg)?;
+        let err_msg = e.to_string();
+        opamp_client.callbacks.on_connect_failed(e.into());
+        return Err(ClientError::ConnectFailedCallback(err_msg));
+    }
+    // The remote config whose `Applying` status was sent can be applied now.
+    opamp_client.apply_sent_remote_config()
+}
+
+// By OpAMP protocol, AgentDisconnect must be sent in the last message.
+fn disconnect<C>(opamp_client: &ClientCore<C>, mut connection: WsConnection)
+where
+    C: Callbacks,
+{
+    match opamp_client.pop_disconnect_message() {
+        Ok(msg) => {
+            let _ = connection
+                .send(&msg)
+                .inspect_err(|err| error!(%err, "sending disconnect OpAMP message"));
+        }
+        Err(err) => {
+            error!(%err, "assembling disconnect OpAMP message");
+        }
+    }
+    connection.close();
+    debug!("disconnected from server");
+}
+
+impl<C> StartedClient for StartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    // Stops the StartedWsClient, disconnects from the server and terminates the running background thread.
+    fn stop(self) -> StartedClientResult<()> {
+        self.shutdown_notifier.notify_or_warn();
+        self.handle
+            .join()
+            .map_err(|_| StartedClientError::JoinError)
+    }
+}
+
+impl<C> Client for StartedWsClient<C>
+where
+    C: Callbacks + Send + Sync + 'static,
+{
+    fn set_agent_description(&self, description: AgentDescription) -> ClientResult<()> {
+        self.opamp_client.set_agent_description(description)
+    }
+
+    fn get_agent_description(&self) -> ClientResult<AgentDescription> {
+        self.opamp_client.get_agent_description()
+    }
+
+    fn set_health(&self, health: ComponentHealth) -> ClientResult<()> {
+        self.opamp_client.set_health(health)
+    }
+
+    fn set_component_health(&self, path: &[&str], health: ComponentHealth) -> ClientResult<()> {
+        self.opamp_client.set_component_health(path, health)
+    }
+
+    fn update_effective_config(&self) -> ClientResult<()> {
+        self.opamp_client.update_effective_config()
+    }
+
+    fn set_remote_config_status(&self, status: RemoteConfigStatus) -> ClientResult<()> {
+        self.opamp_client.set_remote_config_status(status)
+    }
+
+    fn set_custom_capabilities(&self, custom_capabilities: CustomCapabilities) -> ClientResult<()> {
+        self.opamp_client
+            .set_custom_capabilities(custom_capabilities)
+    }
+
+    fn set_package_statuses(&self, statuses: PackageStatuses) -> ClientResult<()> {
+        self.opamp_client.set_package_statuses(statuses)
+    }
+
+    fn send_custom_message(&self, message: CustomMessage) -> ClientResult<()> {
+        self.opamp_client.send_custom_message(message)
+    }
+
+    fn request_client_certificate(&self, csr: Vec<u8>) -> ClientResult<()> {
+        self.opamp_client.request_client_certificate(csr)
+    }
+
+    fn server_capabilities(&self) -> ClientResult<Option<ServerCapabilities>> {
+        self.opamp_client.server_capabilities()
+    }
+
+    fn set_available_components(&self, components: AvailableComponents) -> ClientResult<()> {
+        self.opamp_client.set_available_components(components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities;
+    use crate::opamp::proto::{
+        AgentConfigMap, AgentRemoteConfig, AgentToServer, ConnectionSettingsOffers,
+        OpAmpConnectionSettings, ServerToAgent,
+    };
+    use crate::operation::callbacks::{MessageData, tests::MockCallbacksMockall};
+    use crate::ws::connection::tests::{decode_agent_to_server, encode_server_to_agent};
+    use crossbeam::channel::{Sender, unbounded};
+    use std::net::TcpListener;
+    use std::thread::sleep;
+    use tungstenite::{Message, accept};
+
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// In-process OpAMP WebSocket server accepting a single connection. Every received
+    /// [`AgentToServer`] is forwarded to the returned channel and answered with the provided
+    /// [`ServerToAgent`] message.
+    fn start_server(response: ServerToAgent) -> (Uri, Receiver<AgentToServer>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("ws://{}/v1/opamp", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (received, receiver): (Sender<AgentToServer>, _) = unbounded();
+
+        spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = accept(stream).unwrap();
+            while let Ok(msg) = socket.read() {
+                if let Message::Binary(data) = msg {
+                    let _ = received.send(decode_agent_to_server(&data));
+                    let _ = socket.send(Message::Binary(encode_server_to_agent(&response).into()));
+                }
+            }
+        });
+
+        (uri, receiver)
+    }
+
+    #[test]
+    fn start_fails_if_server_is_not_reachable() {
+        // Bind and drop the listener to get a free port with no server behind
+        let uri = format!(
+            "ws://{}/v1/opamp",
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        )
+        .parse()
+        .unwrap();
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_connect_failed();
+
+        let Err(err) = NotStartedWsClient::new(uri, callbacks, StartSettings::default())
+            .unwrap()
+            .start()
+        else {
+            panic!("start should fail when the server is not reachable");
+        };
+
+        assert_matches::assert_matches!(
+            err,
+            crate::NotStartedClientError::ClientError(ClientError::ConnectFailedCallback(_))
+        );
+    }
+
+    #[test]
+    fn start_with_startup_check_disabled_keeps_reconnecting() {
+        let uri = format!(
+            "ws://{}/v1/opamp",
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        )
+        .parse()
+        .unwrap();
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.expect_on_connect_failed().return_const(());
+
+        let started_client = NotStartedWsClient::new(uri, callbacks, StartSettings::default())
+            .unwrap()
+            .with_startup_check_disabled()
+            .start()
+            .expect("Start should not fail");
+
+        // Verify that the thread didn't panic
+        started_client.stop().unwrap();
+    }
+
+    #[test]
+    fn server_messages_are_processed_and_disconnect_is_sent_on_stop() {
+        let remote_config = AgentRemoteConfig {
+            config: Some(AgentConfigMap::default()),
+            config_hash: "hash".into(),
+        };
+        let (uri, received) = start_server(ServerToAgent {
+            remote_config: Some(remote_config.clone()),
+            ..Default::default()
+        });
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_connect();
+        callbacks.should_on_message(MessageData {
+            remote_config: Some(remote_config),
+            ..Default::default()
+        });
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::AcceptsRemoteConfig),
+            ..Default::default()
+        };
+        let started_client = NotStartedWsClient::new(uri, callbacks, settings)
+            .unwrap()
+            .start()
+            .unwrap();
+
+        // first message
+        let first = received.recv_timeout(RECEIVE_TIMEOUT).unwrap();
+        assert_eq!(first.sequence_num, 1);
+        assert!(first.agent_disconnect.is_none());
+
+        // give time to process the server response
+        sleep(Duration::from_millis(300));
+        started_client.stop().unwrap();
+
+        let last = received.recv_timeout(RECEIVE_TIMEOUT).unwrap();
+        assert!(last.agent_disconnect.is_some());
+    }
+
+    #[test]
+    fn status_changes_are_pushed_to_server() {
+        let (uri, received) = start_server(ServerToAgent::default());
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_connect();
+        callbacks.expect_on_message().return_const(());
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsHealth),
+            ..Default::default()
+        };
+        let started_client = NotStartedWsClient::new(uri, callbacks, settings)
+            .unwrap()
+            .start()
+            .unwrap();
+        // first message
+        received.recv_timeout(RECEIVE_TIMEOUT).unwrap();
+
+        let health = ComponentHealth {
+            healthy: true,
+            status: "running".to_string(),
+            ..Default::default()
+        };
+        started_client.set_health(health.clone()).unwrap();
+
+        let msg = received.recv_timeout(RECEIVE_TIMEOUT).unwrap();
+        assert_eq!(msg.health, Some(health));
+
+        started_client.stop().unwrap();
+    }
+
+    #[test]
+    fn heartbeats_are_sent_at_the_interval_requested_by_the_server() {
+        let (uri, received) = start_server(ServerToAgent {
+            connection_settings: Some(ConnectionSettingsOffers {
+                opamp: Some(OpAmpConnectionSettings {
+                    heartbeat_interval_seconds: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let mut callbacks = MockCallbacksMockall::new();
+        callbacks.should_on_connect();
+        callbacks.expect_on_message().return_const(());
+
+        let settings = StartSettings {
+            capabilities: capabilities!(AgentCapabilities::ReportsHeartbeat),
+            ..Default::default()
+        };
+        let started_client = NotStartedWsClient::new(uri, callbacks, settings)
+            .unwrap()
+            .start()
+            .unwrap();
+
+        // first message and two heartbeats without any status change
+        for sequence_num in 1..=3 {
+            let msg = received.recv_timeout(RECEIVE_TIMEOUT).unwrap();
+            assert_eq!(msg.sequence_num, sequence_num);
+        }
+
+        started_client.stop().unwrap();
+    }
+}
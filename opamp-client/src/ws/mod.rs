@@ -0,0 +1,16 @@
+//! # Synchronous WebSocket Client implementation.
+//!
+//! Unlike the HTTP transport, which polls the server periodically, the WebSocket transport keeps a
+//! connection open so the server can push messages (such as remote configurations) to the Agent as
+//! soon as they are available.
+//!
+//! Only the plain `ws://` scheme is supported, `wss://` uris are rejected when connecting.
+
+mod connection;
+mod managed_client;
+
+// export public structs
+pub use {
+    connection::WsClientError,
+    managed_client::{NotStartedWsClient, StartedWsClient},
+};
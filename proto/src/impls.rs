@@ -136,7 +136,7 @@ mod tests {
     }
     #[test]
     fn test_debug_agent_to_server() {
-        let expected_string = "AgentToServer { instance_uid: [0, 1, 2, 3], sequence_num: 0, agent_description: Some(AgentDescription { identifying_attributes: [KeyValue { key: \"key\", value: Some(\"value\") }], non_identifying_attributes: [] }), capabilities: 0, health: None, effective_config: Some(EffectiveConfig { config_map: Some(AgentConfigMap { config_map: {\"test-fleet-list-erich\": AgentConfigFile { body: \"hocus pocus\", content_type: \"text/yaml\" }} }) }), remote_config_status: Some(RemoteConfigStatus { status: 0, last_remote_config_hash: \"hash\", last_error: \"error\" }), package_statuses: None, agent_disconnect: None, flags: 0, connection_settings_request: None, custom_capabilities: None, custom_message: None }";
+        let expected_string = "AgentToServer { instance_uid: [0, 1, 2, 3], sequence_num: 0, agent_description: Some(AgentDescription { identifying_attributes: [KeyValue { key: \"key\", value: Some(\"value\") }], non_identifying_attributes: [] }), capabilities: 0, health: None, effective_config: Some(EffectiveConfig { config_map: Some(AgentConfigMap { config_map: {\"test-fleet-list-erich\": AgentConfigFile { body: \"hocus pocus\", content_type: \"text/yaml\" }} }) }), remote_config_status: Some(RemoteConfigStatus { status: 0, last_remote_config_hash: \"hash\", last_error: \"error\" }), package_statuses: None, agent_disconnect: None, flags: 0, connection_settings_request: None, custom_capabilities: None, custom_message: None, available_components: None }";
         let sample_message = AgentToServer {
             instance_uid: vec![0, 1, 2, 3],
             effective_config: Some(EffectiveConfig {
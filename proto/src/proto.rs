@@ -133,6 +133,10 @@ pub struct AgentToServer {
     /// Status: \[Development\]
     #[prost(message, optional, tag = "13")]
     pub custom_message: ::core::option::Option<CustomMessage>,
+    /// A message indicating the components that are available for configuration on the agent.
+    /// Status: \[Development\]
+    #[prost(message, optional, tag = "14")]
+    pub available_components: ::core::option::Option<AvailableComponents>,
 }
 /// AgentDisconnect is the last message sent from the Agent to the Server. The Server
 /// SHOULD forget the association of the Agent instance with the message stream.
@@ -256,6 +260,19 @@ pub struct OpAmpConnectionSettings {
     /// This field can be used to perform a client certificate revocation/rotation.
     #[prost(message, optional, tag = "3")]
     pub certificate: ::core::option::Option<TlsCertificate>,
+    /// The Agent MUST periodically send an AgentToServer message if the
+    /// AgentCapabilities_ReportsHeartbeat capability is true. At a minimum the instance_uid
+    /// field MUST be set.
+    ///
+    /// An HTTP Client MUST use the value as polling interval, if heartbeat_interval_seconds is non-zero.
+    ///
+    /// A heartbeat is used to keep the connection active and inform the server that the Agent
+    /// is still alive and active.
+    ///
+    /// If this field has no value or is set to 0, the Agent should not send any heartbeats.
+    /// Status: \[Development\]
+    #[prost(uint64, tag = "4")]
+    pub heartbeat_interval_seconds: u64,
 }
 /// The TelemetryConnectionSettings message is a collection of fields which comprise an
 /// offer from the Server to the Agent to use the specified settings for a network
@@ -600,6 +617,40 @@ pub struct ComponentHealth {
         ::std::collections::HashMap<::prost::alloc::string::String, ComponentHealth>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AvailableComponents {
+    /// A map of a unique component ID to details about the component.
+    /// This may be omitted from the message if the server has not
+    /// explicitly requested it be sent by setting the ReportAvailableComponents
+    /// flag in the previous ServerToAgent message.
+    #[prost(map = "string, message", tag = "1")]
+    pub components: ::std::collections::HashMap<::prost::alloc::string::String, ComponentDetails>,
+    /// Agent-calculated hash of the components.
+    /// This hash should be included in every AvailableComponents message.
+    #[prost(bytes = "vec", tag = "2")]
+    pub hash: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ComponentDetails {
+    /// Extra key/value pairs that may be used to describe the component.
+    /// The key/value pairs are according to semantic conventions, see:
+    /// <https://opentelemetry.io/docs/specs/semconv/>
+    ///
+    /// For example, you may use the "code" semantic conventions to
+    /// report the location of the code for a specific component:
+    /// <https://opentelemetry.io/docs/specs/semconv/attributes-registry/code/>
+    ///
+    /// Or you may use the "vcs" semantic conventions to report the
+    /// repository the component may be a part of:
+    /// <https://opentelemetry.io/docs/specs/semconv/attributes-registry/vcs/>
+    #[prost(message, repeated, tag = "1")]
+    pub metadata: ::prost::alloc::vec::Vec<KeyValue>,
+    /// A map of component ID to sub components details. It can nest as deeply as needed to
+    /// describe the underlying system.
+    #[prost(map = "string, message", tag = "2")]
+    pub sub_component_map:
+        ::std::collections::HashMap<::prost::alloc::string::String, ComponentDetails>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct EffectiveConfig {
     /// The effective config of the Agent.
     #[prost(message, optional, tag = "1")]
@@ -821,6 +872,12 @@ pub enum ServerToAgentFlags {
     /// AgentToServer.sequence_num values.
     /// The Server asks the Agent to report full status.
     ReportFullState = 1,
+    /// ReportAvailableComponents flag can be used by the server if the Agent did
+    /// not include the full AvailableComponents message, but only the hash.
+    /// If this flag is specified, the agent will populate available_components.components
+    /// with a full description of the agent's components.
+    /// Status: \[Development\]
+    ReportAvailableComponents = 2,
 }
 impl ServerToAgentFlags {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -831,6 +888,7 @@ impl ServerToAgentFlags {
         match self {
             Self::Unspecified => "ServerToAgentFlags_Unspecified",
             Self::ReportFullState => "ServerToAgentFlags_ReportFullState",
+            Self::ReportAvailableComponents => "ServerToAgentFlags_ReportAvailableComponents",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -838,6 +896,7 @@ impl ServerToAgentFlags {
         match value {
             "ServerToAgentFlags_Unspecified" => Some(Self::Unspecified),
             "ServerToAgentFlags_ReportFullState" => Some(Self::ReportFullState),
+            "ServerToAgentFlags_ReportAvailableComponents" => Some(Self::ReportAvailableComponents),
             _ => None,
         }
     }
@@ -1039,6 +1098,16 @@ pub enum AgentCapabilities {
     ReportsHealth = 2048,
     /// The Agent will report RemoteConfig status via AgentToServer.remote_config_status field.
     ReportsRemoteConfig = 4096,
+    /// The Agent can report heartbeats.
+    /// This is specified by the ServerToAgent.OpAMPConnectionSettings.heartbeat_interval_seconds field.
+    /// If this capability is true, but the Server does not set a heartbeat_interval_seconds field, the
+    /// Agent should use its own configured interval, which by default will be 30s. The Server may not
+    /// know the configured interval and should not make assumptions about it.
+    /// Status: \[Development\]
+    ReportsHeartbeat = 8192,
+    /// The agent will report AvailableComponents via the AgentToServer.available_components field.
+    /// Status: \[Development\]
+    ReportsAvailableComponents = 16384,
 }
 impl AgentCapabilities {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -1065,6 +1134,8 @@ impl AgentCapabilities {
             Self::AcceptsRestartCommand => "AgentCapabilities_AcceptsRestartCommand",
             Self::ReportsHealth => "AgentCapabilities_ReportsHealth",
             Self::ReportsRemoteConfig => "AgentCapabilities_ReportsRemoteConfig",
+            Self::ReportsHeartbeat => "AgentCapabilities_ReportsHeartbeat",
+            Self::ReportsAvailableComponents => "AgentCapabilities_ReportsAvailableComponents",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -1088,6 +1159,10 @@ impl AgentCapabilities {
             "AgentCapabilities_AcceptsRestartCommand" => Some(Self::AcceptsRestartCommand),
             "AgentCapabilities_ReportsHealth" => Some(Self::ReportsHealth),
             "AgentCapabilities_ReportsRemoteConfig" => Some(Self::ReportsRemoteConfig),
+            "AgentCapabilities_ReportsHeartbeat" => Some(Self::ReportsHeartbeat),
+            "AgentCapabilities_ReportsAvailableComponents" => {
+                Some(Self::ReportsAvailableComponents)
+            }
             _ => None,
         }
     }